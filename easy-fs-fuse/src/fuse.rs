@@ -0,0 +1,708 @@
+//! Serve an easy-fs image to the host kernel through `/dev/fuse`.
+//!
+//! The requests of the FUSE protocol are mapped onto the
+//! [`easy_fs::Inode`] API, node ids are inode ids plus one as the
+//! kernel names the root 1.
+
+use easy_fs::{
+  DiskInodeType, EasyFileSystem, Inode, Metadata, BLOCK_SZ,
+  NAME_LENGTH_LIMIT,
+};
+use spin::Mutex;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read, Result, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const FUSE_ROOT_ID: u64 = 1;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_READLINK: u32 = 5;
+const FUSE_SYMLINK: u32 = 6;
+const FUSE_MKDIR: u32 = 9;
+const FUSE_UNLINK: u32 = 10;
+const FUSE_RMDIR: u32 = 11;
+const FUSE_RENAME: u32 = 12;
+const FUSE_LINK: u32 = 13;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_FSYNCDIR: u32 = 30;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_CREATE: u32 = 35;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+const FUSE_RENAME2: u32 = 45;
+
+/// `FUSE_BIG_WRITES`, writes larger than a page for older kernels
+const FUSE_BIG_WRITES: u32 = 1 << 5;
+/// `FATTR_SIZE` of the fields a setattr request sets
+const FATTR_SIZE: u32 = 1 << 3;
+
+/// Size of `fuse_in_header`
+const IN_HEADER_SZ: usize = 40;
+/// Size of `fuse_out_header`
+const OUT_HEADER_SZ: usize = 16;
+/// Size of `fuse_dirent` without its name
+const DIRENT_HEADER_SZ: usize = 24;
+/// Largest write the kernel is allowed to send
+const MAX_WRITE: u32 = 128 * 1024;
+/// Seconds the kernel may cache entries and attributes for, nobody
+/// else changes the image while it is mounted
+const TTL_SECS: u64 = 1;
+
+/// Mount a FUSE filesystem at `mountpoint`, return the device its
+/// requests are read from
+pub fn mount(mountpoint: &str) -> Result<File> {
+  let device = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .open("/dev/fuse")?;
+  let options = CString::new(format!(
+    "fd={},rootmode=40000,user_id={},group_id={}",
+    device.as_raw_fd(),
+    unsafe { libc::getuid() },
+    unsafe { libc::getgid() },
+  ))
+  .unwrap();
+  let source = CString::new("easy-fs").unwrap();
+  let target = CString::new(mountpoint)?;
+  let fstype = CString::new("fuse.easy-fs").unwrap();
+  let ret = unsafe {
+    libc::mount(
+      source.as_ptr(),
+      target.as_ptr(),
+      fstype.as_ptr(),
+      libc::MS_NOSUID | libc::MS_NODEV,
+      options.as_ptr() as *const libc::c_void,
+    )
+  };
+  if ret != 0 {
+    return Err(Error::last_os_error());
+  }
+  Ok(device)
+}
+
+/// Serve the requests read from `device` until the filesystem is
+/// unmounted.
+pub fn serve(
+  efs: Arc<Mutex<EasyFileSystem>>,
+  device: File,
+) -> Result<()> {
+  let root_inode =
+    Arc::new(EasyFileSystem::root_inode(&efs));
+  let mut session = Session {
+    device,
+    efs,
+    nodes: BTreeMap::new(),
+  };
+  // the kernel never forgets the root
+  session.nodes.insert(FUSE_ROOT_ID, (root_inode, 1));
+  let result = session.run();
+  session.efs.lock().commit();
+  result
+}
+
+/// A mounted filesystem
+struct Session {
+  /// The opened `/dev/fuse`
+  device: File,
+  efs: Arc<Mutex<EasyFileSystem>>,
+  /// Inodes the kernel knows by node id, with the number of lookups it
+  /// has not forgotten
+  nodes: BTreeMap<u64, (Arc<Inode>, u64)>,
+}
+
+/// The fields of `fuse_in_header` requests are dispatched on
+struct InHeader {
+  opcode: u32,
+  unique: u64,
+  nodeid: u64,
+}
+
+/// Reader of the arguments of a request
+struct Args<'a>(&'a [u8]);
+
+impl<'a> Args<'a> {
+  fn bytes(
+    &mut self,
+    len: usize,
+  ) -> std::result::Result<&'a [u8], i32> {
+    if self.0.len() < len {
+      return Err(libc::EINVAL);
+    }
+    let (bytes, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(bytes)
+  }
+
+  fn u32(&mut self) -> std::result::Result<u32, i32> {
+    Ok(u32::from_le_bytes(
+      self.bytes(4)?.try_into().unwrap(),
+    ))
+  }
+
+  fn u64(&mut self) -> std::result::Result<u64, i32> {
+    Ok(u64::from_le_bytes(
+      self.bytes(8)?.try_into().unwrap(),
+    ))
+  }
+
+  /// Read a name terminated by NUL
+  fn name(&mut self) -> std::result::Result<&'a str, i32> {
+    let len = self
+      .0
+      .iter()
+      .position(|&b| b == 0)
+      .ok_or(libc::EINVAL)?;
+    let name = self.bytes(len + 1)?;
+    std::str::from_utf8(&name[..len])
+      .map_err(|_| libc::EINVAL)
+  }
+}
+
+/// Builder of the payload of a reply
+#[derive(Default)]
+struct Reply(Vec<u8>);
+
+impl Reply {
+  fn u16(mut self, value: u16) -> Self {
+    self.0.extend_from_slice(&value.to_le_bytes());
+    self
+  }
+
+  fn u32(mut self, value: u32) -> Self {
+    self.0.extend_from_slice(&value.to_le_bytes());
+    self
+  }
+
+  fn u64(mut self, value: u64) -> Self {
+    self.0.extend_from_slice(&value.to_le_bytes());
+    self
+  }
+
+  /// Append a `fuse_attr` of the inode `metadata` is of
+  fn attr(self, metadata: &Metadata) -> Self {
+    let type_ = match metadata.type_ {
+      DiskInodeType::File => libc::S_IFREG,
+      DiskInodeType::Directory => libc::S_IFDIR,
+      DiskInodeType::SymLink => libc::S_IFLNK,
+    };
+    let size = metadata.size as u64;
+    self
+      .u64(node_id(metadata.inode_id))
+      .u64(size)
+      .u64((size + BLOCK_SZ as u64 - 1) / BLOCK_SZ as u64)
+      .u64(metadata.atime / 1000)
+      .u64(metadata.mtime / 1000)
+      .u64(metadata.ctime / 1000)
+      .u32((metadata.atime % 1000) as u32 * 1_000_000)
+      .u32((metadata.mtime % 1000) as u32 * 1_000_000)
+      .u32((metadata.ctime % 1000) as u32 * 1_000_000)
+      .u32(type_ | metadata.mode & 0o7777)
+      .u32(metadata.nlink)
+      .u32(metadata.uid)
+      .u32(metadata.gid)
+      .u32(0)
+      .u32(BLOCK_SZ as u32)
+      .u32(0)
+  }
+
+  /// Append a `fuse_entry_out` of `inode`
+  fn entry(self, inode: &Inode) -> Self {
+    self
+      .u64(node_id(inode.inode_id()))
+      .u64(0)
+      .u64(TTL_SECS)
+      .u64(TTL_SECS)
+      .u32(0)
+      .u32(0)
+      .attr(&inode.metadata())
+  }
+
+  /// Append a `fuse_open_out`, inodes are their own file handles
+  fn open(self) -> Self {
+    self.u64(0).u32(0).u32(0)
+  }
+}
+
+/// The node id the kernel knows the inode `inode_id` by
+fn node_id(inode_id: u32) -> u64 {
+  inode_id as u64 + 1
+}
+
+/// The `d_type` of the entries of `type_` in a directory
+fn dirent_type(type_: DiskInodeType) -> u32 {
+  (match type_ {
+    DiskInodeType::File => libc::DT_REG,
+    DiskInodeType::Directory => libc::DT_DIR,
+    DiskInodeType::SymLink => libc::DT_LNK,
+  }) as u32
+}
+
+/// Why `name` could not be made under `dir`
+fn create_errno(dir: &Inode, name: &str) -> i32 {
+  if name.len() > NAME_LENGTH_LIMIT {
+    libc::ENAMETOOLONG
+  } else if dir.find(name).is_some() {
+    libc::EEXIST
+  } else {
+    libc::EINVAL
+  }
+}
+
+/// Why `name` could not be removed from `dir`
+fn remove_errno(
+  dir: &Inode,
+  name: &str,
+  is_dir: bool,
+) -> i32 {
+  match dir.find(name) {
+    None => libc::ENOENT,
+    Some(inode) if inode.is_dir() != is_dir => {
+      if is_dir {
+        libc::ENOTDIR
+      } else {
+        libc::EISDIR
+      }
+    }
+    Some(_) if is_dir => libc::ENOTEMPTY,
+    Some(_) => libc::EINVAL,
+  }
+}
+
+/// Why `old_name` of `dir` could not be moved to `new_name` of
+/// `new_dir`
+fn rename_errno(
+  dir: &Inode,
+  old_name: &str,
+  new_dir: &Inode,
+  new_name: &str,
+) -> i32 {
+  let old = match dir.find(old_name) {
+    Some(old) => old,
+    None => return libc::ENOENT,
+  };
+  match new_dir.find(new_name) {
+    Some(new) if old.is_dir() && !new.is_dir() => {
+      libc::ENOTDIR
+    }
+    Some(new) if !old.is_dir() && new.is_dir() => {
+      libc::EISDIR
+    }
+    Some(new) if new.is_dir() && new.ls().len() > 2 => {
+      libc::ENOTEMPTY
+    }
+    _ if new_name.len() > NAME_LENGTH_LIMIT => {
+      libc::ENAMETOOLONG
+    }
+    _ => libc::EINVAL,
+  }
+}
+
+impl Session {
+  /// Read and answer requests until the filesystem is unmounted
+  fn run(&mut self) -> Result<()> {
+    let mut buf = vec![0u8; MAX_WRITE as usize + 4096];
+    loop {
+      let len = match self.device.read(&mut buf) {
+        Ok(len) => len,
+        Err(err) => match err.raw_os_error() {
+          // the request was interrupted before it was read
+          Some(
+            libc::ENOENT | libc::EINTR | libc::EAGAIN,
+          ) => continue,
+          // unmounted
+          Some(libc::ENODEV) => return Ok(()),
+          _ => return Err(err),
+        },
+      };
+      let mut args = Args(&buf[..len]);
+      let header = (|| {
+        let _len = args.u32()?;
+        let opcode = args.u32()?;
+        let unique = args.u64()?;
+        let nodeid = args.u64()?;
+        args.bytes(IN_HEADER_SZ - 24)?;
+        Ok::<_, i32>(InHeader {
+          opcode,
+          unique,
+          nodeid,
+        })
+      })();
+      let header = match header {
+        Ok(header) => header,
+        Err(_) => continue,
+      };
+      let reply = self.dispatch(&header, args);
+      match reply {
+        Ok(None) => {}
+        Ok(Some(reply)) => {
+          self.reply(header.unique, 0, &reply.0)?
+        }
+        Err(errno) => {
+          self.reply(header.unique, -errno, &[])?
+        }
+      }
+      if header.opcode == FUSE_DESTROY {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Send the reply to the request `unique`
+  fn reply(
+    &mut self,
+    unique: u64,
+    error: i32,
+    data: &[u8],
+  ) -> Result<()> {
+    let mut out = Reply::default()
+      .u32((OUT_HEADER_SZ + data.len()) as u32)
+      .u32(error as u32)
+      .u64(unique);
+    out.0.extend_from_slice(data);
+    match self.device.write(&out.0) {
+      // the request was interrupted while it was answered
+      Err(err)
+        if err.raw_os_error() == Some(libc::ENOENT) =>
+      {
+        Ok(())
+      }
+      Err(err) => Err(err),
+      Ok(_) => Ok(()),
+    }
+  }
+
+  /// Get the inode of the node `nodeid`
+  fn node(
+    &self,
+    nodeid: u64,
+  ) -> std::result::Result<Arc<Inode>, i32> {
+    self
+      .nodes
+      .get(&nodeid)
+      .map(|(inode, _)| Arc::clone(inode))
+      .ok_or(libc::ESTALE)
+  }
+
+  /// Count a lookup of `inode` the kernel remembers, and reply with its
+  /// entry
+  fn lookup(&mut self, inode: Arc<Inode>) -> Reply {
+    let reply = Reply::default().entry(&inode);
+    self
+      .nodes
+      .entry(node_id(inode.inode_id()))
+      .or_insert((inode, 0))
+      .1 += 1;
+    reply
+  }
+
+  /// Drop `nlookup` lookups of the node `nodeid`
+  fn forget(&mut self, nodeid: u64, nlookup: u64) {
+    if nodeid == FUSE_ROOT_ID {
+      return;
+    }
+    if let Some((_, count)) = self.nodes.get_mut(&nodeid) {
+      *count = count.saturating_sub(nlookup);
+      if *count == 0 {
+        self.nodes.remove(&nodeid);
+      }
+    }
+  }
+
+  /// Answer a request, `None` if it takes no reply
+  fn dispatch(
+    &mut self,
+    header: &InHeader,
+    mut args: Args,
+  ) -> std::result::Result<Option<Reply>, i32> {
+    let reply = match header.opcode {
+      FUSE_INIT => {
+        let major = args.u32()?;
+        let minor = args.u32()?;
+        let max_readahead = args.u32()?;
+        if major != FUSE_KERNEL_VERSION {
+          return Err(libc::EPROTO);
+        }
+        Reply::default()
+          .u32(FUSE_KERNEL_VERSION)
+          .u32(minor.min(FUSE_KERNEL_MINOR_VERSION))
+          .u32(max_readahead)
+          .u32(FUSE_BIG_WRITES)
+          .u16(16)
+          .u16(12)
+          .u32(MAX_WRITE)
+          // timestamps are kept in milliseconds
+          .u32(1_000_000)
+          .u16(0)
+          .u16(0)
+          .u32(0)
+          .u64(0)
+          .u64(0)
+          .u64(0)
+          .u32(0)
+      }
+      FUSE_DESTROY => Reply::default(),
+      FUSE_FORGET => {
+        let nlookup = args.u64()?;
+        self.forget(header.nodeid, nlookup);
+        return Ok(None);
+      }
+      FUSE_BATCH_FORGET => {
+        let count = args.u32()?;
+        args.u32()?;
+        for _ in 0..count {
+          let nodeid = args.u64()?;
+          let nlookup = args.u64()?;
+          self.forget(nodeid, nlookup);
+        }
+        return Ok(None);
+      }
+      FUSE_INTERRUPT => return Ok(None),
+      FUSE_LOOKUP => {
+        let name = args.name()?;
+        let inode = self
+          .node(header.nodeid)?
+          .find(name)
+          .ok_or(libc::ENOENT)?;
+        self.lookup(inode)
+      }
+      FUSE_GETATTR => {
+        let inode = self.node(header.nodeid)?;
+        Reply::default()
+          .u64(TTL_SECS)
+          .u32(0)
+          .u32(0)
+          .attr(&inode.metadata())
+      }
+      FUSE_SETATTR => {
+        let valid = args.u32()?;
+        args.u32()?;
+        let _fh = args.u64()?;
+        let size = args.u64()?;
+        let inode = self.node(header.nodeid)?;
+        // easy-fs keeps no other attribute that can be set
+        if valid & FATTR_SIZE != 0 {
+          if inode.is_dir() {
+            return Err(libc::EISDIR);
+          }
+          let size = usize::try_from(size)
+            .map_err(|_| libc::EFBIG)?;
+          if !inode.truncate(size) {
+            return Err(libc::EFBIG);
+          }
+        }
+        Reply::default()
+          .u64(TTL_SECS)
+          .u32(0)
+          .u32(0)
+          .attr(&inode.metadata())
+      }
+      FUSE_READLINK => {
+        let target = self
+          .node(header.nodeid)?
+          .read_link()
+          .ok_or(libc::EINVAL)?;
+        Reply(target.into_bytes())
+      }
+      FUSE_SYMLINK => {
+        let name = args.name()?;
+        let target = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let inode = dir
+          .symlink(name, target)
+          .ok_or_else(|| create_errno(&dir, name))?;
+        self.lookup(inode)
+      }
+      FUSE_MKDIR => {
+        let _mode = args.u32()?;
+        let _umask = args.u32()?;
+        let name = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let inode = dir
+          .mkdir(name)
+          .ok_or_else(|| create_errno(&dir, name))?;
+        self.lookup(inode)
+      }
+      FUSE_CREATE => {
+        let _flags = args.u32()?;
+        let _mode = args.u32()?;
+        let _umask = args.u32()?;
+        let _open_flags = args.u32()?;
+        let name = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let inode = dir
+          .create(name)
+          .ok_or_else(|| create_errno(&dir, name))?;
+        self.lookup(inode).open()
+      }
+      FUSE_LINK => {
+        let old_nodeid = args.u64()?;
+        let name = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let inode = self.node(old_nodeid)?;
+        if inode.is_dir() {
+          return Err(libc::EPERM);
+        }
+        if !dir.link(name, &inode) {
+          return Err(create_errno(&dir, name));
+        }
+        self.lookup(inode)
+      }
+      FUSE_UNLINK | FUSE_RMDIR => {
+        let name = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let is_dir = header.opcode == FUSE_RMDIR;
+        let removed = if is_dir {
+          dir.rmdir(name)
+        } else {
+          dir.unlink(name)
+        };
+        if !removed {
+          return Err(remove_errno(&dir, name, is_dir));
+        }
+        Reply::default()
+      }
+      FUSE_RENAME | FUSE_RENAME2 => {
+        let new_nodeid = args.u64()?;
+        if header.opcode == FUSE_RENAME2 {
+          // no flag like `RENAME_NOREPLACE` is supported
+          if args.u32()? != 0 {
+            return Err(libc::EINVAL);
+          }
+          args.u32()?;
+        }
+        let old_name = args.name()?;
+        let new_name = args.name()?;
+        let dir = self.node(header.nodeid)?;
+        let new_dir = self.node(new_nodeid)?;
+        if !dir.rename(old_name, &new_dir, new_name) {
+          return Err(rename_errno(
+            &dir, old_name, &new_dir, new_name,
+          ));
+        }
+        Reply::default()
+      }
+      FUSE_OPEN | FUSE_OPENDIR => {
+        self.node(header.nodeid)?;
+        Reply::default().open()
+      }
+      FUSE_READ => {
+        let _fh = args.u64()?;
+        let offset = args.u64()?;
+        let size = args.u32()?;
+        let inode = self.node(header.nodeid)?;
+        let mut data = vec![0u8; size as usize];
+        let len = usize::try_from(offset)
+          .map_or(0, |offset| {
+            inode.read_at(offset, &mut data)
+          });
+        data.truncate(len);
+        Reply(data)
+      }
+      FUSE_WRITE => {
+        let _fh = args.u64()?;
+        let offset = args.u64()?;
+        let size = args.u32()?;
+        let _write_flags = args.u32()?;
+        let _lock_owner = args.u64()?;
+        let _flags = args.u32()?;
+        args.u32()?;
+        let data = args.bytes(size as usize)?;
+        let inode = self.node(header.nodeid)?;
+        let offset = usize::try_from(offset)
+          .map_err(|_| libc::EFBIG)?;
+        match offset.checked_add(data.len()) {
+          Some(end) if end <= inode.max_size() => {}
+          _ => return Err(libc::EFBIG),
+        }
+        // a write is only cut short when space runs out
+        let len = inode.write_at(offset, data);
+        if len == 0 && !data.is_empty() {
+          return Err(libc::ENOSPC);
+        }
+        Reply::default().u32(len as u32).u32(0)
+      }
+      FUSE_READDIR => {
+        let _fh = args.u64()?;
+        let mut offset = args.u64()? as usize;
+        let size = args.u32()? as usize;
+        let dir = self.node(header.nodeid)?;
+        let mut reply = Reply::default();
+        while let Some((entry, next)) =
+          dir.read_dirent(offset)
+        {
+          let name = entry.name.as_bytes();
+          let entry_len =
+            (DIRENT_HEADER_SZ + name.len() + 7) & !7;
+          if reply.0.len() + entry_len > size {
+            break;
+          }
+          reply = reply
+            .u64(node_id(entry.inode_id))
+            .u64(next as u64)
+            .u32(name.len() as u32)
+            .u32(dirent_type(entry.type_));
+          reply.0.extend_from_slice(name);
+          reply.0.resize(
+            reply.0.len() + entry_len
+              - DIRENT_HEADER_SZ
+              - name.len(),
+            0,
+          );
+          offset = next;
+        }
+        reply
+      }
+      FUSE_STATFS => {
+        let efs = self.efs.lock();
+        let super_block = efs.super_block();
+        let blocks = super_block.data_area_blocks as u64;
+        let files = super_block.inode_bitmap_blocks as u64
+          * BLOCK_SZ as u64
+          * 8;
+        let free_blocks =
+          blocks - efs.used_data_blocks() as u64;
+        let free_files = files - efs.used_inodes() as u64;
+        Reply::default()
+          .u64(blocks)
+          .u64(free_blocks)
+          .u64(free_blocks)
+          .u64(files)
+          .u64(free_files)
+          .u32(BLOCK_SZ as u32)
+          .u32(NAME_LENGTH_LIMIT as u32)
+          .u32(BLOCK_SZ as u32)
+          .u32(0)
+          .u64(0)
+          .u64(0)
+          .u64(0)
+      }
+      FUSE_FSYNC | FUSE_FSYNCDIR => {
+        self.efs.lock().commit();
+        Reply::default()
+      }
+      FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH
+      | FUSE_ACCESS => Reply::default(),
+      _ => return Err(libc::ENOSYS),
+    };
+    Ok(Some(reply))
+  }
+}
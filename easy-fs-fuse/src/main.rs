@@ -1,8 +1,16 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
-use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use clap::{value_t, App, Arg, ArgMatches, SubCommand};
+use easy_fs::{
+  BlockDevice, DiskInodeType, EasyFileSystem, Inode,
+  InodeFormat,
+};
+use std::fs::{read_dir, read_link, File, OpenOptions};
+use std::io::{
+  Error, ErrorKind, Read, Seek, SeekFrom, Write,
+};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod fuse;
 
 const BLOCK_SZ: usize = 512;
 
@@ -34,22 +42,25 @@ impl BlockDevice for BlockFile {
   }
 }
 
-fn main() {
-  easyfs_pack().expect("Error when packing easy-fs!");
+/// The in-image path argument of a subcommand
+fn path_arg(help: &'static str) -> Arg<'static, 'static> {
+  Arg::with_name("path").required(true).help(help)
 }
 
-// #[derive(Parser)]
-// #[clap(author, version, about, long_about = None)]
-// struct App {
-//   /// Executable source dir(with backslash)
-//   #[clap(short, long)]
-//   source: String,
-//   /// Executable source dir(with backslash)
-//   #[clap(short, long)]
-//   target: String,
-// }
-fn easyfs_pack() -> std::io::Result<()> {
-  let matches = App::new("EasyFileSystem packer")
+/// A subcommand operating on an existing image
+fn image_command(
+  name: &'static str,
+  about: &'static str,
+) -> App<'static, 'static> {
+  SubCommand::with_name(name).about(about).arg(
+    Arg::with_name("image")
+      .required(true)
+      .help("Path of the image"),
+  )
+}
+
+fn app() -> App<'static, 'static> {
+  App::new("EasyFileSystem packer")
     .arg(
       Arg::with_name("source")
         .short("s")
@@ -64,48 +75,233 @@ fn easyfs_pack() -> std::io::Result<()> {
         .takes_value(true)
         .help("Executable target dir(with backslash)"),
     )
-    .get_matches();
+    .arg(
+      Arg::with_name("format")
+        .short("f")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["indirect", "extent"])
+        .default_value("indirect")
+        .help("How inodes index their data blocks"),
+    )
+    .arg(
+      Arg::with_name("keep-ext")
+        .short("k")
+        .long("keep-ext")
+        .help("Keep the extensions of source names instead of dropping the last one"),
+    )
+    .arg(
+      Arg::with_name("blocks")
+        .short("b")
+        .long("blocks")
+        .takes_value(true)
+        .default_value("32768")
+        .help("Size of the image in blocks"),
+    )
+    .arg(
+      Arg::with_name("inodes")
+        .short("i")
+        .long("inodes")
+        .takes_value(true)
+        .default_value("4096")
+        .help("Number of inodes, rounded up to a multiple of 4096"),
+    )
+    .subcommand(
+      image_command("fsck", "Check an easy-fs image").arg(
+        Arg::with_name("repair")
+          .short("r")
+          .long("repair")
+          .help("Repair the problems found"),
+      ),
+    )
+    .subcommand(
+      image_command("ls", "List a directory of an image").arg(
+        Arg::with_name("path")
+          .default_value("/")
+          .help("Directory or file to list"),
+      ),
+    )
+    .subcommand(
+      image_command("cat", "Print a file of an image")
+        .arg(path_arg("File to print")),
+    )
+    .subcommand(
+      image_command("extract", "Copy a file out of an image")
+        .arg(path_arg("File to copy"))
+        .arg(
+          Arg::with_name("host")
+            .required(true)
+            .help("Host path to copy it to"),
+        ),
+    )
+    .subcommand(
+      image_command(
+        "put",
+        "Copy a host file into an image, replacing the file there",
+      )
+      .arg(
+        Arg::with_name("host")
+          .required(true)
+          .help("Host file to copy"),
+      )
+      .arg(path_arg("Path to copy it to")),
+    )
+    .subcommand(
+      image_command(
+        "rm",
+        "Remove a file or an empty directory of an image",
+      )
+      .arg(path_arg("File or directory to remove")),
+    )
+    .subcommand(
+      image_command("mkdir", "Make a directory in an image")
+        .arg(path_arg("Directory to make")),
+    )
+    .subcommand(image_command(
+      "info",
+      "Print the layout and usage of an image",
+    ))
+    .subcommand(
+      image_command(
+        "mount",
+        "Mount an image through FUSE until it is unmounted, which needs root",
+      )
+      .arg(
+        Arg::with_name("mountpoint")
+          .required(true)
+          .help("Directory to mount it on"),
+      ),
+    )
+}
+
+fn main() {
+  let matches = app().get_matches();
+  match matches.subcommand() {
+    ("fsck", Some(matches)) => {
+      let code = easyfs_fsck(
+        matches.value_of("image").unwrap(),
+        matches.is_present("repair"),
+      )
+      .expect("Error when checking easy-fs!");
+      std::process::exit(code);
+    }
+    (name, Some(matches)) => {
+      if let Err(err) = easyfs_command(name, matches) {
+        eprintln!("{}: {}", name, err);
+        std::process::exit(1);
+      }
+    }
+    _ => easyfs_pack(&matches)
+      .expect("Error when packing easy-fs!"),
+  }
+}
+
+// #[derive(Parser)]
+// #[clap(author, version, about, long_about = None)]
+// struct App {
+//   /// Executable source dir(with backslash)
+//   #[clap(short, long)]
+//   source: String,
+//   /// Executable source dir(with backslash)
+//   #[clap(short, long)]
+//   target: String,
+// }
+fn easyfs_pack(
+  matches: &ArgMatches,
+) -> std::io::Result<()> {
   let src_path = matches.value_of("source").unwrap();
   let target_path = matches.value_of("target").unwrap();
   println!(
     "src_path = {}\ntarget_path = {}\n",
     src_path, target_path
   );
+  let total_blocks = value_t!(matches, "blocks", u32)
+    .unwrap_or_else(|err| err.exit());
+  let inodes = value_t!(matches, "inodes", u32)
+    .unwrap_or_else(|err| err.exit());
+  let inode_bitmap_blocks =
+    ((inodes as usize + BLOCK_SZ * 8 - 1) / (BLOCK_SZ * 8))
+      .max(1) as u32;
+  if total_blocks
+    < EasyFileSystem::min_blocks(inode_bitmap_blocks)
+  {
+    return Err(Error::new(
+      ErrorKind::InvalidInput,
+      format!(
+        "{} inodes need at least {} blocks",
+        inodes,
+        EasyFileSystem::min_blocks(inode_bitmap_blocks)
+      ),
+    ));
+  }
   let block_file = Arc::new(BlockFile(Mutex::new({
     let f = OpenOptions::new()
       .read(true)
       .write(true)
       .create(true)
       .open(format!("{}{}", target_path, "fs.img"))?;
-    f.set_len(16 * 2048 * 512).unwrap();
+    f.set_len(total_blocks as u64 * BLOCK_SZ as u64)
+      .unwrap();
     f
   })));
 
-  // 4MiB, at most 4095 files
-  let efs =
-    EasyFileSystem::create(block_file, 16 * 2048, 1);
+  let format = match matches.value_of("format") {
+    Some("extent") => InodeFormat::Extent,
+    _ => InodeFormat::Indirect,
+  };
+  // 16MiB, at most 4095 files by default
+  let efs = EasyFileSystem::create_with_format(
+    block_file,
+    total_blocks,
+    inode_bitmap_blocks,
+    format,
+  );
+  efs.lock().set_clock(host_time_ms);
 
   let root_inode =
     Arc::new(EasyFileSystem::root_inode(&efs));
+  let name_of = |name: String| {
+    if matches.is_present("keep-ext") {
+      name
+    } else {
+      strip_ext(name)
+    }
+  };
+  // the name of each app without extension, with the stripped target
+  // of a host symlink
   let apps = read_dir(src_path)
     .unwrap()
     .map(|dir_entry| {
-      let mut name_with_ext = dir_entry
-        .unwrap()
-        .file_name()
-        .into_string()
-        .unwrap();
-      name_with_ext.drain(
-        name_with_ext.find('.').unwrap()
-          ..name_with_ext.len(),
+      let dir_entry = dir_entry.unwrap();
+      let name = name_of(
+        dir_entry.file_name().into_string().unwrap(),
       );
-      name_with_ext
+      let target =
+        if dir_entry.file_type().unwrap().is_symlink() {
+          let target = read_link(dir_entry.path()).unwrap();
+          Some(name_of(
+            target.into_os_string().into_string().unwrap(),
+          ))
+        } else {
+          None
+        };
+      (name, target)
     })
     .collect::<Vec<_>>();
 
   println!("-- There are {} applications --", apps.len());
 
-  for app in apps {
+  for (app, target) in apps {
+    if let Some(target) = target {
+      println!(
+        "-- linking {} to {} in efs --",
+        app, target
+      );
+      root_inode
+        .symlink(app.as_str(), target.as_str())
+        .unwrap();
+      continue;
+    }
     // load app data from host file system
     println!("-- loading {} application to efs --", app);
     let mut host_file =
@@ -125,8 +321,383 @@ fn easyfs_pack() -> std::io::Result<()> {
   Ok(())
 }
 
+/// Max number of repair passes, a repair may uncover more problems as
+/// copies of a shared indirect block still share the blocks below it.
+const FSCK_PASSES: usize = 8;
+
+/// Check the image at `path` and print the problems found, return the
+/// exit code as `e2fsck` does: 0 if it is clean, 1 if the problems are
+/// repaired, 4 if some are left and 8 if it is no easy-fs image.
+fn easyfs_fsck(
+  path: &str,
+  repair: bool,
+) -> std::io::Result<i32> {
+  let file =
+    OpenOptions::new().read(true).write(true).open(path)?;
+  if file.metadata()?.len() < BLOCK_SZ as u64 {
+    println!("{}: not an easy-fs image", path);
+    return Ok(8);
+  }
+  let block_file: Arc<dyn BlockDevice> =
+    Arc::new(BlockFile(Mutex::new(file)));
+  if !EasyFileSystem::is_valid_image(&block_file) {
+    println!("{}: not an easy-fs image", path);
+    return Ok(8);
+  }
+  let efs = EasyFileSystem::open(block_file);
+  let mut efs = efs.lock();
+  let mut problems = efs.check(repair);
+  for problem in problems.iter() {
+    println!("{}", problem);
+  }
+  if problems.is_empty() {
+    println!("{}: clean", path);
+    return Ok(0);
+  }
+  if !repair {
+    return Ok(4);
+  }
+  for _ in 1..FSCK_PASSES {
+    problems = efs.check(true);
+    if problems.is_empty() {
+      println!("{}: repaired", path);
+      return Ok(1);
+    }
+  }
+  for problem in efs.check(false) {
+    println!("left: {}", problem);
+  }
+  Ok(4)
+}
+
+/// Open the easy-fs image at `path`.
+fn open_image(
+  path: &str,
+) -> std::io::Result<Arc<dyn BlockDevice>> {
+  let not_image = || {
+    Error::new(
+      ErrorKind::InvalidData,
+      format!("{}: not an easy-fs image", path),
+    )
+  };
+  let file =
+    OpenOptions::new().read(true).write(true).open(path)?;
+  if file.metadata()?.len() < BLOCK_SZ as u64 {
+    return Err(not_image());
+  }
+  let block_file: Arc<dyn BlockDevice> =
+    Arc::new(BlockFile(Mutex::new(file)));
+  if !EasyFileSystem::is_valid_image(&block_file) {
+    return Err(not_image());
+  }
+  Ok(block_file)
+}
+
+/// Split `path` into its parent directory and its last component.
+fn split_path(path: &str) -> (&str, &str) {
+  let path = path.trim_end_matches('/');
+  match path.rfind('/') {
+    Some(idx) => (&path[..idx], &path[idx + 1..]),
+    None => ("", path),
+  }
+}
+
+/// An error about the in-image `path`
+fn path_error(
+  kind: ErrorKind,
+  path: &str,
+  msg: &str,
+) -> Error {
+  Error::new(kind, format!("{}: {}", path, msg))
+}
+
+/// Find the inode of `path` under `root_inode`.
+fn find_inode(
+  root_inode: &Arc<Inode>,
+  path: &str,
+) -> std::io::Result<Arc<Inode>> {
+  root_inode.find_path(path).ok_or_else(|| {
+    path_error(
+      ErrorKind::NotFound,
+      path,
+      "no such file or directory",
+    )
+  })
+}
+
+/// Find the parent directory of `path` under `root_inode`, return it
+/// with the last component of `path`.
+fn find_parent<'a>(
+  root_inode: &Arc<Inode>,
+  path: &'a str,
+) -> std::io::Result<(Arc<Inode>, &'a str)> {
+  let (parent, name) = split_path(path);
+  if name.is_empty() || name == "." || name == ".." {
+    return Err(path_error(
+      ErrorKind::InvalidInput,
+      path,
+      "not a name in a directory",
+    ));
+  }
+  let parent_inode = find_inode(root_inode, parent)?;
+  if !parent_inode.is_dir() {
+    return Err(path_error(
+      ErrorKind::InvalidInput,
+      parent,
+      "not a directory",
+    ));
+  }
+  Ok((parent_inode, name))
+}
+
+/// Read the whole regular file or symlink at `path`.
+fn read_file(
+  root_inode: &Arc<Inode>,
+  path: &str,
+) -> std::io::Result<Vec<u8>> {
+  let inode = find_inode(root_inode, path)?;
+  if inode.is_dir() {
+    return Err(path_error(
+      ErrorKind::InvalidInput,
+      path,
+      "is a directory",
+    ));
+  }
+  let mut data = vec![0u8; inode.metadata().size as usize];
+  inode.read_at(0, &mut data);
+  Ok(data)
+}
+
+/// Print the entry `name` of `inode` as `ls -l` does.
+fn print_entry(inode: &Inode, name: &str) {
+  let metadata = inode.metadata();
+  let type_ = match metadata.type_ {
+    DiskInodeType::File => '-',
+    DiskInodeType::Directory => 'd',
+    DiskInodeType::SymLink => 'l',
+  };
+  match inode.read_link() {
+    Some(target) => println!(
+      "{} {:>5} {:>3} {:>9} {} -> {}",
+      type_,
+      metadata.inode_id,
+      metadata.nlink,
+      metadata.size,
+      name,
+      target
+    ),
+    None => println!(
+      "{} {:>5} {:>3} {:>9} {}",
+      type_,
+      metadata.inode_id,
+      metadata.nlink,
+      metadata.size,
+      name
+    ),
+  }
+}
+
+/// Run the subcommand `name` on the image it is given.
+fn easyfs_command(
+  name: &str,
+  matches: &ArgMatches,
+) -> std::io::Result<()> {
+  let efs = EasyFileSystem::open(open_image(
+    matches.value_of("image").unwrap(),
+  )?);
+  efs.lock().set_clock(host_time_ms);
+  // commands which only read leave the image as it is
+  if matches!(name, "ls" | "cat" | "extract" | "info") {
+    efs.lock().set_noatime(true);
+  }
+  let root_inode =
+    Arc::new(EasyFileSystem::root_inode(&efs));
+  let path = matches.value_of("path").unwrap_or("/");
+  match name {
+    "mount" => {
+      drop(root_inode);
+      let device = fuse::mount(
+        matches.value_of("mountpoint").unwrap(),
+      )?;
+      return fuse::serve(efs, device);
+    }
+    "ls" => {
+      let inode = find_inode(&root_inode, path)?;
+      if !inode.is_dir() {
+        print_entry(&inode, path);
+        return Ok(());
+      }
+      let mut offset = 0;
+      while let Some((entry, next)) =
+        inode.read_dirent(offset)
+      {
+        print_entry(
+          &inode.find(&entry.name).unwrap(),
+          &entry.name,
+        );
+        offset = next;
+      }
+    }
+    "cat" => std::io::stdout()
+      .write_all(&read_file(&root_inode, path)?)?,
+    "extract" => std::fs::write(
+      matches.value_of("host").unwrap(),
+      read_file(&root_inode, path)?,
+    )?,
+    "put" => {
+      let data =
+        std::fs::read(matches.value_of("host").unwrap())?;
+      let (parent, name) = find_parent(&root_inode, path)?;
+      // all the inodes share the format of their block index
+      if data.len() > parent.max_size() {
+        return Err(path_error(
+          ErrorKind::InvalidInput,
+          path,
+          "file too large",
+        ));
+      }
+      let inode = match parent.find(name) {
+        Some(inode) if inode.is_dir() => {
+          return Err(path_error(
+            ErrorKind::InvalidInput,
+            path,
+            "is a directory",
+          ))
+        }
+        Some(inode) => {
+          inode.clear();
+          inode
+        }
+        None => parent.create(name).ok_or_else(|| {
+          path_error(
+            ErrorKind::InvalidInput,
+            path,
+            "invalid name or no space left on the image",
+          )
+        })?,
+      };
+      if inode.write_at(0, &data) < data.len() {
+        return Err(path_error(
+          ErrorKind::Other,
+          path,
+          "no space left on the image",
+        ));
+      }
+    }
+    "rm" => {
+      let (parent, name) = find_parent(&root_inode, path)?;
+      let inode = find_inode(&root_inode, path)?;
+      let removed = if inode.is_dir() {
+        parent.rmdir(name)
+      } else {
+        parent.unlink(name)
+      };
+      if !removed {
+        return Err(path_error(
+          ErrorKind::Other,
+          path,
+          "directory not empty",
+        ));
+      }
+    }
+    "mkdir" => {
+      let (parent, name) = find_parent(&root_inode, path)?;
+      if parent.find(name).is_some() {
+        return Err(path_error(
+          ErrorKind::AlreadyExists,
+          path,
+          "file exists",
+        ));
+      }
+      parent.mkdir(name).ok_or_else(|| {
+        path_error(
+          ErrorKind::InvalidInput,
+          path,
+          "invalid name",
+        )
+      })?;
+    }
+    "info" => {
+      let efs = efs.lock();
+      let super_block = efs.super_block();
+      let total_inodes = super_block.inode_bitmap_blocks
+        as usize
+        * BLOCK_SZ
+        * 8;
+      let format = match efs.inode_format() {
+        InodeFormat::Indirect => "indirect",
+        InodeFormat::Extent => "extent",
+      };
+      println!("block size:   {}", BLOCK_SZ);
+      println!(
+        "total blocks: {}",
+        super_block.total_blocks
+      );
+      println!(
+        "journal:      {} blocks",
+        super_block.journal_blocks
+      );
+      println!(
+        "inode bitmap: {} blocks",
+        super_block.inode_bitmap_blocks
+      );
+      println!(
+        "inode area:   {} blocks",
+        super_block.inode_area_blocks
+      );
+      println!(
+        "data bitmap:  {} blocks",
+        super_block.data_bitmap_blocks
+      );
+      println!(
+        "data area:    {} blocks",
+        super_block.data_area_blocks
+      );
+      println!("format:       {}", format);
+      println!(
+        "inodes:       {} / {} used",
+        efs.used_inodes(),
+        total_inodes
+      );
+      println!(
+        "data blocks:  {} / {} used",
+        efs.used_data_blocks(),
+        super_block.data_area_blocks
+      );
+    }
+    _ => unreachable!(),
+  }
+  efs.lock().commit();
+  Ok(())
+}
+
+/// Milliseconds since the Unix epoch on the host.
+fn host_time_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_millis() as u64
+}
+
+/// Drop the last extension of the last component of `path`.
+fn strip_ext(mut path: String) -> String {
+  let name_start = path.rfind('/').map_or(0, |idx| idx + 1);
+  // a leading dot starts a hidden name rather than an extension
+  if let Some(idx) =
+    path[name_start..].rfind('.').filter(|&idx| idx > 0)
+  {
+    path.truncate(name_start + idx);
+  }
+  path
+}
+
+/// Tests share the global block cache, so they run one at a time
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
+  let _guard = TEST_LOCK.lock();
   // efs block file
   let block_file = Arc::new(BlockFile(Mutex::new({
     let f = OpenOptions::new()
@@ -208,3 +779,1063 @@ fn efs_test() -> std::io::Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn dir_test() {
+  let _guard = TEST_LOCK.lock();
+  let (_, efs) = memory_fs(2048);
+  let root_inode =
+    Arc::new(EasyFileSystem::root_inode(&efs));
+
+  // directories come with `.` and `..`, paths walk through them
+  let dir = root_inode.mkdir("dir").unwrap();
+  assert!(dir.is_dir());
+  assert_eq!(dir.ls(), vec![".", ".."]);
+  let sub = dir.mkdir("sub").unwrap();
+  sub.create("filec").unwrap();
+  assert!(root_inode.mkdir("dir").is_none());
+  assert_eq!(
+    root_inode
+      .find_path("dir/./sub/filec")
+      .unwrap()
+      .inode_id(),
+    sub.find("filec").unwrap().inode_id()
+  );
+  assert_eq!(
+    root_inode
+      .find_path("/dir/sub/../..")
+      .unwrap()
+      .inode_id(),
+    root_inode.inode_id()
+  );
+  assert_eq!(
+    root_inode.find_path("..").unwrap().inode_id(),
+    root_inode.inode_id()
+  );
+  assert!(root_inode
+    .find_path("dir/sub/filec/x")
+    .is_none());
+
+  // entries are read one at a time with their types
+  let mut entries = Vec::new();
+  let mut offset = 0;
+  while let Some((entry, next)) = dir.read_dirent(offset) {
+    entries.push((entry.name, entry.type_));
+    offset = next;
+  }
+  assert_eq!(
+    entries,
+    vec![
+      (".".to_string(), DiskInodeType::Directory),
+      ("..".to_string(), DiskInodeType::Directory),
+      ("sub".to_string(), DiskInodeType::Directory),
+    ]
+  );
+  // entries are as long as their names, the offset of the next one is
+  // returned with each
+  let (_, next) = sub.read_dirent(0).unwrap();
+  let (_, next) = sub.read_dirent(next).unwrap();
+  let (entry, next) = sub.read_dirent(next).unwrap();
+  assert_eq!(entry.name, "filec");
+  assert!(sub.read_dirent(next).is_none());
+  assert!(sub
+    .find("filec")
+    .unwrap()
+    .read_dirent(0)
+    .is_none());
+
+  // names are up to 255 bytes, dots included
+  let long = "a.".repeat(127) + "b";
+  assert!(sub.create(&long).is_some());
+  assert!(sub.create(&(long.clone() + "c")).is_none());
+  assert!(sub.create("").is_none());
+  assert!(sub.create("lib.so.1").is_some());
+  assert!(sub.rename(&long, &sub, "short"));
+  assert!(sub.rename("short", &sub, &long));
+  // removing an entry leaves the others where they are, and its
+  // space is taken again
+  let offsets = || {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while let Some((entry, next)) = sub.read_dirent(offset)
+    {
+      offsets.push((entry.name, offset));
+      offset = next;
+    }
+    offsets
+  };
+  let before = offsets();
+  assert!(sub.unlink("filec"));
+  assert_eq!(
+    offsets(),
+    before
+      .into_iter()
+      .filter(|(name, _)| name != "filec")
+      .collect::<Vec<_>>()
+  );
+  let size = sub.metadata().size;
+  assert!(sub.create("filed").is_some());
+  assert_eq!(sub.metadata().size, size);
+  assert!(sub.unlink("filed"));
+  assert!(sub.unlink(&long));
+  assert!(sub.unlink("lib.so.1"));
+}
+
+#[test]
+fn rename_test() {
+  let _guard = TEST_LOCK.lock();
+  let (_, efs) = memory_fs(2048);
+  let root_inode =
+    Arc::new(EasyFileSystem::root_inode(&efs));
+  root_inode.create("filea").unwrap();
+  let dir = root_inode.mkdir("dir").unwrap();
+  let sub = dir.mkdir("sub").unwrap();
+  sub.create("filec").unwrap();
+
+  // entries can be moved between directories and removed
+  assert!(sub.rename("filec", &dir, "filed"));
+  assert!(sub.find("filec").is_none());
+  assert!(dir.find("filed").is_some());
+  assert!(!dir.rmdir("filed"));
+  assert!(!dir.unlink("sub"));
+  assert!(!root_inode.rename("dir", &sub, "dir"));
+  assert!(dir.rename("sub", &root_inode, "sub"));
+  assert_eq!(
+    root_inode.find_path("sub/..").unwrap().inode_id(),
+    root_inode.inode_id()
+  );
+  assert!(!root_inode.rmdir("dir"));
+  assert!(dir.unlink("filed"));
+  assert!(root_inode.rmdir("dir"));
+  assert!(root_inode.rmdir("sub"));
+  assert!(root_inode.find("dir").is_none());
+  assert_eq!(root_inode.ls(), vec![".", "..", "filea"]);
+
+  // a directory spanning indirect blocks shrinks as entries are removed
+  let dir = root_inode.mkdir("dir").unwrap();
+  let dir_size = dir.metadata().size;
+  let names: Vec<String> =
+    (0..600).map(|i| format!("f{}", i)).collect();
+  for name in names.iter() {
+    dir.create(name).unwrap();
+  }
+  for name in names.iter().rev().step_by(2) {
+    assert!(dir.unlink(name));
+  }
+  for name in names.iter().step_by(2) {
+    assert!(dir.find(name).is_some());
+    assert!(dir.unlink(name));
+  }
+  assert_eq!(dir.ls(), vec![".", ".."]);
+  // entries removed while the directory is read do not hide others
+  for name in names.iter() {
+    dir.create(name).unwrap();
+  }
+  let mut removed = 0;
+  let mut offset = 0;
+  while let Some((entry, next)) = dir.read_dirent(offset) {
+    if entry.name != "." && entry.name != ".." {
+      assert!(dir.unlink(&entry.name));
+      removed += 1;
+    }
+    offset = next;
+  }
+  assert_eq!(removed, names.len());
+  assert_eq!(dir.metadata().size, dir_size);
+  assert!(root_inode.rmdir("dir"));
+}
+
+#[test]
+fn link_test() {
+  let _guard = TEST_LOCK.lock();
+  let (device, efs) = memory_fs(2048);
+  let root_inode = EasyFileSystem::root_inode(&efs);
+
+  // hard links share an inode, which lives until its last link is gone
+  // and no vfs inode refers to it
+  let fileb = root_inode.create("fileb").unwrap();
+  fileb.write_at(0, b"linked");
+  assert!(root_inode.link("filec", &fileb));
+  assert!(!root_inode.link("filec", &fileb));
+  assert_eq!(fileb.nlink(), 2);
+  assert!(root_inode.unlink("fileb"));
+  assert_eq!(fileb.nlink(), 1);
+  let filec = root_inode.find("filec").unwrap();
+  assert_eq!(filec.inode_id(), fileb.inode_id());
+  assert!(root_inode.unlink("filec"));
+  assert_eq!(fileb.nlink(), 0);
+  let mut buf = [0u8; 6];
+  assert_eq!(filec.read_at(0, &mut buf), 6);
+  assert_eq!(&buf, b"linked");
+  let inode_id = fileb.inode_id();
+  drop(filec);
+  assert!(
+    root_inode.create("fileb").unwrap().inode_id()
+      != inode_id
+  );
+  drop(fileb);
+  assert!(root_inode.unlink("fileb"));
+  assert_eq!(
+    root_inode.create("fileb").unwrap().inode_id(),
+    inode_id
+  );
+  assert!(!root_inode.link("dir", &root_inode));
+
+  // a link count already at zero, as in a corrupted image, is not
+  // dropped any further
+  let inode_id =
+    root_inode.create("filed").unwrap().inode_id();
+  let (block_id, offset) =
+    efs.lock().get_disk_inode_pos(inode_id);
+  drop((root_inode, efs));
+  // the link count follows the size in a disk inode
+  device.live.lock().unwrap()[block_id as usize]
+    [offset + 4..offset + 8]
+    .fill(0);
+  let efs = EasyFileSystem::open(device);
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  assert!(!root_inode.unlink("filed"));
+  assert!(root_inode.find("filed").is_some());
+}
+
+#[test]
+fn symlink_test() {
+  let _guard = TEST_LOCK.lock();
+  let (_, efs) = memory_fs(2048);
+  let root_inode = EasyFileSystem::root_inode(&efs);
+
+  // symbolic links keep their target path, which may dangle
+  let link =
+    root_inode.symlink("link", "/dir/sub").unwrap();
+  assert!(link.is_symlink());
+  assert_eq!(link.read_link().unwrap(), "/dir/sub");
+  assert!(root_inode.find("link").unwrap().is_symlink());
+  assert!(root_inode.read_link().is_none());
+  assert!(root_inode.symlink("link", "x").is_none());
+  assert!(root_inode.unlink("link"));
+
+  // a target spanning blocks is kept whole, an overlong one is
+  // refused without leaving an entry behind
+  let target = "/d".repeat(BLOCK_SZ);
+  let link = root_inode.symlink("long", &target).unwrap();
+  assert_eq!(link.read_link().unwrap(), target);
+  let target = "x".repeat(16 * BLOCK_SZ + 1);
+  assert!(root_inode
+    .symlink("overlong", &target)
+    .is_none());
+  assert!(root_inode.find("overlong").is_none());
+}
+
+#[test]
+fn metadata_test() {
+  let _guard = TEST_LOCK.lock();
+  let (_, efs) = memory_fs(2048);
+  let root_inode = EasyFileSystem::root_inode(&efs);
+
+  // metadata is kept on disk
+  let before = host_time_ms();
+  let file = root_inode.create("filed").unwrap();
+  let meta = file.metadata();
+  assert_eq!(meta.type_, DiskInodeType::File);
+  assert_eq!(
+    (meta.mode, meta.nlink, meta.uid, meta.size),
+    (0o644, 1, 0, 0)
+  );
+  assert!(meta.mtime >= before && meta.ctime == meta.mtime);
+  std::thread::sleep(std::time::Duration::from_millis(2));
+  file.write_at(0, b"stat");
+  let written = file.metadata();
+  assert_eq!(written.size, 4);
+  assert!(written.mtime > meta.mtime);
+
+  // a read after a write updates the access time, later ones do not
+  std::thread::sleep(std::time::Duration::from_millis(2));
+  let mut buf = [0u8; 4];
+  file.read_at(0, &mut buf);
+  let read = file.metadata();
+  assert!(read.atime > written.mtime);
+  std::thread::sleep(std::time::Duration::from_millis(2));
+  file.read_at(0, &mut buf);
+  assert_eq!(file.metadata().atime, read.atime);
+  assert_eq!(
+    root_inode.mkdir("dird").unwrap().metadata().type_,
+    DiskInodeType::Directory
+  );
+  assert!(root_inode.rmdir("dird"));
+  assert!(root_inode.unlink("filed"));
+}
+
+#[test]
+fn truncate_test() {
+  let _guard = TEST_LOCK.lock();
+  let (_, efs) = memory_fs(8192);
+  let root_inode = EasyFileSystem::root_inode(&efs);
+
+  // removed files give their blocks back
+  for _ in 0..4 {
+    let file = root_inode.create("filee").unwrap();
+    file.write_at(0, &[1u8; 2000 * BLOCK_SZ]);
+    assert!(root_inode.unlink("filee"));
+  }
+
+  // truncation frees the tail, growth leaves holes reading as zeros
+  let file = root_inode.create("filef").unwrap();
+  for _ in 0..4 {
+    file.write_at(0, &[1u8; 2000 * BLOCK_SZ]);
+    assert!(file.truncate(300 * BLOCK_SZ + 7));
+    assert!(file.truncate(10000 * BLOCK_SZ));
+    assert_eq!(
+      file.metadata().size as usize,
+      10000 * BLOCK_SZ
+    );
+    let mut buf = [0xffu8; BLOCK_SZ];
+    assert_eq!(
+      file.read_at(300 * BLOCK_SZ, &mut buf),
+      BLOCK_SZ
+    );
+    assert!(buf[..7].iter().all(|&b| b == 1));
+    assert!(buf[7..].iter().all(|&b| b == 0));
+    file.write_at(9000 * BLOCK_SZ + 3, b"sparse");
+    assert_eq!(
+      file.read_at(9000 * BLOCK_SZ, &mut buf),
+      BLOCK_SZ
+    );
+    assert_eq!(&buf[..9], b"\0\0\0sparse");
+    assert!(buf[9..].iter().all(|&b| b == 0));
+    assert_eq!(
+      file.read_at(5000 * BLOCK_SZ, &mut buf),
+      BLOCK_SZ
+    );
+    assert!(buf.iter().all(|&b| b == 0));
+    assert!(file.truncate(0));
+  }
+
+  // writes stop at the maximum size of a file
+  use easy_fs::MAX_FILE_SIZE;
+  assert_eq!(file.write_at(MAX_FILE_SIZE - 1, b"end"), 1);
+  assert_eq!(file.metadata().size as usize, MAX_FILE_SIZE);
+  assert_eq!(file.write_at(MAX_FILE_SIZE, b"end"), 0);
+  assert_eq!(file.write_at(1 << 32, b"end"), 0);
+  assert_eq!(file.write_at(usize::MAX, b"end"), 0);
+  assert!(file.truncate(0));
+  assert!(!root_inode.truncate(0));
+  assert!(root_inode.unlink("filef"));
+
+  // a full filesystem cuts writes short and refuses new entries, while
+  // blocks already there can still be written
+  let file = root_inode.create("fileg").unwrap();
+  let data = vec![5u8; 8192 * BLOCK_SZ];
+  let len = file.write_at(0, &data);
+  assert!(len > 0 && len < data.len());
+  assert_eq!(file.write_at(len, b"more"), 0);
+  assert!(root_inode.create("fileh").is_none());
+  assert_eq!(file.write_at(0, b"over"), 4);
+  drop(file);
+  assert!(root_inode.unlink("fileg"));
+  assert!(root_inode.create("fileh").is_some());
+  assert!(root_inode.unlink("fileh"));
+}
+
+/// A device in memory that loses every write after the first `budget`
+/// ones, as if the machine crashed there. Reads still see the lost writes
+/// so that the filesystem runs on unaware of the crash.
+#[cfg(test)]
+struct CrashDevice {
+  /// Blocks as the filesystem sees them
+  live: Mutex<Vec<[u8; BLOCK_SZ]>>,
+  /// Blocks as they are left on disk
+  disk: Mutex<Vec<[u8; BLOCK_SZ]>>,
+  /// Number of writes until the crash
+  budget: Mutex<usize>,
+}
+
+#[cfg(test)]
+impl CrashDevice {
+  fn new(
+    image: Vec<[u8; BLOCK_SZ]>,
+    budget: usize,
+  ) -> Arc<Self> {
+    Arc::new(Self {
+      live: Mutex::new(image.clone()),
+      disk: Mutex::new(image),
+      budget: Mutex::new(budget),
+    })
+  }
+
+  /// The blocks left on disk
+  fn image(&self) -> Vec<[u8; BLOCK_SZ]> {
+    self.disk.lock().unwrap().clone()
+  }
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashDevice {
+  fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    buf.copy_from_slice(
+      &self.live.lock().unwrap()[block_id],
+    );
+  }
+
+  fn write_block(&self, block_id: usize, buf: &[u8]) {
+    self.live.lock().unwrap()[block_id]
+      .copy_from_slice(buf);
+    let mut budget = self.budget.lock().unwrap();
+    if *budget > 0 {
+      *budget -= 1;
+      self.disk.lock().unwrap()[block_id]
+        .copy_from_slice(buf);
+    }
+  }
+}
+
+/// Create a filesystem of `total_blocks` blocks on a device in memory
+/// which never crashes, with the clock of the host.
+#[cfg(test)]
+fn memory_fs(
+  total_blocks: usize,
+) -> (Arc<CrashDevice>, Arc<spin::Mutex<EasyFileSystem>>) {
+  memory_fs_with_format(total_blocks, InodeFormat::Indirect)
+}
+
+/// Create a filesystem like [`memory_fs`], whose inodes index their data
+/// blocks in `format`
+#[cfg(test)]
+fn memory_fs_with_format(
+  total_blocks: usize,
+  format: InodeFormat,
+) -> (Arc<CrashDevice>, Arc<spin::Mutex<EasyFileSystem>>) {
+  let device = CrashDevice::new(
+    vec![[0u8; BLOCK_SZ]; total_blocks],
+    usize::MAX,
+  );
+  let efs = EasyFileSystem::create_with_format(
+    device.clone(),
+    total_blocks as u32,
+    1,
+    format,
+  );
+  efs.lock().set_clock(host_time_ms);
+  (device, efs)
+}
+
+/// What can be seen of a filesystem: every path with the contents of
+/// files, and the numbers of inodes and data blocks in use
+#[cfg(test)]
+type FsState = (Vec<(String, Vec<u8>)>, usize, usize);
+
+/// Get the state of the filesystem of `root` with its `used` numbers of
+/// inodes and data blocks
+#[cfg(test)]
+fn fs_state(
+  root: &easy_fs::Inode,
+  used: (usize, usize),
+) -> FsState {
+  fn walk(
+    dir: &easy_fs::Inode,
+    path: &str,
+    files: &mut Vec<(String, Vec<u8>)>,
+  ) {
+    for name in dir.ls() {
+      if name == "." || name == ".." {
+        continue;
+      }
+      let inode = dir.find(&name).unwrap();
+      let path = format!("{}/{}", path, name);
+      if inode.is_dir() {
+        files.push((format!("{}/", path), Vec::new()));
+        walk(&inode, &path, files);
+      } else {
+        let mut buf =
+          vec![0u8; inode.metadata().size as usize];
+        inode.read_at(0, &mut buf);
+        files.push((path, buf));
+      }
+    }
+  }
+  let mut files = Vec::new();
+  walk(root, "", &mut files);
+  (files, used.0, used.1)
+}
+
+/// Run a series of operations on `device`, return the state before and
+/// after each of them. With `write_back`, operations are committed every
+/// few of them instead of as they finish.
+#[cfg(test)]
+fn crash_ops(
+  device: Arc<CrashDevice>,
+  write_back: bool,
+) -> Vec<FsState> {
+  let efs = EasyFileSystem::open(device);
+  efs.lock().set_write_back(write_back);
+  let root = EasyFileSystem::root_inode(&efs);
+  let state = || {
+    let used = {
+      let efs = efs.lock();
+      (efs.used_inodes(), efs.used_data_blocks())
+    };
+    fs_state(&root, used)
+  };
+  let mut states = vec![state()];
+  let mut step = |op: &dyn Fn()| {
+    op();
+    // the periodic flush of a write-back filesystem
+    if write_back && states.len() % 3 == 0 {
+      efs.lock().commit();
+    }
+    states.push(state());
+  };
+  step(&|| {
+    root.create("a").unwrap();
+  });
+  step(&|| {
+    root.find("a").unwrap().write_at(0, &[1u8; 3000]);
+  });
+  step(&|| {
+    root.mkdir("d").unwrap();
+  });
+  step(&|| {
+    root.find("d").unwrap().create("b").unwrap();
+  });
+  step(&|| {
+    let b = root.find("d").unwrap().find("b").unwrap();
+    b.write_at(0, &[2u8; 40 * BLOCK_SZ]);
+  });
+  step(&|| {
+    assert!(root.rename(
+      "a",
+      &root.find("d").unwrap(),
+      "c"
+    ));
+  });
+  step(&|| {
+    assert!(root.link(
+      "e",
+      &root.find("d").unwrap().find("c").unwrap()
+    ));
+  });
+  step(&|| {
+    assert!(root.find("d").unwrap().unlink("b"));
+  });
+  step(&|| {
+    assert!(root.find("e").unwrap().truncate(100));
+  });
+  step(&|| {
+    assert!(root.find("d").unwrap().unlink("c"));
+  });
+  step(&|| {
+    assert!(root.unlink("e"));
+  });
+  step(&|| {
+    assert!(root.rmdir("d"));
+  });
+  efs.lock().set_write_back(false);
+  states
+}
+
+#[test]
+fn crash_test() {
+  let _guard = TEST_LOCK.lock();
+  let (device, _) = memory_fs(2048);
+  let image = device.image();
+  for write_back in [false, true] {
+    // a run without a crash
+    let device =
+      CrashDevice::new(image.clone(), usize::MAX);
+    let states = crash_ops(device.clone(), write_back);
+    let writes =
+      usize::MAX - *device.budget.lock().unwrap();
+    assert!(writes > 0);
+    // a crash at any write leaves the state before or after some
+    // operation once the journal is replayed
+    for budget in 0..writes {
+      let device = CrashDevice::new(image.clone(), budget);
+      crash_ops(device.clone(), write_back);
+      let efs = EasyFileSystem::open(CrashDevice::new(
+        device.image(),
+        usize::MAX,
+      ));
+      let used = {
+        let efs = efs.lock();
+        (efs.used_inodes(), efs.used_data_blocks())
+      };
+      let state =
+        fs_state(&EasyFileSystem::root_inode(&efs), used);
+      assert!(
+        states.contains(&state),
+        "inconsistent after a crash at write {}{}",
+        budget,
+        if write_back {
+          " in write-back mode"
+        } else {
+          ""
+        }
+      );
+    }
+  }
+
+  // an operation too large for a transaction is committed in parts,
+  // a crash in between leaves a consistent filesystem
+  let (device, efs) = memory_fs(32768);
+  let root = EasyFileSystem::root_inode(&efs);
+  let file = root.create("big").unwrap();
+  // the index blocks exist before the write fills the holes among them
+  for i in 0..100 {
+    file.write_at(i * 128 * BLOCK_SZ, b"x");
+  }
+  let data = vec![3u8; 100 * 128 * BLOCK_SZ];
+  *device.budget.lock().unwrap() = 400;
+  assert_eq!(file.write_at(0, &data), data.len());
+  let efs = EasyFileSystem::open(CrashDevice::new(
+    device.image(),
+    usize::MAX,
+  ));
+  assert!(efs.lock().check(false).is_empty());
+}
+
+#[test]
+fn fsck_test() {
+  use easy_fs::FsckProblem;
+  let _guard = TEST_LOCK.lock();
+  let (device, efs) = memory_fs(2048);
+  let (a_id, b_id) = {
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let a = root_inode.create("a").unwrap();
+    a.write_at(0, &[1u8; 1000]);
+    let b = root_inode.create("b").unwrap();
+    b.write_at(0, &[2u8; 1000]);
+    root_inode.mkdir("d").unwrap().create("c").unwrap();
+    (a.inode_id(), b.inode_id())
+  };
+  assert!(efs.lock().check(false).is_empty());
+  // `b` starts with the first block of `a`, direct block ids follow
+  // 44 bytes of size, link count, times and owner in a disk inode
+  let pos = |inode_id: u32| {
+    let (block, offset) =
+      efs.lock().get_disk_inode_pos(inode_id);
+    (block as usize, offset + 44)
+  };
+  let ((a_block, a_offset), (b_block, b_offset)) =
+    (pos(a_id), pos(b_id));
+  drop(efs);
+
+  // corrupt the image behind the filesystem's back
+  {
+    let mut blocks = device.live.lock().unwrap();
+    let word = |block: &[u8; BLOCK_SZ], i: usize| {
+      u32::from_le_bytes(
+        block[i * 4..i * 4 + 4].try_into().unwrap(),
+      ) as usize
+    };
+    // the super block holds the magic, the total size, then the sizes
+    // of the journal, inode bitmap, inode area and data bitmap
+    let journal = word(&blocks[0], 2);
+    let inode_bitmap = 1 + journal;
+    let data_bitmap = inode_bitmap
+      + word(&blocks[0], 3)
+      + word(&blocks[0], 4);
+    for block in 0..word(&blocks[0], 5) {
+      blocks[data_bitmap + block] = [0u8; BLOCK_SZ];
+    }
+    // an inode nothing refers to
+    blocks[inode_bitmap][100 / 8] |= 1 << (100 % 8);
+    let shared: [u8; 4] = blocks[a_block]
+      [a_offset..a_offset + 4]
+      .try_into()
+      .unwrap();
+    blocks[b_block][b_offset..b_offset + 4]
+      .copy_from_slice(&shared);
+  }
+
+  let efs = EasyFileSystem::open(device);
+  let problems = efs.lock().check(false);
+  assert!(problems.contains(&FsckProblem::OrphanInode(100)));
+  assert!(problems
+    .iter()
+    .any(|p| matches!(p, FsckProblem::FreeBlockInUse(_))));
+  assert!(problems.iter().any(|p| matches!(
+    p,
+    FsckProblem::DuplicateBlock { inode_id, .. } if *inode_id == b_id
+  )));
+  assert_eq!(efs.lock().check(true), problems);
+  assert!(efs.lock().check(false).is_empty());
+  // `b` got a copy of the block it shared
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  let mut buf = [0u8; 1000];
+  root_inode.find("a").unwrap().read_at(0, &mut buf);
+  assert!(buf.iter().all(|&byte| byte == 1));
+  root_inode.find("b").unwrap().read_at(0, &mut buf);
+  assert!(buf[..BLOCK_SZ].iter().all(|&byte| byte == 1));
+  assert!(buf[BLOCK_SZ..].iter().all(|&byte| byte == 2));
+}
+
+#[test]
+fn cache_test() {
+  use easy_fs::block_cache_stats;
+  let _guard = TEST_LOCK.lock();
+  let (device, _) = memory_fs(2048);
+
+  // a cache of a single block keeps working while more are in use
+  let before = block_cache_stats();
+  let efs =
+    EasyFileSystem::open_with_cache(device.clone(), 1);
+  let data: Vec<u8> =
+    (0..20 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+  {
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.create("file").unwrap();
+    assert_eq!(file.write_at(0, &data), data.len());
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf), data.len());
+    assert_eq!(buf, data);
+  }
+  let after = block_cache_stats();
+  assert!(after.hits > before.hits);
+  assert!(after.misses > before.misses);
+  assert!(after.evictions > before.evictions);
+  assert!(after.write_backs > before.write_backs);
+  drop(efs);
+
+  // with write back, operations reach the disk at the next commit
+  let efs =
+    EasyFileSystem::open_with_cache(device.clone(), 64);
+  efs.lock().set_write_back(true);
+  {
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    root_inode
+      .create("later")
+      .unwrap()
+      .write_at(0, b"written back");
+  }
+  let uncommitted = device.image();
+  efs.lock().commit();
+  let committed = device.image();
+  drop(efs);
+  let efs = EasyFileSystem::open(CrashDevice::new(
+    uncommitted,
+    usize::MAX,
+  ));
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  assert!(root_inode.find("later").is_none());
+  assert!(root_inode.find("file").is_some());
+  drop(root_inode);
+  let efs = EasyFileSystem::open(CrashDevice::new(
+    committed,
+    usize::MAX,
+  ));
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  let mut buf = [0u8; 32];
+  let len =
+    root_inode.find("later").unwrap().read_at(0, &mut buf);
+  assert_eq!(&buf[..len], b"written back");
+}
+
+#[test]
+fn extent_test() {
+  use easy_fs::FsckProblem;
+  let _guard = TEST_LOCK.lock();
+  let (device, efs) =
+    memory_fs_with_format(24576, InodeFormat::Extent);
+  let used = || efs.lock().used_data_blocks();
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  let base = used();
+
+  // past the 8 MiB the indirect format holds, written in order the
+  // blocks form a single extent without any tree node
+  let big = root_inode.create("big").unwrap();
+  let pattern = |offset: usize| {
+    (0..64 * 1024)
+      .map(|i| ((offset + i) % 251) as u8)
+      .collect::<Vec<_>>()
+  };
+  let size = 9 * 1024 * 1024;
+  for offset in (0..size).step_by(64 * 1024) {
+    assert_eq!(
+      big.write_at(offset, &pattern(offset)),
+      64 * 1024
+    );
+  }
+  assert_eq!(used() - base, size / BLOCK_SZ);
+  let mut buf = vec![0u8; 64 * 1024];
+  for offset in [0, 4 * 1024 * 1024, size - 64 * 1024] {
+    assert_eq!(big.read_at(offset, &mut buf), buf.len());
+    assert_eq!(buf, pattern(offset));
+  }
+  assert!(big.truncate(size / 2));
+  assert_eq!(used() - base, size / BLOCK_SZ / 2);
+  assert!(big.truncate(0));
+  assert_eq!(used(), base);
+
+  // every other block written, each one an extent of its own
+  let sparse = root_inode.create("sparse").unwrap();
+  let count = 600;
+  let mark = |i: usize| (i % 250) as u8 + 1;
+  for i in 0..count {
+    sparse.write_at(i * 2 * BLOCK_SZ, &[mark(i); 8]);
+  }
+  // holding more extents than the nodes a level below the root do
+  assert!(used() - base > count);
+  let mut byte = [0u8; 1];
+  for i in 0..count {
+    sparse.read_at(i * 2 * BLOCK_SZ + 7, &mut byte);
+    assert_eq!(byte[0], mark(i));
+  }
+  for i in 0..count - 1 {
+    sparse.read_at((i * 2 + 1) * BLOCK_SZ, &mut byte);
+    assert_eq!(byte[0], 0);
+  }
+  assert!(sparse.truncate(count * BLOCK_SZ));
+  sparse.read_at((count - 2) * BLOCK_SZ, &mut byte);
+  assert_eq!(byte[0], mark(count / 2 - 1));
+  assert!(efs.lock().check(false).is_empty());
+  assert!(sparse.truncate(0));
+  assert_eq!(used(), base);
+  drop((big, sparse, root_inode));
+
+  // the format is kept in the image
+  let efs = EasyFileSystem::open(device.clone());
+  assert_eq!(
+    efs.lock().inode_format(),
+    InodeFormat::Extent
+  );
+  let (a_id, b_id) = {
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let a = root_inode.create("a").unwrap();
+    a.write_at(0, &[1u8; 1000]);
+    let b = root_inode.create("b").unwrap();
+    b.write_at(0, &[2u8; 1000]);
+    (a.inode_id(), b.inode_id())
+  };
+  // the root extent of `b` follows the 4 bytes of depth and count at 44
+  // bytes into a disk inode, it is pointed at the blocks of `a`
+  let pos = |inode_id: u32| {
+    let (block, offset) =
+      efs.lock().get_disk_inode_pos(inode_id);
+    (block as usize, offset + 48 + 8)
+  };
+  let ((a_block, a_offset), (b_block, b_offset)) =
+    (pos(a_id), pos(b_id));
+  drop(efs);
+  {
+    let mut blocks = device.live.lock().unwrap();
+    let start: [u8; 4] = blocks[a_block]
+      [a_offset..a_offset + 4]
+      .try_into()
+      .unwrap();
+    blocks[b_block][b_offset..b_offset + 4]
+      .copy_from_slice(&start);
+  }
+  let efs = EasyFileSystem::open(device);
+  let problems = efs.lock().check(true);
+  assert!(problems.iter().any(|p| matches!(
+    p,
+    FsckProblem::DuplicateBlock { inode_id, .. } if *inode_id == b_id
+  )));
+  assert!(problems
+    .iter()
+    .any(|p| matches!(p, FsckProblem::LeakedBlock(_))));
+  assert!(efs.lock().check(false).is_empty());
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  let mut buf = [0u8; 1000];
+  root_inode.find("b").unwrap().read_at(0, &mut buf);
+  assert!(buf.iter().all(|&byte| byte == 1));
+}
+
+#[test]
+fn toolbox_test() -> std::io::Result<()> {
+  let _guard = TEST_LOCK.lock();
+  let image = "target/toolbox.img";
+  let run = |args: &[&str]| {
+    let matches = app().get_matches_from(
+      ["easy-fs-fuse"].iter().chain(args.iter()),
+    );
+    let (name, matches) = matches.subcommand();
+    easyfs_command(name, matches.unwrap())
+  };
+  {
+    let f = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .open(image)?;
+    f.set_len(4096 * 512)?;
+    let block_file = Arc::new(BlockFile(Mutex::new(f)));
+    EasyFileSystem::create(block_file, 4096, 1);
+  }
+  let data = std::fs::read("src/main.rs")?;
+  run(&["mkdir", image, "/dir"])?;
+  run(&["put", image, "src/main.rs", "/dir/main.rs"])?;
+  let before = std::fs::read(image)?;
+  run(&["ls", image, "dir"])?;
+  run(&["info", image])?;
+  run(&[
+    "extract",
+    image,
+    "/dir/main.rs",
+    "target/main.rs",
+  ])?;
+  assert_eq!(std::fs::read("target/main.rs")?, data);
+  // reading does not write to the image
+  assert!(std::fs::read(image)? == before);
+
+  // replacing a file drops its old tail
+  run(&["put", image, "Cargo.toml", "/dir/main.rs"])?;
+  run(&[
+    "extract",
+    image,
+    "/dir/main.rs",
+    "target/main.rs",
+  ])?;
+  assert_eq!(
+    std::fs::read("target/main.rs")?,
+    std::fs::read("Cargo.toml")?
+  );
+
+  let kind = |args: &[&str]| run(args).unwrap_err().kind();
+  assert_eq!(
+    kind(&["mkdir", image, "dir"]),
+    ErrorKind::AlreadyExists
+  );
+  assert_eq!(
+    kind(&["cat", image, "dir/x"]),
+    ErrorKind::NotFound
+  );
+  assert_eq!(
+    kind(&["put", image, "Cargo.toml", "dir"]),
+    ErrorKind::InvalidInput
+  );
+  assert_eq!(
+    kind(&["put", image, "Cargo.toml", "no/x"]),
+    ErrorKind::NotFound
+  );
+  assert_eq!(
+    kind(&["rm", image, "/"]),
+    ErrorKind::InvalidInput
+  );
+  assert_eq!(kind(&["rm", image, "dir"]), ErrorKind::Other);
+  assert_eq!(
+    kind(&["info", "Cargo.toml"]),
+    ErrorKind::InvalidData
+  );
+
+  // files too large for an inode or for the space left are refused
+  std::fs::write("target/big.bin", vec![7u8; 9 << 20])?;
+  assert_eq!(
+    kind(&["put", image, "target/big.bin", "dir/big"]),
+    ErrorKind::InvalidInput
+  );
+  std::fs::write("target/big.bin", vec![7u8; 3 << 20])?;
+  assert_eq!(
+    kind(&["put", image, "target/big.bin", "dir/big"]),
+    ErrorKind::Other
+  );
+  run(&["rm", image, "dir/big"])?;
+
+  run(&["rm", image, "dir/main.rs"])?;
+  run(&["rm", image, "dir/"])?;
+  assert_eq!(
+    kind(&["ls", image, "dir"]),
+    ErrorKind::NotFound
+  );
+  let efs = EasyFileSystem::open(open_image(image)?);
+  assert_eq!(efs.lock().used_inodes(), 1);
+  assert_eq!(efs.lock().used_data_blocks(), 1);
+  assert!(efs.lock().check(false).is_empty());
+  Ok(())
+}
+
+#[test]
+fn fuse_test() -> std::io::Result<()> {
+  let _guard = TEST_LOCK.lock();
+  let image = "target/fuse.img";
+  let mountpoint = "target/mnt";
+  let block_file: Arc<dyn BlockDevice> =
+    Arc::new(BlockFile(Mutex::new({
+      let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(image)?;
+      f.set_len(4096 * 512)?;
+      f
+    })));
+  let efs = EasyFileSystem::create(block_file, 4096, 1);
+  std::fs::create_dir_all(mountpoint)?;
+  let device = match fuse::mount(mountpoint) {
+    Ok(device) => device,
+    // mounting needs root and a FUSE device
+    Err(err) => {
+      println!("skip fuse_test: {}", err);
+      return Ok(());
+    }
+  };
+  let server =
+    std::thread::spawn(move || fuse::serve(efs, device));
+
+  let data = std::fs::read("src/main.rs")?;
+  std::fs::create_dir("target/mnt/dir")?;
+  std::fs::write("target/mnt/dir/main.rs", &data)?;
+  assert_eq!(
+    std::fs::read("target/mnt/dir/main.rs")?,
+    data
+  );
+  std::fs::write("target/mnt/dir/main.rs", b"short")?;
+  assert_eq!(
+    std::fs::read("target/mnt/dir/main.rs")?,
+    b"short"
+  );
+  std::fs::rename(
+    "target/mnt/dir/main.rs",
+    "target/mnt/file",
+  )?;
+  let names = std::fs::read_dir(mountpoint)?
+    .map(|entry| {
+      entry.unwrap().file_name().into_string().unwrap()
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(names, vec!["dir", "file"]);
+  assert!(std::fs::metadata("target/mnt/dir")?.is_dir());
+  assert_eq!(
+    std::fs::metadata("target/mnt/file")?.len(),
+    5
+  );
+  assert_eq!(
+    std::fs::remove_dir(mountpoint.to_string() + "/x")
+      .unwrap_err()
+      .kind(),
+    ErrorKind::NotFound
+  );
+  std::fs::remove_dir("target/mnt/dir")?;
+
+  // writes past the max size or the space left fail
+  {
+    let mut file = File::create("target/mnt/big")?;
+    file.seek(SeekFrom::Start(
+      easy_fs::MAX_FILE_SIZE as u64,
+    ))?;
+    assert_eq!(
+      file.write(b"x").unwrap_err().raw_os_error(),
+      Some(libc::EFBIG)
+    );
+    file.seek(SeekFrom::Start(0))?;
+    assert_eq!(
+      file
+        .write_all(&vec![7u8; 3 << 20])
+        .unwrap_err()
+        .raw_os_error(),
+      Some(libc::ENOSPC)
+    );
+  }
+  std::fs::remove_file("target/mnt/big")?;
+
+  let target = std::ffi::CString::new(mountpoint).unwrap();
+  assert_eq!(
+    unsafe { libc::umount2(target.as_ptr(), 0) },
+    0
+  );
+  server.join().unwrap()?;
+  let efs = EasyFileSystem::open(open_image(image)?);
+  assert!(efs.lock().check(false).is_empty());
+  let root_inode = EasyFileSystem::root_inode(&efs);
+  assert_eq!(root_inode.ls(), vec![".", "..", "file"]);
+  Ok(())
+}
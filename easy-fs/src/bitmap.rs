@@ -16,15 +16,30 @@ const BLOCK_BITS: usize = BLOCK_SZ * 8;
 pub struct Bitmap {
   start_block_id: usize,
   blocks: usize,
+  /// Number of bits standing for something, the bits after them in the
+  /// last block are never allocated
+  len: usize,
 }
 
 impl Bitmap {
-  pub fn new(start_block_id: usize, blocks: usize) -> Self {
+  pub fn new(start_block_id: usize, blocks: usize, len: usize) -> Self {
     Self {
       start_block_id,
       blocks,
+      len,
     }
   }
+
+  /// Get the number of bits standing for something
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Get the id of the block holding `bit`
+  pub fn block_id_of(&self, bit: usize) -> usize {
+    self.start_block_id + bit / BLOCK_BITS
+  }
+
   pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
     // enumerate each block(block_id), we are trying to find a free bit within a block and set it as 1.
     for block_id in 0..self.blocks {
@@ -50,9 +65,14 @@ impl Bitmap {
             (bits64_pos, bits64.trailing_ones() as usize)
           })
         {
+          let bit = block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos;
+          // the bits past `len` are not backed by anything
+          if bit >= self.len {
+            return None;
+          }
           // modify cache
           bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-          Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos)
+          Some(bit)
         } else {
           // doesn't exist
           None
@@ -76,6 +96,54 @@ impl Bitmap {
       });
   }
 
+  /// Whether `bit` is allocated
+  pub fn get(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+    let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+    get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+      .lock()
+      .read(0, |bitmap_block: &BitmapBlock| {
+        bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+      })
+  }
+
+  /// Mark `bit` allocated or not regardless of its current state
+  pub fn set(
+    &self,
+    block_device: &Arc<dyn BlockDevice>,
+    bit: usize,
+    allocated: bool,
+  ) {
+    let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+    get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+      .lock()
+      .modify(0, |bitmap_block: &mut BitmapBlock| {
+        if allocated {
+          bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+        } else {
+          bitmap_block[bits64_pos] &= !(1u64 << inner_pos);
+        }
+      });
+  }
+
+  /// Get the number of allocated bits
+  pub fn allocated(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+    (0..self.blocks)
+      .map(|block_id| {
+        get_block_cache(
+          block_id + self.start_block_id,
+          Arc::clone(block_device),
+        )
+        .lock()
+        .read(0, |bitmap_block: &BitmapBlock| {
+          bitmap_block
+            .iter()
+            .map(|bits64| bits64.count_ones() as usize)
+            .sum::<usize>()
+        })
+      })
+      .sum()
+  }
+
   /// Get the max number of allocatable blocks
   pub fn maximum(&self) -> usize {
     self.blocks * BLOCK_BITS
@@ -1,4 +1,5 @@
 use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -9,6 +10,10 @@ lazy_static! {
     Mutex::new(BlockCacheManager::new());
 }
 
+/// Number of dirty blocks written back, counted outside the manager as
+/// blocks are synced with only their own lock held
+static WRITE_BACKS: AtomicUsize = AtomicUsize::new(0);
+
 /// Get the block cache corresponding to the given block id and block device
 pub fn get_block_cache(
   block_id: usize,
@@ -27,10 +32,44 @@ pub fn block_cache_sync_all() {
   }
 }
 
+/// Set the number of blocks the block cache holds
+pub fn set_block_cache_capacity(capacity: usize) {
+  BLOCK_CACHE_MANAGER.lock().set_capacity(capacity);
+}
+
+/// Get the counters of the block cache
+pub fn block_cache_stats() -> CacheStats {
+  CacheStats {
+    write_backs: WRITE_BACKS.load(Ordering::Relaxed),
+    ..BLOCK_CACHE_MANAGER.lock().stats
+  }
+}
+
+/// Get the number of cached blocks that are modified but not yet synced
+pub fn block_cache_dirty_count() -> usize {
+  let manager = BLOCK_CACHE_MANAGER.lock();
+  manager
+    .queue
+    .iter()
+    .filter(|(_, cache)| cache.lock().modified)
+    .count()
+}
+
+/// Sync all block cache to block device and drop them, so that blocks
+/// are loaded again through the device passed in next time
+pub fn block_cache_clear() {
+  block_cache_sync_all();
+  BLOCK_CACHE_MANAGER.lock().queue.clear();
+}
+
+/// Block data aligned for the on-disk structures holding `u64`s
+#[repr(C, align(8))]
+struct BlockData([u8; BLOCK_SZ]);
+
 /// Cached block inside memory
 pub struct BlockCache {
   /// cached block data
-  cache: [u8; BLOCK_SZ],
+  cache: BlockData,
   /// underlying block id
   block_id: usize,
   /// underlying block device
@@ -42,8 +81,8 @@ pub struct BlockCache {
 impl BlockCache {
   /// Load a new BlockCache from disk.
   pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
-    let mut cache = [0u8; BLOCK_SZ];
-    block_device.read_block(block_id, &mut cache);
+    let mut cache = BlockData([0u8; BLOCK_SZ]);
+    block_device.read_block(block_id, &mut cache.0);
     Self {
       cache,
       block_id,
@@ -54,7 +93,7 @@ impl BlockCache {
 
   /// Get the address of an offset inside the cached block data.
   fn addr_of_offset(&self, offset: usize) -> usize {
-    &self.cache[offset] as *const _ as usize
+    &self.cache.0[offset] as *const _ as usize
   }
 
   /// It can get an immutable reference to an on-disk data structure of type
@@ -98,7 +137,8 @@ impl BlockCache {
   pub fn sync(&mut self) {
     if self.modified {
       self.modified = false;
-      self.block_device.write_block(self.block_id, &self.cache);
+      self.block_device.write_block(self.block_id, &self.cache.0);
+      WRITE_BACKS.fetch_add(1, Ordering::Relaxed);
     }
   }
 }
@@ -109,16 +149,64 @@ impl Drop for BlockCache {
   }
 }
 
-/// Use a block cache of 16 blocks;
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Default number of blocks the cache holds
+pub const DEFAULT_CACHE_BLOCKS: usize = 16;
+
+/// Counters of the block cache, a snapshot taken by [`block_cache_stats`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+  /// Lookups served from the cache
+  pub hits: usize,
+  /// Lookups that had to read the block from the device
+  pub misses: usize,
+  /// Blocks dropped from the cache to make room for others
+  pub evictions: usize,
+  /// Dirty blocks written back to the device
+  pub write_backs: usize,
+}
+
+/// Block caches kept in least recently used order
 pub struct BlockCacheManager {
+  /// From the least recently used to the most recently used
   queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+  /// Number of blocks kept, more are only held while all are in use
+  capacity: usize,
+  stats: CacheStats,
 }
 
 impl BlockCacheManager {
   pub fn new() -> Self {
     Self {
       queue: VecDeque::new(),
+      capacity: DEFAULT_CACHE_BLOCKS,
+      stats: CacheStats::default(),
+    }
+  }
+
+  /// Set the number of blocks kept, evicting the least recently used
+  /// blocks not in use to fit.
+  pub fn set_capacity(&mut self, capacity: usize) {
+    assert!(capacity > 0);
+    self.capacity = capacity;
+    self.shrink(capacity);
+  }
+
+  /// Evict the least recently used blocks not in use until at most
+  /// `len` blocks are left or all of them are in use.
+  fn shrink(&mut self, len: usize) {
+    while self.queue.len() > len {
+      // a block cache is only referenced by the queue if nobody uses it
+      match self
+        .queue
+        .iter()
+        .position(|pair| Arc::strong_count(&pair.1) == 1)
+      {
+        Some(idx) => {
+          self.queue.remove(idx);
+          self.stats.evictions += 1;
+        }
+        None => break,
+      }
     }
   }
 
@@ -128,33 +216,24 @@ impl BlockCacheManager {
     block_device: Arc<dyn BlockDevice>,
   ) -> Arc<Mutex<BlockCache>> {
     // try to find a block-cache with the same block-id.
-    if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-      Arc::clone(&pair.1)
-    } else {
-      // substitute, throw out a block-cache. (FIFO here)
-      // the head block-cache may still being used,
-      // if the strong reference count less than 2 (< 2), this block-cache can be removed.
-      if self.queue.len() == BLOCK_CACHE_SIZE {
-        // from front to tail
-        if let Some((idx, _)) = self
-          .queue
-          .iter()
-          .enumerate()
-          .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-        {
-          self.queue.drain(idx..=idx);
-        } else {
-          // the block-caches in the self.queue are all being using, the queue is full.
-          panic!("Run out of BlockCache!");
-        }
-      }
-      // load block into memory and push it back to the self.queue, after that return to requester.
-      let block_cache = Arc::new(Mutex::new(BlockCache::new(
-        block_id,
-        Arc::clone(&block_device),
-      )));
-      self.queue.push_back((block_id, Arc::clone(&block_cache)));
-      block_cache
+    if let Some(idx) = self.queue.iter().position(|pair| pair.0 == block_id) {
+      self.stats.hits += 1;
+      // move it to the most recently used end
+      let pair = self.queue.remove(idx).unwrap();
+      let block_cache = Arc::clone(&pair.1);
+      self.queue.push_back(pair);
+      return block_cache;
     }
+    self.stats.misses += 1;
+    // if all the blocks are in use the cache grows past its capacity
+    // for a while, it shrinks back on later misses.
+    self.shrink(self.capacity - 1);
+    // load block into memory and push it back to the self.queue, after that return to requester.
+    let block_cache = Arc::new(Mutex::new(BlockCache::new(
+      block_id,
+      Arc::clone(&block_device),
+    )));
+    self.queue.push_back((block_id, Arc::clone(&block_cache)));
+    block_cache
   }
 }
@@ -1,26 +1,57 @@
-use alloc::sync::Arc;
+use alloc::{
+  collections::{BTreeMap, BTreeSet},
+  sync::Arc,
+  vec::Vec,
+};
 use spin::Mutex;
 
 use crate::{
-  block_cache_sync_all,
+  block_cache_clear, block_cache_dirty_count, block_cache_sync_all,
   block_dev::BlockDevice,
   get_block_cache,
+  journal::{Journal, JOURNAL_BLOCKS, JOURNAL_CAPACITY},
+  set_block_cache_capacity,
   vfs::Inode,
-  Bitmap, BLOCK_SZ, {DiskInode, DiskInodeType, SuperBlock},
+  Bitmap, InodeFormat, BLOCK_SZ, DEFAULT_CACHE_BLOCKS,
+  {DiskInode, DiskInodeType, SuperBlock},
 };
 
 type DataBlock = [u8; BLOCK_SZ];
 
 /// An easy file system on block
 pub struct EasyFileSystem {
-  /// Real device
+  /// Device all blocks are accessed through, the journal over the real one
   pub block_device: Arc<dyn BlockDevice>,
+  /// Journal all blocks are written through
+  journal: Arc<Journal>,
   /// Inode bitmap
   pub inode_bitmap: Bitmap,
   /// Data bitmap
   pub data_bitmap: Bitmap,
-  inode_area_start_block: u32,
-  data_area_start_block: u32,
+  pub(crate) inode_area_start_block: u32,
+  pub(crate) data_area_start_block: u32,
+  /// Number of vfs inodes alive for each inode id
+  open_inodes: BTreeMap<u32, usize>,
+  /// Data blocks deallocated in the current transaction
+  freed: Vec<u32>,
+  /// Current time in milliseconds since the Unix epoch
+  clock: fn() -> u64,
+  /// Format of the block index of all the inodes
+  format: InodeFormat,
+  /// Whether operations leave their changes in the cache to be committed
+  /// later instead of committing them as they finish
+  write_back: bool,
+  /// Whether reads leave the access times of inodes as they are
+  noatime: bool,
+}
+
+/// Most index blocks or extent tree nodes a step of a write or a new
+/// directory entry needs besides its data blocks
+pub(crate) const INDEX_RESERVE_BLOCKS: usize = 8;
+
+/// The clock of a filesystem nobody set one for
+fn no_clock() -> u64 {
+  0
 }
 
 impl EasyFileSystem {
@@ -30,29 +61,34 @@ impl EasyFileSystem {
     total_blocks: u32,
     inode_bitmap_blocks: u32,
   ) -> Arc<Mutex<Self>> {
-    // calculate block size of areas & create bitmaps
-    let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
-    let inode_num = inode_bitmap.maximum();
-    let inode_area_blocks =
-      ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1)
-        / BLOCK_SZ) as u32;
+    Self::create_with_format(
+      block_device,
+      total_blocks,
+      inode_bitmap_blocks,
+      InodeFormat::Indirect,
+    )
+  }
+
+  /// Create a filesystem like [`EasyFileSystem::create`], whose inodes
+  /// index their data blocks in `format`
+  pub fn create_with_format(
+    block_device: Arc<dyn BlockDevice>,
+    total_blocks: u32,
+    inode_bitmap_blocks: u32,
+    format: InodeFormat,
+  ) -> Arc<Mutex<Self>> {
+    assert!(
+      total_blocks >= Self::min_blocks(inode_bitmap_blocks),
+      "Too few blocks for the inodes!"
+    );
+    // calculate block size of areas
+    let inode_bitmap_start = 1 + JOURNAL_BLOCKS;
+    let inode_area_blocks = Self::inode_area_blocks(inode_bitmap_blocks);
     let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-    let data_total_blocks = total_blocks - 1 - inode_area_blocks;
+    let data_total_blocks =
+      total_blocks - inode_bitmap_start - inode_total_blocks;
     let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
     let data_area_blocks = data_total_blocks - data_bitmap_blocks;
-    let data_bitmap = Bitmap::new(
-      (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
-      data_bitmap_blocks as usize,
-    );
-
-    // Create EasyFileSystem instance
-    let mut efs = Self {
-      block_device: Arc::clone(&block_device),
-      inode_bitmap,
-      data_bitmap,
-      inode_area_start_block: 1 + inode_bitmap_blocks,
-      data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
-    };
 
     // clear all blocks
     for i in 0..total_blocks {
@@ -71,53 +107,249 @@ impl EasyFileSystem {
       |super_block: &mut SuperBlock| {
         super_block.initialize(
           total_blocks,
+          JOURNAL_BLOCKS,
           inode_bitmap_blocks,
           inode_area_blocks,
           data_bitmap_blocks,
           data_area_blocks,
+          format,
         );
       },
     );
     // write back immediately
+    block_cache_clear();
+    let mut efs = Self::new(
+      block_device,
+      inode_bitmap_blocks,
+      inode_area_blocks,
+      data_bitmap_blocks,
+      data_area_blocks,
+      format,
+    );
     // create a inode for root node "/"
-    assert_eq!(efs.alloc_inode(), 0);
+    assert_eq!(efs.alloc_inode(), Some(0));
     let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
-    get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
-      .lock()
-      .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-        disk_inode.initialize(DiskInodeType::Directory);
-      });
-    block_cache_sync_all();
-    Arc::new(Mutex::new(efs))
+    get_block_cache(
+      root_inode_block_id as usize,
+      Arc::clone(&efs.block_device),
+    )
+    .lock()
+    .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
+      disk_inode.initialize(DiskInodeType::Directory, format, efs.now());
+    });
+    efs.commit();
+    let efs = Arc::new(Mutex::new(efs));
+    // the parent of "/" is itself
+    let root_inode = Self::root_inode(&efs);
+    root_inode.init_root();
+    efs
   }
 
-  /// Open a block device as a filesystem
+  /// Number of blocks the inodes of `inode_bitmap_blocks` bitmap blocks
+  /// take up
+  fn inode_area_blocks(inode_bitmap_blocks: u32) -> u32 {
+    let inode_num = inode_bitmap_blocks as usize * BLOCK_SZ * 8;
+    ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ)
+      as u32
+  }
+
+  /// Get the fewest blocks a filesystem with `inode_bitmap_blocks` inode
+  /// bitmap blocks can be created on, which leave it one data block
+  pub fn min_blocks(inode_bitmap_blocks: u32) -> u32 {
+    1 + JOURNAL_BLOCKS
+      + inode_bitmap_blocks
+      + Self::inode_area_blocks(inode_bitmap_blocks)
+      + 2
+  }
+
+  /// Open a block device as a filesystem, replaying the transaction
+  /// its journal holds if the last run crashed after committing it
   pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    Self::open_with_cache(block_device, DEFAULT_CACHE_BLOCKS)
+  }
+
+  /// Open a block device as a filesystem like [`EasyFileSystem::open`],
+  /// with a block cache holding `cache_blocks` blocks
+  pub fn open_with_cache(
+    block_device: Arc<dyn BlockDevice>,
+    cache_blocks: usize,
+  ) -> Arc<Mutex<Self>> {
+    set_block_cache_capacity(cache_blocks);
     // read SuperBlock
-    get_block_cache(0, Arc::clone(&block_device)).lock().read(
-      0,
-      |super_block: &SuperBlock| {
-        assert!(super_block.is_valid(), "Error loading EFS!");
-        let inode_total_blocks =
-          super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-        let efs = Self {
-          block_device,
-          inode_bitmap: Bitmap::new(
-            1,
-            super_block.inode_bitmap_blocks as usize,
-          ),
-          data_bitmap: Bitmap::new(
-            (1 + inode_total_blocks) as usize,
-            super_block.data_bitmap_blocks as usize,
-          ),
-          inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-          data_area_start_block: 1
-            + inode_total_blocks
-            + super_block.data_bitmap_blocks,
-        };
-        Arc::new(Mutex::new(efs))
-      },
-    )
+    let super_block = get_block_cache(0, Arc::clone(&block_device))
+      .lock()
+      .read(0, |super_block: &SuperBlock| *super_block);
+    assert!(super_block.is_valid(), "Error loading EFS!");
+    assert_eq!(super_block.journal_blocks, JOURNAL_BLOCKS);
+    // blocks cached before are read through the journal from now on
+    block_cache_clear();
+    Arc::new(Mutex::new(Self::new(
+      block_device,
+      super_block.inode_bitmap_blocks,
+      super_block.inode_area_blocks,
+      super_block.data_bitmap_blocks,
+      super_block.data_area_blocks,
+      super_block.inode_format(),
+    )))
+  }
+
+  /// Build a filesystem over a formatted device given the sizes of its
+  /// areas, the blocks are all accessed through the journal.
+  fn new(
+    block_device: Arc<dyn BlockDevice>,
+    inode_bitmap_blocks: u32,
+    inode_area_blocks: u32,
+    data_bitmap_blocks: u32,
+    data_area_blocks: u32,
+    format: InodeFormat,
+  ) -> Self {
+    let inode_bitmap_start = 1 + JOURNAL_BLOCKS;
+    let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
+    let journal = Arc::new(Journal::open(block_device, 1));
+    Self {
+      block_device: journal.clone(),
+      journal,
+      inode_bitmap: Bitmap::new(
+        inode_bitmap_start as usize,
+        inode_bitmap_blocks as usize,
+        inode_bitmap_blocks as usize * BLOCK_SZ * 8,
+      ),
+      data_bitmap: Bitmap::new(
+        (inode_bitmap_start + inode_total_blocks) as usize,
+        data_bitmap_blocks as usize,
+        data_area_blocks as usize,
+      ),
+      inode_area_start_block: inode_bitmap_start + inode_bitmap_blocks,
+      data_area_start_block: inode_bitmap_start
+        + inode_total_blocks
+        + data_bitmap_blocks,
+      open_inodes: BTreeMap::new(),
+      freed: Vec::new(),
+      clock: no_clock,
+      format,
+      write_back: false,
+      noatime: false,
+    }
+  }
+
+  /// Commit the changes made since the last commit as one transaction.
+  /// Data blocks deallocated in it are only freed in the bitmap now, so
+  /// that they are not reused before the transaction is on disk.
+  ///
+  /// Freeing more blocks than the transaction has room for in the bitmap
+  /// goes on in the transactions after it, a crash in between leaks the
+  /// blocks not freed yet but leaves nothing inconsistent.
+  pub fn commit(&mut self) {
+    // counting the dirty blocks locks every cached block, so it is done
+    // once and each bitmap block first modified in a part adds one more
+    let mut used = self.journal.pending_len() + block_cache_dirty_count();
+    let mut touched = BTreeSet::new();
+    for block_id in core::mem::take(&mut self.freed) {
+      let bit = (block_id - self.data_area_start_block) as usize;
+      let bitmap_block_id = self.data_bitmap.block_id_of(bit);
+      if !touched.contains(&bitmap_block_id) {
+        if used >= JOURNAL_CAPACITY {
+          block_cache_sync_all();
+          self.journal.commit();
+          used = 0;
+          touched.clear();
+        }
+        touched.insert(bitmap_block_id);
+        used += 1;
+      }
+      self.data_bitmap.dealloc(&self.block_device, bit);
+    }
+    block_cache_sync_all();
+    self.journal.commit();
+  }
+
+  /// Let operations leave their changes in the block cache until
+  /// [`EasyFileSystem::commit`] is called, so that a burst of them is
+  /// written back as one transaction. A crash loses the operations not
+  /// committed yet but never leaves the filesystem inconsistent.
+  pub fn set_write_back(&mut self, write_back: bool) {
+    self.write_back = write_back;
+    if !write_back {
+      self.commit();
+    }
+  }
+
+  /// Finish an operation, committing it unless it can be left to a
+  /// later commit.
+  pub(crate) fn end_op(&mut self) {
+    if self.write_back {
+      self.commit_if_full();
+    } else {
+      self.commit();
+    }
+  }
+
+  /// Commit the changes made so far once they could grow past what a
+  /// transaction logs. An operation too large for one transaction calls
+  /// this between its steps, and is committed in parts that each leave
+  /// the filesystem consistent.
+  pub(crate) fn commit_if_full(&mut self) {
+    if self.journal.pending_len() + block_cache_dirty_count()
+      > JOURNAL_CAPACITY / 2
+    {
+      self.commit();
+    }
+  }
+
+  /// Get the format of the block index of the inodes
+  pub fn inode_format(&self) -> InodeFormat {
+    self.format
+  }
+
+  /// Get a copy of the super block
+  pub fn super_block(&self) -> SuperBlock {
+    get_block_cache(0, Arc::clone(&self.block_device))
+      .lock()
+      .read(0, |super_block: &SuperBlock| *super_block)
+  }
+
+  /// Get the number of inodes in use
+  pub fn used_inodes(&self) -> usize {
+    self.inode_bitmap.allocated(&self.block_device)
+  }
+
+  /// Get the number of data blocks in use
+  pub fn used_data_blocks(&self) -> usize {
+    self.data_bitmap.allocated(&self.block_device)
+  }
+
+  /// Get the number of data blocks free to allocate. Blocks deallocated
+  /// since the last commit are not free yet.
+  pub fn free_data_blocks(&self) -> usize {
+    self.data_bitmap.len() - self.used_data_blocks()
+  }
+
+  /// Whether `blocks` more data blocks can be allocated, leaving room for
+  /// the index blocks or extent tree nodes that reach them
+  pub(crate) fn has_room(&self, blocks: usize) -> bool {
+    self.free_data_blocks() >= blocks + INDEX_RESERVE_BLOCKS
+  }
+
+  /// Set the clock inode timestamps are taken from, which returns the
+  /// milliseconds since the Unix epoch
+  pub fn set_clock(&mut self, clock: fn() -> u64) {
+    self.clock = clock;
+  }
+
+  /// Get the current time of the clock
+  pub fn now(&self) -> u64 {
+    (self.clock)()
+  }
+
+  /// Stop reads from updating access times, so that reading a file
+  /// never writes to the filesystem.
+  pub fn set_noatime(&mut self, noatime: bool) {
+    self.noatime = noatime;
+  }
+
+  /// Whether reads leave the access times as they are
+  pub(crate) fn noatime(&self) -> bool {
+    self.noatime
   }
 
   /// Get the root inode of the filesystem
@@ -125,8 +357,9 @@ impl EasyFileSystem {
     let block_device = Arc::clone(&efs.lock().block_device);
     // acquire efs lock temporarily
     let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
+    efs.lock().open_inode(0);
     // release efs lock
-    Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+    Inode::new(0, block_id, block_offset, Arc::clone(efs), block_device)
   }
 
   /// Get inode by id
@@ -145,27 +378,66 @@ impl EasyFileSystem {
     self.data_area_start_block + data_block_id
   }
 
-  /// Allocate a new inode
-  pub fn alloc_inode(&mut self) -> u32 {
-    self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+  /// Count a new vfs inode of `inode_id`
+  pub(crate) fn open_inode(&mut self, inode_id: u32) {
+    *self.open_inodes.entry(inode_id).or_insert(0) += 1;
   }
 
-  /// Allocate a data block
-  pub fn alloc_data(&mut self) -> u32 {
-    self.data_bitmap.alloc(&self.block_device).unwrap() as u32
-      + self.data_area_start_block
+  /// Uncount a vfs inode of `inode_id`, return whether it was the last one
+  pub(crate) fn close_inode(&mut self, inode_id: u32) -> bool {
+    let count = self.open_inodes.get_mut(&inode_id).unwrap();
+    *count -= 1;
+    if *count == 0 {
+      self.open_inodes.remove(&inode_id);
+      true
+    } else {
+      false
+    }
   }
 
-  /// Deallocate a data block
-  pub fn dealloc_data(&mut self, block_id: u32) {
+  /// Whether some vfs inode of `inode_id` is alive
+  pub(crate) fn is_open(&self, inode_id: u32) -> bool {
+    self.open_inodes.contains_key(&inode_id)
+  }
+
+  /// Allocate a new inode, `None` if all of them are in use
+  pub fn alloc_inode(&mut self) -> Option<u32> {
+    self
+      .inode_bitmap
+      .alloc(&self.block_device)
+      .map(|inode_id| inode_id as u32)
+  }
+
+  /// Deallocate an inode
+  pub fn dealloc_inode(&mut self, inode_id: u32) {
+    self
+      .inode_bitmap
+      .dealloc(&self.block_device, inode_id as usize)
+  }
+
+  /// Allocate a data block, which is zeroed, `None` if the data area is
+  /// full
+  pub fn alloc_data(&mut self) -> Option<u32> {
+    let block_id = self.data_bitmap.alloc(&self.block_device)? as u32
+      + self.data_area_start_block;
+    // a block free on disk can be written in place
+    self.mark_data(block_id);
     get_block_cache(block_id as usize, Arc::clone(&self.block_device))
       .lock()
       .modify(0, |data_block: &mut DataBlock| {
         data_block.iter_mut().for_each(|p| *p = 0)
       });
-    self.data_bitmap.dealloc(
-      &self.block_device,
-      (block_id - self.data_area_start_block) as usize,
-    )
+    Some(block_id)
+  }
+
+  /// Deallocate a data block at the next commit
+  pub fn dealloc_data(&mut self, block_id: u32) {
+    self.freed.push(block_id);
+  }
+
+  /// Write the data block `block_id` in place instead of journaling it
+  /// until the next commit
+  pub(crate) fn mark_data(&self, block_id: u32) {
+    self.journal.mark_direct(block_id as usize);
   }
 }
@@ -0,0 +1,684 @@
+//! Consistency check of an easy-fs image
+//!
+//! [`EasyFileSystem::check`] walks the directory tree from the root and
+//! every block tree of the inodes it reaches, then cross-checks what is in
+//! use against the inode and data bitmaps. Broken extent trees are
+//! rebuilt from the extents that are sound.
+
+use alloc::{
+  collections::{btree_map::Entry, BTreeMap, VecDeque},
+  string::String,
+  sync::Arc,
+  vec::Vec,
+};
+use core::fmt::{Display, Formatter, Result};
+
+use crate::{
+  block_dev::BlockDevice, efs::EasyFileSystem, extent_nodes_needed,
+  get_block_cache, journal::JOURNAL_BLOCKS, DirEntry, DiskInode, Extent,
+  ExtentBlock, InodeFormat, SuperBlock, BLOCK_SZ,
+};
+
+type DataBlock = [u8; BLOCK_SZ];
+type IndirectBlock = [u32; BLOCK_SZ / 4];
+
+/// A problem found in an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+  /// An inode reachable from the root is free in the inode bitmap
+  FreeInodeInUse(u32),
+  /// An inode allocated in the inode bitmap is not reachable from the
+  /// root, it is freed by a repair
+  OrphanInode(u32),
+  /// A data block in use is free in the data bitmap
+  FreeBlockInUse(u32),
+  /// A data block allocated in the data bitmap is not in use
+  LeakedBlock(u32),
+  /// A data block of `inode_id` is already used elsewhere, a repair gives
+  /// the inode a copy of it
+  DuplicateBlock { inode_id: u32, block_id: u32 },
+  /// A block id of `inode_id` is out of the data area, a repair turns it
+  /// into a hole
+  BadBlock { inode_id: u32, block_id: u32 },
+  /// An inode with an invalid type or size, it is not repaired
+  BadInode(u32),
+  /// A directory entry refers to an inode out of range, it is not
+  /// repaired
+  BadDirEntry { dir_id: u32, name: String },
+  /// A directory holds a malformed entry at byte `offset`, the entries
+  /// from it on are lost and a repair cuts the directory there
+  BadDirectory { dir_id: u32, offset: u32 },
+  /// The link count of an inode differs from the number of directory
+  /// entries referring to it
+  WrongLinkCount {
+    inode_id: u32,
+    nlink: u32,
+    links: u32,
+  },
+}
+
+impl Display for FsckProblem {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+    match self {
+      Self::FreeInodeInUse(inode_id) => {
+        write!(f, "inode {} is in use but free in the bitmap", inode_id)
+      }
+      Self::OrphanInode(inode_id) => {
+        write!(f, "inode {} is allocated but unreachable", inode_id)
+      }
+      Self::FreeBlockInUse(block_id) => {
+        write!(f, "block {} is in use but free in the bitmap", block_id)
+      }
+      Self::LeakedBlock(block_id) => {
+        write!(f, "block {} is allocated but unused", block_id)
+      }
+      Self::DuplicateBlock { inode_id, block_id } => write!(
+        f,
+        "block {} of inode {} is already in use",
+        block_id, inode_id
+      ),
+      Self::BadBlock { inode_id, block_id } => write!(
+        f,
+        "block {} of inode {} is out of the data area",
+        block_id, inode_id
+      ),
+      Self::BadInode(inode_id) => {
+        write!(f, "inode {} has an invalid type or size", inode_id)
+      }
+      Self::BadDirEntry { dir_id, name } => write!(
+        f,
+        "entry {:?} of directory {} refers to no valid inode",
+        name, dir_id
+      ),
+      Self::BadDirectory { dir_id, offset } => write!(
+        f,
+        "directory {} has a malformed entry at {}",
+        dir_id, offset
+      ),
+      Self::WrongLinkCount {
+        inode_id,
+        nlink,
+        links,
+      } => write!(
+        f,
+        "inode {} has link count {} but {} links",
+        inode_id, nlink, links
+      ),
+    }
+  }
+}
+
+/// Where a block id is kept
+#[derive(Clone, Copy)]
+enum Slot {
+  /// A direct block id of an inode, followed by its `indirect1` and
+  /// `indirect2`
+  Inode(u32, usize),
+  /// An entry of an indirect block
+  Indirect(u32, usize),
+}
+
+/// What a walk over the tree finds
+#[derive(Default)]
+struct Walk {
+  /// End of the data area
+  data_area_end: u32,
+  /// Reachable inodes and whether each one is a directory
+  inodes: BTreeMap<u32, bool>,
+  /// Number of entries referring to each inode
+  links: BTreeMap<u32, u32>,
+  /// Owner of each data block in use
+  owners: BTreeMap<u32, u32>,
+  problems: Vec<FsckProblem>,
+  /// Slots holding block ids out of the data area
+  bad_slots: Vec<Slot>,
+  /// Slots holding blocks used elsewhere
+  dup_slots: Vec<Slot>,
+  /// Extent trees to rebuild
+  extent_fixes: Vec<ExtentFix>,
+}
+
+/// What a walk over the entries of a directory finds
+struct DirEntries {
+  /// Names and inode numbers of the entries
+  entries: Vec<(String, u32)>,
+  /// Offset of a malformed entry ending the sound entries
+  malformed: Option<u32>,
+}
+
+/// An extent tree to rebuild from what is sound in it
+struct ExtentFix {
+  inode_id: u32,
+  /// Data blocks mapped by inner id, and whether each one is used
+  /// elsewhere and needs a copy
+  blocks: Vec<(u32, u32, bool)>,
+  /// Nodes the tree can be kept in
+  nodes: Vec<u32>,
+  /// Whether anything in the tree is broken
+  broken: bool,
+}
+
+/// The parts of a disk inode a check needs
+struct InodeInfo {
+  is_dir: bool,
+  nlink: u32,
+  direct: Vec<u32>,
+  indirect1: u32,
+  indirect2: u32,
+  /// Depth and entries of the root of the extent tree of an inode in the
+  /// extent format
+  extent_root: Option<(u16, Vec<Extent>)>,
+}
+
+impl EasyFileSystem {
+  /// Whether the device holds a valid super block of this version with
+  /// areas fitting in it
+  pub fn is_valid_image(block_device: &Arc<dyn BlockDevice>) -> bool {
+    get_block_cache(0, Arc::clone(block_device)).lock().read(
+      0,
+      |super_block: &SuperBlock| {
+        super_block.is_valid()
+          && super_block.journal_blocks == JOURNAL_BLOCKS
+          && [
+            super_block.journal_blocks,
+            super_block.inode_bitmap_blocks,
+            super_block.inode_area_blocks,
+            super_block.data_bitmap_blocks,
+            super_block.data_area_blocks,
+          ]
+          .iter()
+          .try_fold(1u32, |sum, &blocks| sum.checked_add(blocks))
+          .map_or(false, |sum| sum <= super_block.total_blocks)
+      },
+    )
+  }
+
+  /// Check the filesystem and return the problems found. With `repair`
+  /// set the problems that can be are repaired and committed, check
+  /// again to see what is left.
+  ///
+  /// No vfs inode of the filesystem may be alive.
+  pub fn check(&mut self, repair: bool) -> Vec<FsckProblem> {
+    let data_area_blocks = get_block_cache(0, Arc::clone(&self.block_device))
+      .lock()
+      .read(0, |super_block: &SuperBlock| super_block.data_area_blocks);
+    let max_inodes = self.inode_bitmap.maximum() as u32;
+    let mut walk = Walk {
+      data_area_end: self.data_area_start_block + data_area_blocks,
+      ..Walk::default()
+    };
+    let mut queue = VecDeque::from([0u32]);
+    walk.inodes.insert(0, true);
+    while let Some(inode_id) = queue.pop_front() {
+      let info = match self.inode_info(inode_id) {
+        Some(info) => info,
+        None => {
+          walk.problems.push(FsckProblem::BadInode(inode_id));
+          continue;
+        }
+      };
+      walk.inodes.insert(inode_id, info.is_dir);
+      let bad_slots = walk.bad_slots.len();
+      let mut sound = true;
+      if let Some((depth, entries)) = info.extent_root {
+        let mut fix = ExtentFix {
+          inode_id,
+          blocks: Vec::new(),
+          nodes: Vec::new(),
+          broken: false,
+        };
+        self.claim_extents(&mut walk, &mut fix, depth, &entries);
+        if fix.broken {
+          sound = false;
+          walk.extent_fixes.push(fix);
+        }
+      } else {
+        for (i, &block_id) in info.direct.iter().enumerate() {
+          let slot = Slot::Inode(inode_id, i);
+          self.claim(&mut walk, inode_id, slot, block_id, 0);
+        }
+        let count = info.direct.len();
+        for (i, block_id, depth) in
+          [(count, info.indirect1, 1), (count + 1, info.indirect2, 2)]
+        {
+          let slot = Slot::Inode(inode_id, i);
+          self.claim(&mut walk, inode_id, slot, block_id, depth);
+        }
+      }
+      // entries are only read through a sound block tree
+      if !info.is_dir || !sound || walk.bad_slots.len() != bad_slots {
+        continue;
+      }
+      let dir = self.dir_entries(inode_id);
+      if let Some(offset) = dir.malformed {
+        walk.problems.push(FsckProblem::BadDirectory {
+          dir_id: inode_id,
+          offset,
+        });
+      }
+      for (name, entry_id) in dir.entries {
+        if name == "." || name == ".." {
+          continue;
+        }
+        if entry_id >= max_inodes {
+          walk.problems.push(FsckProblem::BadDirEntry {
+            dir_id: inode_id,
+            name,
+          });
+          continue;
+        }
+        *walk.links.entry(entry_id).or_insert(0) += 1;
+        if let Entry::Vacant(entry) = walk.inodes.entry(entry_id) {
+          entry.insert(false);
+          queue.push_back(entry_id);
+        }
+      }
+    }
+    // link counts, a directory always has one
+    let mut nlink_fixes = Vec::new();
+    for (&inode_id, &is_dir) in walk.inodes.iter() {
+      let links = if is_dir {
+        1
+      } else {
+        walk.links.get(&inode_id).copied().unwrap_or(0)
+      };
+      let nlink = match self.inode_info(inode_id) {
+        Some(info) => info.nlink,
+        None => continue,
+      };
+      if nlink != links {
+        walk.problems.push(FsckProblem::WrongLinkCount {
+          inode_id,
+          nlink,
+          links,
+        });
+        nlink_fixes.push((inode_id, links));
+      }
+    }
+    // bitmaps against what is in use
+    let mut inode_fixes = Vec::new();
+    for inode_id in 0..max_inodes {
+      let allocated =
+        self.inode_bitmap.get(&self.block_device, inode_id as usize);
+      let used = walk.inodes.contains_key(&inode_id);
+      if allocated && !used {
+        walk.problems.push(FsckProblem::OrphanInode(inode_id));
+      } else if !allocated && used {
+        walk.problems.push(FsckProblem::FreeInodeInUse(inode_id));
+      } else {
+        continue;
+      }
+      inode_fixes.push((inode_id, used));
+    }
+    let mut block_fixes = Vec::new();
+    for bit in 0..data_area_blocks {
+      let block_id = self.data_area_start_block + bit;
+      let allocated = self.data_bitmap.get(&self.block_device, bit as usize);
+      let used = walk.owners.contains_key(&block_id);
+      if allocated && !used {
+        walk.problems.push(FsckProblem::LeakedBlock(block_id));
+      } else if !allocated && used {
+        walk.problems.push(FsckProblem::FreeBlockInUse(block_id));
+      } else {
+        continue;
+      }
+      block_fixes.push((bit, used));
+    }
+    if repair {
+      // a large repair is committed in parts, running it again finishes
+      // one a crash cut short
+      for &(inode_id, used) in inode_fixes.iter() {
+        self
+          .inode_bitmap
+          .set(&self.block_device, inode_id as usize, used);
+        self.commit_if_full();
+      }
+      // blocks in use are marked before copies are allocated
+      for &(bit, used) in block_fixes.iter().filter(|(_, used)| *used) {
+        self.data_bitmap.set(&self.block_device, bit as usize, used);
+        self.commit_if_full();
+      }
+      for &slot in walk.bad_slots.iter() {
+        self.set_slot(slot, 0);
+        self.commit_if_full();
+      }
+      for &slot in walk.dup_slots.iter() {
+        // without room for a copy the block is left as a hole
+        let copy = self.copy_block(self.get_slot(slot)).unwrap_or(0);
+        self.set_slot(slot, copy);
+        self.commit_if_full();
+      }
+      for fix in walk.extent_fixes {
+        self.rebuild_extents(fix);
+        self.commit_if_full();
+      }
+      for problem in walk.problems.iter() {
+        if let FsckProblem::BadDirectory { dir_id, offset } = *problem {
+          self.cut_dir(dir_id, offset);
+          self.commit_if_full();
+        }
+      }
+      for &(bit, used) in block_fixes.iter().filter(|(_, used)| !*used) {
+        self.data_bitmap.set(&self.block_device, bit as usize, used);
+        self.commit_if_full();
+      }
+      for &(inode_id, links) in nlink_fixes.iter() {
+        let (block_id, offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .modify(offset, |disk_inode: &mut DiskInode| {
+            disk_inode.nlink = links;
+          });
+        self.commit_if_full();
+      }
+      self.commit();
+    }
+    walk.problems
+  }
+
+  /// Read the disk inode `inode_id`, `None` if its type or size is
+  /// invalid
+  fn inode_info(&self, inode_id: u32) -> Option<InodeInfo> {
+    let (block_id, offset) = self.get_disk_inode_pos(inode_id);
+    let cache =
+      get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+    let cache = cache.lock();
+    let valid = cache.read(0, |data_block: &DataBlock| {
+      DiskInode::raw_is_valid(&data_block[offset..])
+    });
+    if !valid {
+      return None;
+    }
+    cache.read(offset, |disk_inode: &DiskInode| {
+      let size = disk_inode.size;
+      if size as usize > disk_inode.max_size() {
+        return None;
+      }
+      let extent_root = match disk_inode.format() {
+        InodeFormat::Indirect => None,
+        InodeFormat::Extent => {
+          let root = disk_inode.extent_root();
+          Some((root.depth(), root.entries()?.to_vec()))
+        }
+      };
+      Some(InodeInfo {
+        is_dir: disk_inode.is_dir(),
+        nlink: disk_inode.nlink,
+        direct: disk_inode.direct.to_vec(),
+        indirect1: disk_inode.indirect1,
+        indirect2: disk_inode.indirect2,
+        extent_root,
+      })
+    })
+  }
+
+  /// Read the entries of the directory `inode_id` up to its end or the
+  /// first malformed entry
+  fn dir_entries(&self, inode_id: u32) -> DirEntries {
+    let (block_id, offset) = self.get_disk_inode_pos(inode_id);
+    get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+      .lock()
+      .read(offset, |disk_inode: &DiskInode| {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while let Some((dirent, rec_len)) =
+          DirEntry::read_from(disk_inode, offset, &self.block_device)
+        {
+          if let Some(dirent) = dirent {
+            entries.push((String::from(dirent.name()), dirent.inode_number()));
+          }
+          offset += rec_len;
+        }
+        let malformed =
+          (offset != disk_inode.size as usize).then_some(offset as u32);
+        DirEntries { entries, malformed }
+      })
+  }
+
+  /// Claim `block_id` kept in `slot` for `inode_id`, and the blocks it
+  /// refers to if it is an indirect block of `depth` levels
+  fn claim(
+    &self,
+    walk: &mut Walk,
+    inode_id: u32,
+    slot: Slot,
+    block_id: u32,
+    depth: usize,
+  ) {
+    if block_id == 0 {
+      return;
+    }
+    if block_id < self.data_area_start_block || block_id >= walk.data_area_end {
+      walk
+        .problems
+        .push(FsckProblem::BadBlock { inode_id, block_id });
+      walk.bad_slots.push(slot);
+      return;
+    }
+    if walk.owners.contains_key(&block_id) {
+      walk
+        .problems
+        .push(FsckProblem::DuplicateBlock { inode_id, block_id });
+      walk.dup_slots.push(slot);
+      return;
+    }
+    walk.owners.insert(block_id, inode_id);
+    if depth == 0 {
+      return;
+    }
+    let entries =
+      get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+        .lock()
+        .read(0, |indirect: &IndirectBlock| *indirect);
+    for (i, &entry) in entries.iter().enumerate() {
+      let slot = Slot::Indirect(block_id, i);
+      self.claim(walk, inode_id, slot, entry, depth - 1);
+    }
+  }
+
+  /// Claim the blocks of an extent tree under a node of `depth` holding
+  /// `entries` for `fix`, recording the data blocks and nodes that are
+  /// sound. A node that is out of the data area, used elsewhere or not
+  /// a node a level down is dropped with the blocks under it.
+  fn claim_extents(
+    &self,
+    walk: &mut Walk,
+    fix: &mut ExtentFix,
+    depth: u16,
+    entries: &[Extent],
+  ) {
+    let inode_id = fix.inode_id;
+    let data_area_end = walk.data_area_end;
+    let in_data_area = |block_id: u32, len: u32| {
+      block_id >= self.data_area_start_block
+        && block_id
+          .checked_add(len)
+          .map_or(false, |end| end <= data_area_end)
+    };
+    for extent in entries {
+      let block_id = extent.start;
+      if depth == 0 {
+        if !in_data_area(block_id, extent.len) {
+          walk
+            .problems
+            .push(FsckProblem::BadBlock { inode_id, block_id });
+          fix.broken = true;
+          continue;
+        }
+        for i in 0..extent.len {
+          let block_id = block_id + i;
+          let dup = walk.owners.contains_key(&block_id);
+          if dup {
+            walk
+              .problems
+              .push(FsckProblem::DuplicateBlock { inode_id, block_id });
+            fix.broken = true;
+          } else {
+            walk.owners.insert(block_id, inode_id);
+          }
+          fix.blocks.push((extent.first + i, block_id, dup));
+        }
+        continue;
+      }
+      if !in_data_area(block_id, 1) {
+        walk
+          .problems
+          .push(FsckProblem::BadBlock { inode_id, block_id });
+        fix.broken = true;
+        continue;
+      }
+      if walk.owners.contains_key(&block_id) {
+        walk
+          .problems
+          .push(FsckProblem::DuplicateBlock { inode_id, block_id });
+        fix.broken = true;
+        continue;
+      }
+      let node =
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .read(0, |node: &ExtentBlock| {
+            Some((node.depth(), node.entries()?.to_vec()))
+          });
+      match node {
+        Some((child_depth, children)) if child_depth + 1 == depth => {
+          walk.owners.insert(block_id, inode_id);
+          fix.nodes.push(block_id);
+          self.claim_extents(walk, fix, child_depth, &children);
+        }
+        _ => {
+          walk
+            .problems
+            .push(FsckProblem::BadBlock { inode_id, block_id });
+          fix.broken = true;
+        }
+      }
+    }
+  }
+
+  /// Rebuild the extent tree of `fix` from its sound blocks, copying the
+  /// ones used elsewhere
+  fn rebuild_extents(&mut self, mut fix: ExtentFix) {
+    fix
+      .blocks
+      .sort_unstable_by_key(|&(inner_id, _, _)| inner_id);
+    fix.blocks.dedup_by_key(|&mut (inner_id, _, _)| inner_id);
+    let mut extents = Vec::new();
+    for (inner_id, block_id, dup) in fix.blocks {
+      // without room for a copy the block is left as a hole
+      let start = if dup {
+        match self.copy_block(block_id) {
+          Some(copy) => copy,
+          None => continue,
+        }
+      } else {
+        block_id
+      };
+      Extent {
+        first: inner_id,
+        len: 1,
+        start,
+      }
+      .push_to(&mut extents);
+    }
+    let mut nodes = fix.nodes;
+    while nodes.len() < extent_nodes_needed(extents.len()) {
+      // without room for the nodes the last extents are dropped, their
+      // blocks are reclaimed by the next check
+      match self.alloc_data() {
+        Some(node) => nodes.push(node),
+        None => {
+          extents.pop();
+        }
+      }
+    }
+    let (block_id, offset) = self.get_disk_inode_pos(fix.inode_id);
+    let unused =
+      get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+        .lock()
+        .modify(offset, |disk_inode: &mut DiskInode| {
+          disk_inode.set_extents(extents, nodes, &self.block_device, || {
+            unreachable!("enough nodes are allocated")
+          })
+        });
+    for block_id in unused {
+      self.dealloc_data(block_id);
+    }
+  }
+
+  /// Cut the directory `dir_id` at byte `offset`
+  fn cut_dir(&mut self, dir_id: u32, offset: u32) {
+    let (block_id, block_offset) = self.get_disk_inode_pos(dir_id);
+    let unused =
+      get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+        .lock()
+        .modify(block_offset, |disk_inode: &mut DiskInode| {
+          disk_inode.decrease_size(offset, &self.block_device)
+        });
+    for block_id in unused {
+      self.dealloc_data(block_id);
+    }
+  }
+
+  /// Copy the data block `block_id` to a new block and return it, `None`
+  /// if the data area is full
+  fn copy_block(&mut self, block_id: u32) -> Option<u32> {
+    let data =
+      get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+        .lock()
+        .read(0, |data_block: &DataBlock| *data_block);
+    let copy = self.alloc_data()?;
+    get_block_cache(copy as usize, Arc::clone(&self.block_device))
+      .lock()
+      .modify(0, |data_block: &mut DataBlock| *data_block = data);
+    Some(copy)
+  }
+
+  /// Get the block id kept in `slot`
+  fn get_slot(&self, slot: Slot) -> u32 {
+    match slot {
+      Slot::Inode(inode_id, i) => {
+        let (block_id, offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .read(offset, |disk_inode: &DiskInode| {
+            let count = disk_inode.direct.len();
+            match i {
+              i if i < count => disk_inode.direct[i],
+              i if i == count => disk_inode.indirect1,
+              _ => disk_inode.indirect2,
+            }
+          })
+      }
+      Slot::Indirect(block_id, i) => {
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .read(0, |indirect: &IndirectBlock| indirect[i])
+      }
+    }
+  }
+
+  /// Keep `block_id` in `slot`
+  fn set_slot(&self, slot: Slot, block_id: u32) {
+    match slot {
+      Slot::Inode(inode_id, i) => {
+        let (inode_block_id, offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(inode_block_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .modify(offset, |disk_inode: &mut DiskInode| {
+            let count = disk_inode.direct.len();
+            match i {
+              i if i < count => disk_inode.direct[i] = block_id,
+              i if i == count => disk_inode.indirect1 = block_id,
+              _ => disk_inode.indirect2 = block_id,
+            }
+          })
+      }
+      Slot::Indirect(indirect_id, i) => {
+        get_block_cache(indirect_id as usize, Arc::clone(&self.block_device))
+          .lock()
+          .modify(0, |indirect: &mut IndirectBlock| indirect[i] = block_id)
+      }
+    }
+  }
+}
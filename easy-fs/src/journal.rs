@@ -0,0 +1,153 @@
+//! Write-ahead journal of metadata blocks
+//!
+//! [`Journal`] sits between the block cache and the real device. Blocks
+//! written to it are held back in memory until [`Journal::commit`], which
+//! logs them to the journal region, marks the transaction committed with a
+//! single header block write, and only then writes them to their homes.
+//! A crash before the header write loses the whole transaction, and a crash
+//! after it is repaired by replaying the log when the journal is opened.
+//!
+//! ```text
+//! +--------+---------+---------+-----+
+//! | header | block 0 | block 1 | ... |
+//! +--------+---------+---------+-----+
+//! ```
+//!
+//! File data is not journaled. Blocks marked by [`Journal::mark_direct`]
+//! go straight to the device, which is safe for blocks free on disk and
+//! gives the usual ordered guarantee for file data: it reaches the device
+//! before the metadata that refers to it is committed.
+
+use alloc::{
+  collections::{BTreeMap, BTreeSet},
+  sync::Arc,
+  vec::Vec,
+};
+use spin::Mutex;
+
+use crate::{block_dev::BlockDevice, BLOCK_SZ};
+
+/// Magic number of a journal header
+const JOURNAL_MAGIC: u32 = 0x4a4f5552;
+/// Number of blocks in the journal region, a header and the logged blocks
+pub const JOURNAL_BLOCKS: u32 = 64;
+/// The max number of blocks a transaction can log
+pub const JOURNAL_CAPACITY: usize = JOURNAL_BLOCKS as usize - 1;
+
+/// A block device whose writes are grouped into transactions
+pub struct Journal {
+  /// Real device
+  device: Arc<dyn BlockDevice>,
+  /// Block id of the journal header
+  start: usize,
+  inner: Mutex<JournalInner>,
+}
+
+struct JournalInner {
+  /// Blocks written in the current transaction by block id
+  pending: BTreeMap<usize, Vec<u8>>,
+  /// Blocks written to the device directly in the current transaction
+  direct: BTreeSet<usize>,
+}
+
+impl Journal {
+  /// Open the journal region starting at block `start` of `device`, a
+  /// committed transaction left there by a crash is replayed.
+  pub fn open(device: Arc<dyn BlockDevice>, start: usize) -> Self {
+    let journal = Self {
+      device,
+      start,
+      inner: Mutex::new(JournalInner {
+        pending: BTreeMap::new(),
+        direct: BTreeSet::new(),
+      }),
+    };
+    journal.replay();
+    journal
+  }
+
+  /// Let writes to `block_id` bypass the journal until the next commit
+  pub fn mark_direct(&self, block_id: usize) {
+    self.inner.lock().direct.insert(block_id);
+  }
+
+  /// Get the number of blocks the current transaction logs so far
+  pub fn pending_len(&self) -> usize {
+    self.inner.lock().pending.len()
+  }
+
+  /// Commit the blocks written since the last commit
+  pub fn commit(&self) {
+    let mut inner = self.inner.lock();
+    inner.direct.clear();
+    let pending = core::mem::take(&mut inner.pending);
+    if pending.is_empty() {
+      return;
+    }
+    // operations commit in parts before they outgrow the journal
+    assert!(
+      pending.len() <= JOURNAL_CAPACITY,
+      "Transaction too large for the journal!"
+    );
+    for (i, data) in pending.values().enumerate() {
+      self.device.write_block(self.start + 1 + i, data);
+    }
+    let block_ids: Vec<usize> = pending.keys().copied().collect();
+    self.write_header(&block_ids);
+    for (&block_id, data) in pending.iter() {
+      self.device.write_block(block_id, data);
+    }
+    self.write_header(&[]);
+  }
+
+  /// Write the header listing the homes of the logged blocks, an empty
+  /// list means there is nothing to replay.
+  fn write_header(&self, block_ids: &[usize]) {
+    let mut header = [0u8; BLOCK_SZ];
+    header[0..4].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+    header[4..8].copy_from_slice(&(block_ids.len() as u32).to_le_bytes());
+    for (i, &block_id) in block_ids.iter().enumerate() {
+      header[8 + i * 4..12 + i * 4]
+        .copy_from_slice(&(block_id as u32).to_le_bytes());
+    }
+    self.device.write_block(self.start, &header);
+  }
+
+  /// Copy the logged blocks of a committed transaction to their homes
+  fn replay(&self) {
+    let mut header = [0u8; BLOCK_SZ];
+    self.device.read_block(self.start, &mut header);
+    let word = |i: usize| {
+      u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap())
+    };
+    let count = word(1) as usize;
+    if word(0) != JOURNAL_MAGIC || count == 0 || count > JOURNAL_CAPACITY {
+      return;
+    }
+    let mut data = [0u8; BLOCK_SZ];
+    for i in 0..count {
+      self.device.read_block(self.start + 1 + i, &mut data);
+      self.device.write_block(word(2 + i) as usize, &data);
+    }
+    self.write_header(&[]);
+  }
+}
+
+impl BlockDevice for Journal {
+  fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    match self.inner.lock().pending.get(&block_id) {
+      Some(data) => buf.copy_from_slice(data),
+      None => self.device.read_block(block_id, buf),
+    }
+  }
+
+  fn write_block(&self, block_id: usize, buf: &[u8]) {
+    let mut inner = self.inner.lock();
+    if inner.direct.contains(&block_id) {
+      inner.pending.remove(&block_id);
+      self.device.write_block(block_id, buf);
+    } else {
+      inner.pending.insert(block_id, buf.to_vec());
+    }
+  }
+}
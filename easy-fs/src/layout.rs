@@ -1,15 +1,20 @@
 //! ### `easy-fs` disk layout overview
 //!
 //! In the easy-fs disk layout,
-//! it is divided into 5 contiguous areas with different properties
+//! it is divided into 6 contiguous areas with different properties
 //! in descending order of block number:
-//! ```
+//! ```text
 //! +--------------------------------------------------+
 //! |                  easy-fs Super Block             |
 //! |                                                  |
 //! |  - Magic number                                  |
 //! |  - Location of other contiguous regions          |
 //! +--------------------------------------------------+
+//! |                  Journal                         |
+//! |  (Length in multiple blocks)                     |
+//! |                                                  |
+//! |  - Logs metadata blocks of the last transaction  |
+//! +--------------------------------------------------+
 //! |                  Inode Bitmap                    |
 //! |  (Length in multiple blocks)                     |
 //! |                                                  |
@@ -35,16 +40,19 @@
 //! In logical directory tree, each file and directory are correspond a `inode`.
 
 use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
+use core::mem::MaybeUninit;
+use core::ptr::addr_of;
 
 /// Magic number for sanity check
-const EFS_MAGIC: u32 = 0x3b800001;
+const EFS_MAGIC: u32 = 0x3b800005;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 28;
-/// The max length of inode name
-const NAME_LENGTH_LIMIT: usize = 27;
+const INODE_DIRECT_COUNT: usize = 18;
+/// The max length of inode name in bytes
+pub const NAME_LENGTH_LIMIT: usize = 255;
 /// The max number of indirect1 inodes
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 /// The max number of indirect2 inodes
@@ -55,13 +63,26 @@ const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 /// The upper bound of indirect1 inode index
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 /// The upper bound of indirect2 inode indexs
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The max size of a file in bytes
+pub const MAX_FILE_SIZE: usize = INDIRECT2_BOUND * BLOCK_SZ;
+/// Number of words of the block index of a disk inode
+const INODE_INDEX_WORDS: usize = INODE_DIRECT_COUNT + 2;
+/// The max number of extents in the root node kept in a disk inode
+const ROOT_EXTENT_COUNT: usize =
+  (INODE_INDEX_WORDS * 4 - 4) / core::mem::size_of::<Extent>();
+/// The max number of extents in a node kept in a block
+const BLOCK_EXTENT_COUNT: usize =
+  (BLOCK_SZ - 4) / core::mem::size_of::<Extent>();
+/// The max size of a file indexed by extents in bytes, whose last block
+/// is still counted by a `u32` size
+pub const MAX_EXTENT_FILE_SIZE: usize = u32::MAX as usize + 1 - BLOCK_SZ;
 
 /// Super block of a filesystem
 ///
 /// It is a data structure of disk, storing at the bit number of 0 of disk.
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SuperBlock {
   /// file system legitimacy verification.
   magic: u32,
@@ -69,54 +90,87 @@ pub struct SuperBlock {
   pub total_blocks: u32,
 
   // the number of blocks each area contained.
+  pub journal_blocks: u32,
   pub inode_bitmap_blocks: u32,
   pub inode_area_blocks: u32,
   pub data_bitmap_blocks: u32,
   pub data_area_blocks: u32,
+  /// `InodeFormat` of the inodes, images made before it was added have
+  /// zero here and so the indirect format
+  format: u32,
 }
 
 impl Debug for SuperBlock {
   fn fmt(&self, f: &mut Formatter<'_>) -> Result {
     f.debug_struct("SuperBlock")
       .field("total_blocks", &self.total_blocks)
+      .field("journal_blocks", &self.journal_blocks)
       .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
       .field("inode_area_blocks", &self.inode_area_blocks)
       .field("data_bitmap_blocks", &self.data_bitmap_blocks)
       .field("data_area_blocks", &self.data_area_blocks)
+      .field("format", &self.inode_format())
       .finish()
   }
 }
 
 impl SuperBlock {
   /// Initialize a super block
+  #[allow(clippy::too_many_arguments)]
   pub fn initialize(
     &mut self,
     total_blocks: u32,
+    journal_blocks: u32,
     inode_bitmap_blocks: u32,
     inode_area_blocks: u32,
     data_bitmap_blocks: u32,
     data_area_blocks: u32,
+    format: InodeFormat,
   ) {
     *self = Self {
       magic: EFS_MAGIC,
       total_blocks,
+      journal_blocks,
       inode_bitmap_blocks,
       inode_area_blocks,
       data_bitmap_blocks,
       data_area_blocks,
+      format: format as u32,
     }
   }
 
-  /// Check if a super block is valid using efs magic
+  /// Check if a super block is valid using efs magic and of a known format
   pub fn is_valid(&self) -> bool {
-    self.magic == EFS_MAGIC
+    self.magic == EFS_MAGIC && self.format <= InodeFormat::Extent as u32
+  }
+
+  /// Get the format of the inodes of the filesystem
+  pub fn inode_format(&self) -> InodeFormat {
+    match self.format {
+      0 => InodeFormat::Indirect,
+      _ => InodeFormat::Extent,
+    }
   }
 }
+
+/// Layout of the index of the data blocks of an inode, chosen for all
+/// inodes when a filesystem is created
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InodeFormat {
+  /// `direct` block ids, then an `indirect1` and an `indirect2` block
+  Indirect,
+  /// The root of a tree of extents, each one a run of contiguous blocks
+  Extent,
+}
+
 /// Type of a disk inode
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DiskInodeType {
   File,
   Directory,
+  /// A symbolic link, its data is the target path
+  SymLink,
 }
 
 /// A indirect block
@@ -124,11 +178,107 @@ type IndirectBlock = [u32; BLOCK_SZ / 4];
 /// A data block
 type DataBlock = [u8; BLOCK_SZ];
 
+/// A run of `len` data blocks from `start` holding the blocks of a file
+/// from inner id `first` on. In an index node, `start` is instead the
+/// node holding the extents from `first` on and `len` is unused.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Extent {
+  pub first: u32,
+  pub len: u32,
+  pub start: u32,
+}
+
+impl Extent {
+  /// Append this extent to `extents` sorted by `first`, merging it into
+  /// the last one if it continues that both in the file and on disk.
+  pub(crate) fn push_to(self, extents: &mut Vec<Extent>) {
+    match extents.last_mut() {
+      Some(last)
+        if last.first + last.len == self.first
+          && last.start + last.len == self.start =>
+      {
+        last.len += self.len
+      }
+      _ => extents.push(self),
+    }
+  }
+}
+
+/// A node of an extent tree, the leaves at depth 0 hold extents of data
+/// and the others index the nodes a level down.
+#[repr(C)]
+pub(crate) struct ExtentNode<const N: usize> {
+  depth: u16,
+  count: u16,
+  entries: [Extent; N],
+}
+
+/// Get the number of nodes kept in blocks a tree of `count` extents has
+pub(crate) fn extent_nodes_needed(mut count: usize) -> usize {
+  let mut nodes = 0;
+  while count > ROOT_EXTENT_COUNT {
+    count = (count + BLOCK_EXTENT_COUNT - 1) / BLOCK_EXTENT_COUNT;
+    nodes += count;
+  }
+  nodes
+}
+
+/// The root node of an extent tree, kept in the block index of an inode
+type ExtentRoot = ExtentNode<ROOT_EXTENT_COUNT>;
+/// A node of an extent tree kept in a block
+pub(crate) type ExtentBlock = ExtentNode<BLOCK_EXTENT_COUNT>;
+
+impl<const N: usize> ExtentNode<N> {
+  /// Get the depth of the node, 0 for a leaf
+  pub(crate) fn depth(&self) -> u16 {
+    self.depth
+  }
+
+  /// Get the entries in use, `None` if the count is corrupted
+  pub(crate) fn entries(&self) -> Option<&[Extent]> {
+    self.entries.get(..self.count as usize)
+  }
+
+  /// Get the last entry starting at or before `inner_id`
+  fn find(&self, inner_id: u32) -> Option<Extent> {
+    self
+      .entries()?
+      .iter()
+      .rev()
+      .find(|extent| extent.first <= inner_id)
+      .copied()
+  }
+
+  /// Make this a node of `depth` holding `entries`
+  fn set(&mut self, depth: u16, entries: &[Extent]) {
+    self.depth = depth;
+    self.count = entries.len() as u16;
+    self.entries[..entries.len()].copy_from_slice(entries);
+    self.entries[entries.len()..].fill(Extent::default());
+  }
+}
+
 /// A disk inode
 #[repr(C)]
 pub struct DiskInode {
   /// The total bytes of file/directory
   pub size: u32,
+  /// The number of directory entries referring to this inode, `.` and
+  /// `..` are not counted so a directory always has one.
+  pub nlink: u32,
+  /// Time of the last access in milliseconds since the Unix epoch
+  pub atime: u64,
+  /// Time of the last change of the data
+  pub mtime: u64,
+  /// Time of the last change of the data or the inode itself
+  pub ctime: u64,
+  /// Permission bits, e.g. `0o644`
+  pub mode: u32,
+  /// Owner user id
+  pub uid: u32,
+  /// Owner group id
+  pub gid: u32,
   // --- Index of the data block of the directory/files contents
   pub direct: [u32; INODE_DIRECT_COUNT],
   pub indirect1: u32,
@@ -136,17 +286,53 @@ pub struct DiskInode {
   // ---
   /// Index type (file/directory)
   type_: DiskInodeType,
+  /// How `direct`, `indirect1` and `indirect2` index the data blocks,
+  /// the words of all of them form the root of the extent tree if the
+  /// inode is in the extent format.
+  format: InodeFormat,
 }
 
 impl DiskInode {
   /// Initialize a disk inode, as well as all direct inodes under it
   /// indirect1 and indirect2 block are allocated only when they are needed
-  pub fn initialize(&mut self, type_: DiskInodeType) {
+  /// It starts with the one link it is created with, owned by root with
+  /// the default permissions of its type.
+  pub fn initialize(
+    &mut self,
+    type_: DiskInodeType,
+    format: InodeFormat,
+    now: u64,
+  ) {
     self.size = 0;
+    self.nlink = 1;
+    self.atime = now;
+    self.mtime = now;
+    self.ctime = now;
+    self.mode = match type_ {
+      DiskInodeType::File => 0o644,
+      DiskInodeType::Directory => 0o755,
+      DiskInodeType::SymLink => 0o777,
+    };
+    self.uid = 0;
+    self.gid = 0;
     self.direct.iter_mut().for_each(|v| *v = 0);
     self.indirect1 = 0;
     self.indirect2 = 0;
     self.type_ = type_;
+    self.format = format;
+  }
+
+  /// Whether `raw`, the bytes of a disk inode from a possibly corrupted
+  /// image, holds a valid type and format. It must be checked before the
+  /// bytes are read as a `DiskInode`.
+  pub fn raw_is_valid(raw: &[u8]) -> bool {
+    let inode = MaybeUninit::<DiskInode>::uninit();
+    let base = inode.as_ptr();
+    let offset_of = |field: *const u8| field as usize - base as usize;
+    let type_ = offset_of(unsafe { addr_of!((*base).type_) } as *const u8);
+    let format = offset_of(unsafe { addr_of!((*base).format) } as *const u8);
+    raw[type_] <= DiskInodeType::SymLink as u8
+      && raw[format] <= InodeFormat::Extent as u8
   }
 
   /// Whether this inode is a directory
@@ -154,61 +340,85 @@ impl DiskInode {
     self.type_ == DiskInodeType::Directory
   }
 
+  /// Get the type of this inode
+  pub fn type_(&self) -> DiskInodeType {
+    self.type_
+  }
+
+  /// Record a change of the data at `now`
+  pub fn touch(&mut self, now: u64) {
+    self.mtime = now;
+    self.ctime = now;
+  }
+
   /// Whether this inode is a file
   #[allow(unused)]
   pub fn is_file(&self) -> bool {
     self.type_ == DiskInodeType::File
   }
 
-  /// Return block number correspond to size.
-  pub fn data_blocks(&self) -> u32 {
-    Self::_data_blocks(self.size)
+  /// Whether this inode is a symbolic link
+  pub fn is_symlink(&self) -> bool {
+    self.type_ == DiskInodeType::SymLink
   }
 
-  fn _data_blocks(size: u32) -> u32 {
-    (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
+  /// Get the format of the block index of this inode
+  pub fn format(&self) -> InodeFormat {
+    self.format
   }
 
-  /// Return number of blocks needed include indirect1/2.
-  pub fn total_blocks(size: u32) -> u32 {
-    let data_blocks = Self::_data_blocks(size) as usize;
-    let mut total = data_blocks;
-    // indirect1
-    if data_blocks > INODE_DIRECT_COUNT {
-      total += 1;
+  /// Get the max size of this inode in bytes
+  pub fn max_size(&self) -> usize {
+    match self.format {
+      InodeFormat::Indirect => MAX_FILE_SIZE,
+      InodeFormat::Extent => MAX_EXTENT_FILE_SIZE,
     }
-    // indirect2
-    if data_blocks > INDIRECT1_BOUND {
-      total += 1;
-      // sub indirect1
-      total += (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1)
-        / INODE_INDIRECT1_COUNT;
-    }
-    total as u32
   }
 
-  /// Get the number of data blocks that have to be allocated given the new size of data
-  pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-    assert!(new_size >= self.size);
-    Self::total_blocks(new_size) - Self::total_blocks(self.size)
+  /// Get the root of the extent tree of an inode in the extent format
+  pub(crate) fn extent_root(&self) -> &ExtentRoot {
+    // `direct`, `indirect1` and `indirect2` are contiguous words
+    unsafe { &*(self.direct.as_ptr() as usize as *const ExtentRoot) }
+  }
+
+  fn extent_root_mut(&mut self) -> &mut ExtentRoot {
+    unsafe { &mut *(self.direct.as_mut_ptr() as usize as *mut ExtentRoot) }
   }
 
-  /// Get id of block given inner id
+  /// Return block number correspond to size.
+  pub fn data_blocks(&self) -> u32 {
+    Self::_data_blocks(self.size)
+  }
+
+  fn _data_blocks(size: u32) -> u32 {
+    (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
+  }
+
+  /// Get id of block given inner id, `0` means the block is a hole.
   pub fn get_block_id(
     &self,
     inner_id: u32,
     block_device: &Arc<dyn BlockDevice>,
   ) -> u32 {
+    if self.format == InodeFormat::Extent {
+      return self.extent_block_id(inner_id, block_device);
+    }
     let inner_id = inner_id as usize;
     if inner_id < INODE_DIRECT_COUNT {
       self.direct[inner_id]
     } else if inner_id < INDIRECT1_BOUND {
+      if self.indirect1 == 0 {
+        return 0;
+      }
       get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
         .lock()
         .read(0, |indirect_block: &IndirectBlock| {
           indirect_block[inner_id - INODE_DIRECT_COUNT]
         })
     } else {
+      if self.indirect2 == 0 {
+        return 0;
+      }
       let last = inner_id - INDIRECT1_BOUND;
       let indirect1 =
         get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
@@ -216,6 +426,9 @@ impl DiskInode {
           .read(0, |indirect2: &IndirectBlock| {
             indirect2[last / INODE_INDIRECT1_COUNT]
           });
+      if indirect1 == 0 {
+        return 0;
+      }
       get_block_cache(indirect1 as usize, Arc::clone(block_device))
         .lock()
         .read(0, |indirect1: &IndirectBlock| {
@@ -224,157 +437,325 @@ impl DiskInode {
     }
   }
 
-  /// Increase the size of current disk inode
-  pub fn increase_size(
+  /// Allocate the holes among data blocks `[start, end)` with `alloc`,
+  /// together with the indirect blocks or extent tree nodes needed to
+  /// reach them. Return the tree nodes no longer used, which happens as
+  /// extents merge.
+  ///
+  /// Blocks returned by `alloc` must be zeroed.
+  pub fn fill_holes(
     &mut self,
-    new_size: u32,
-    new_blocks: Vec<u32>,
+    start: u32,
+    end: u32,
     block_device: &Arc<dyn BlockDevice>,
-  ) {
-    let mut current_blocks = self.data_blocks();
-    self.size = new_size;
-    let mut total_blocks = self.data_blocks();
-    let mut new_blocks = new_blocks.into_iter();
-    // fill direct
-    while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-      self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-      current_blocks += 1;
-    }
-    // alloc indirect1
-    if total_blocks > INODE_DIRECT_COUNT as u32 {
-      if current_blocks == INODE_DIRECT_COUNT as u32 {
-        self.indirect1 = new_blocks.next().unwrap();
-      }
-      current_blocks -= INODE_DIRECT_COUNT as u32;
-      total_blocks -= INODE_DIRECT_COUNT as u32;
-    } else {
-      return;
+    mut alloc: impl FnMut() -> u32,
+  ) -> Vec<u32> {
+    if self.format == InodeFormat::Extent {
+      return self.extent_fill_holes(start, end, block_device, alloc);
     }
-    // fill indirect1
-    get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-      .lock()
-      .modify(0, |indirect1: &mut IndirectBlock| {
-        while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-          indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-          current_blocks += 1;
+    for inner_id in start as usize..end as usize {
+      if inner_id < DIRECT_BOUND {
+        if self.direct[inner_id] == 0 {
+          self.direct[inner_id] = alloc();
         }
-      });
-    // alloc indirect2
-    if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-      if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-        self.indirect2 = new_blocks.next().unwrap();
+        continue;
       }
-      current_blocks -= INODE_INDIRECT1_COUNT as u32;
-      total_blocks -= INODE_INDIRECT1_COUNT as u32;
-    } else {
-      return;
-    }
-    // fill indirect2 from (a0, b0) -> (a1, b1)
-    let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-    let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-    let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-    let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-    // alloc low-level indirect1
-    get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-      .lock()
-      .modify(0, |indirect2: &mut IndirectBlock| {
-        while (a0 < a1) || (a0 == a1 && b0 < b1) {
-          if b0 == 0 {
-            indirect2[a0] = new_blocks.next().unwrap();
-          }
-          // fill current
-          get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
+      let (indirect1, index) = if inner_id < INDIRECT1_BOUND {
+        if self.indirect1 == 0 {
+          self.indirect1 = alloc();
+        }
+        (self.indirect1, inner_id - DIRECT_BOUND)
+      } else {
+        if self.indirect2 == 0 {
+          self.indirect2 = alloc();
+        }
+        let last = inner_id - INDIRECT1_BOUND;
+        let indirect1 =
+          get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-              indirect1[b0] = new_blocks.next().unwrap();
+            .modify(0, |indirect2: &mut IndirectBlock| {
+              let entry = &mut indirect2[last / INODE_INDIRECT1_COUNT];
+              if *entry == 0 {
+                *entry = alloc();
+              }
+              *entry
             });
-          // move to next
-          b0 += 1;
-          if b0 == INODE_INDIRECT1_COUNT {
-            b0 = 0;
-            a0 += 1;
+        (indirect1, last % INODE_INDIRECT1_COUNT)
+      };
+      get_block_cache(indirect1 as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |indirect1: &mut IndirectBlock| {
+          if indirect1[index] == 0 {
+            indirect1[index] = alloc();
           }
-        }
-      });
+        });
+    }
+    Vec::new()
   }
 
-  /// Clear size to zero and return blocks that should be deallocated.
-  /// We will clear the block contents to zero later.
-  pub fn clear_size(
+  /// Decrease the size of current disk inode and return the blocks that
+  /// should be deallocated, including the indirect blocks no longer used.
+  /// Holes are skipped.
+  pub fn decrease_size(
     &mut self,
+    new_size: u32,
     block_device: &Arc<dyn BlockDevice>,
   ) -> Vec<u32> {
-    let mut v: Vec<u32> = Vec::new();
-    let mut data_blocks = self.data_blocks() as usize;
-    self.size = 0;
-    let mut current_blocks = 0usize;
-    // direct
-    while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-      v.push(self.direct[current_blocks]);
-      self.direct[current_blocks] = 0;
-      current_blocks += 1;
+    assert!(new_size <= self.size);
+    if self.format == InodeFormat::Extent {
+      return self.extent_decrease_size(new_size, block_device);
     }
-    // indirect1 block
-    if data_blocks > INODE_DIRECT_COUNT {
-      v.push(self.indirect1);
-      data_blocks -= INODE_DIRECT_COUNT;
-      current_blocks = 0;
-    } else {
-      return v;
+    let old_blocks = self.data_blocks() as usize;
+    let new_blocks = Self::_data_blocks(new_size) as usize;
+    let mut v: Vec<u32> = (new_blocks..old_blocks)
+      .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+      .filter(|&block_id| block_id != 0)
+      .collect();
+    self.size = new_size;
+    // direct
+    for id in new_blocks.min(DIRECT_BOUND)..old_blocks.min(DIRECT_BOUND) {
+      self.direct[id] = 0;
     }
     // indirect1
-    get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-      .lock()
-      .modify(0, |indirect1: &mut IndirectBlock| {
-        while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-          v.push(indirect1[current_blocks]);
-          //indirect1[current_blocks] = 0;
-          current_blocks += 1;
-        }
-      });
-    self.indirect1 = 0;
-    // indirect2 block
-    if data_blocks > INODE_INDIRECT1_COUNT {
-      v.push(self.indirect2);
-      data_blocks -= INODE_INDIRECT1_COUNT;
-    } else {
-      return v;
+    if old_blocks > DIRECT_BOUND && self.indirect1 != 0 {
+      if new_blocks <= DIRECT_BOUND {
+        v.push(self.indirect1);
+        self.indirect1 = 0;
+      } else {
+        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+          .lock()
+          .modify(0, |indirect1: &mut IndirectBlock| {
+            let start = new_blocks.min(INDIRECT1_BOUND) - DIRECT_BOUND;
+            let end = old_blocks.min(INDIRECT1_BOUND) - DIRECT_BOUND;
+            indirect1[start..end].iter_mut().for_each(|id| *id = 0);
+          });
+      }
     }
     // indirect2
-    assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-    let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-    let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-    get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-      .lock()
-      .modify(0, |indirect2: &mut IndirectBlock| {
-        // full indirect1 blocks
-        for entry in indirect2.iter_mut().take(a1) {
-          v.push(*entry);
-          get_block_cache(*entry as usize, Arc::clone(block_device))
+    if old_blocks > INDIRECT1_BOUND && self.indirect2 != 0 {
+      let old_last = old_blocks - INDIRECT1_BOUND;
+      let new_last = new_blocks.saturating_sub(INDIRECT1_BOUND);
+      // number of low-level indirect1 blocks kept and used before
+      let kept = (new_last + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+      let used = (old_last + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+      get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |indirect2: &mut IndirectBlock| {
+          for entry in indirect2[kept..used].iter_mut() {
+            if *entry != 0 {
+              v.push(*entry);
+              *entry = 0;
+            }
+          }
+          // the last low-level indirect1 block kept is partially used
+          if new_last % INODE_INDIRECT1_COUNT != 0 && indirect2[kept - 1] != 0 {
+            let base = (kept - 1) * INODE_INDIRECT1_COUNT;
+            let start = new_last - base;
+            let end = old_last.min(kept * INODE_INDIRECT1_COUNT) - base;
+            get_block_cache(
+              indirect2[kept - 1] as usize,
+              Arc::clone(block_device),
+            )
             .lock()
             .modify(0, |indirect1: &mut IndirectBlock| {
-              for entry in indirect1.iter() {
-                v.push(*entry);
-              }
+              indirect1[start..end].iter_mut().for_each(|id| *id = 0);
             });
-        }
-        // last indirect1 block
-        if b1 > 0 {
-          v.push(indirect2[a1]);
-          get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
+          }
+        });
+      if new_blocks <= INDIRECT1_BOUND {
+        v.push(self.indirect2);
+        self.indirect2 = 0;
+      }
+    }
+    v
+  }
+
+  /// Get id of block given inner id in the extent tree, `0` for a hole.
+  fn extent_block_id(
+    &self,
+    inner_id: u32,
+    block_device: &Arc<dyn BlockDevice>,
+  ) -> u32 {
+    let root = self.extent_root();
+    let mut depth = root.depth;
+    let mut entry = root.find(inner_id);
+    while depth > 0 {
+      let node = match entry {
+        Some(extent) => extent.start,
+        None => return 0,
+      };
+      let child_depth;
+      (child_depth, entry) =
+        get_block_cache(node as usize, Arc::clone(block_device))
+          .lock()
+          .read(0, |node: &ExtentBlock| (node.depth, node.find(inner_id)));
+      // a corrupted tree may not get any deeper
+      if child_depth + 1 != depth {
+        return 0;
+      }
+      depth = child_depth;
+    }
+    match entry {
+      Some(extent) if inner_id - extent.first < extent.len => {
+        extent.start + (inner_id - extent.first)
+      }
+      _ => 0,
+    }
+  }
+
+  /// Get the extents of data in the extent tree sorted by `first`, and
+  /// the blocks of the nodes of the tree.
+  pub(crate) fn extents(
+    &self,
+    block_device: &Arc<dyn BlockDevice>,
+  ) -> (Vec<Extent>, Vec<u32>) {
+    let mut nodes = Vec::new();
+    let root = self.extent_root();
+    let mut level = (root.depth, root.entries().unwrap_or(&[]).to_vec());
+    while level.0 > 0 {
+      let depth = level.0 - 1;
+      let mut children = Vec::new();
+      for node in level.1 {
+        nodes.push(node.start);
+        get_block_cache(node.start as usize, Arc::clone(block_device))
+          .lock()
+          .read(0, |node: &ExtentBlock| {
+            if node.depth == depth {
+              children.extend_from_slice(node.entries().unwrap_or(&[]));
+            }
+          });
+      }
+      level = (depth, children);
+    }
+    (level.1, nodes)
+  }
+
+  /// Replace the extent tree by one holding `extents` sorted by `first`.
+  /// Nodes are kept in the blocks in `nodes` and then in blocks from
+  /// `alloc`, the blocks of `nodes` left unused are returned.
+  pub(crate) fn set_extents(
+    &mut self,
+    extents: Vec<Extent>,
+    mut nodes: Vec<u32>,
+    block_device: &Arc<dyn BlockDevice>,
+    mut alloc: impl FnMut() -> u32,
+  ) -> Vec<u32> {
+    nodes.reverse();
+    let mut level = extents;
+    let mut depth = 0;
+    while level.len() > ROOT_EXTENT_COUNT {
+      level = level
+        .chunks(BLOCK_EXTENT_COUNT)
+        .map(|chunk| {
+          let node = nodes.pop().unwrap_or_else(&mut alloc);
+          get_block_cache(node as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-              for entry in indirect1.iter().take(b1) {
-                v.push(*entry);
-              }
-            });
-          //indirect2[a1] = 0;
-        }
+            .modify(0, |node: &mut ExtentBlock| node.set(depth, chunk));
+          Extent {
+            first: chunk[0].first,
+            len: 0,
+            start: node,
+          }
+        })
+        .collect();
+      depth += 1;
+    }
+    self.extent_root_mut().set(depth, &level);
+    nodes
+  }
+
+  /// [`DiskInode::fill_holes`] in the extent format
+  fn extent_fill_holes(
+    &mut self,
+    start: u32,
+    end: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    mut alloc: impl FnMut() -> u32,
+  ) -> Vec<u32> {
+    let (mut extents, nodes) = self.extents(block_device);
+    let mut holes = Vec::new();
+    let mut next = start;
+    for extent in extents.iter() {
+      if extent.first >= end {
+        break;
+      }
+      if extent.first > next {
+        holes.push(next..extent.first);
+      }
+      next = next.max(extent.first + extent.len);
+    }
+    if next < end {
+      holes.push(next..end);
+    }
+    if holes.is_empty() {
+      return Vec::new();
+    }
+    for inner_id in holes.into_iter().flatten() {
+      extents.push(Extent {
+        first: inner_id,
+        len: 1,
+        start: alloc(),
       });
-    self.indirect2 = 0;
+    }
+    extents.sort_unstable_by_key(|extent| extent.first);
+    let mut merged = Vec::with_capacity(extents.len());
+    for extent in extents {
+      extent.push_to(&mut merged);
+    }
+    self.set_extents(merged, nodes, block_device, alloc)
+  }
+
+  /// [`DiskInode::decrease_size`] in the extent format
+  fn extent_decrease_size(
+    &mut self,
+    new_size: u32,
+    block_device: &Arc<dyn BlockDevice>,
+  ) -> Vec<u32> {
+    let new_blocks = Self::_data_blocks(new_size);
+    let (extents, nodes) = self.extents(block_device);
+    let mut v = Vec::new();
+    let mut kept = Vec::new();
+    for mut extent in extents {
+      let keep = new_blocks.saturating_sub(extent.first).min(extent.len);
+      v.extend(extent.start + keep..extent.start + extent.len);
+      if keep > 0 {
+        extent.len = keep;
+        kept.push(extent);
+      }
+    }
+    self.size = new_size;
+    v.extend(self.set_extents(kept, nodes, block_device, || {
+      unreachable!("fewer extents never need more nodes")
+    }));
     v
   }
 
+  /// Zero the bytes after the size in its last block, which may hold
+  /// stale data, before the size grows over them.
+  pub fn clear_tail(&self, block_device: &Arc<dyn BlockDevice>) {
+    let tail = self.size as usize % BLOCK_SZ;
+    if tail == 0 {
+      return;
+    }
+    let block_id = self.get_block_id(self.size / BLOCK_SZ as u32, block_device);
+    if block_id != 0 {
+      get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |data_block: &mut DataBlock| {
+          data_block[tail..].iter_mut().for_each(|p| *p = 0);
+        });
+    }
+  }
+
+  /// Clear size to zero and return blocks that should be deallocated.
+  /// We will clear the block contents to zero later.
+  pub fn clear_size(
+    &mut self,
+    block_device: &Arc<dyn BlockDevice>,
+  ) -> Vec<u32> {
+    self.decrease_size(0, block_device)
+  }
+
   /// Read data from current disk inode
   pub fn read_at(
     &self,
@@ -396,16 +777,19 @@ impl DiskInode {
       // read and update read size
       let block_read_size = end_current_block - start;
       let dst = &mut buf[read_size..read_size + block_read_size];
-      get_block_cache(
-        self.get_block_id(start_block as u32, block_device) as usize,
-        Arc::clone(block_device),
-      )
-      .lock()
-      .read(0, |data_block: &DataBlock| {
-        let src =
-          &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-        dst.copy_from_slice(src);
-      });
+      let block_id = self.get_block_id(start_block as u32, block_device);
+      if block_id == 0 {
+        // a hole reads as zeros
+        dst.fill(0);
+      } else {
+        get_block_cache(block_id as usize, Arc::clone(block_device))
+          .lock()
+          .read(0, |data_block: &DataBlock| {
+            let src =
+              &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+            dst.copy_from_slice(src);
+          });
+      }
       read_size += block_read_size;
       // move to next block
       if end_current_block == end {
@@ -418,7 +802,7 @@ impl DiskInode {
   }
 
   /// Write data into current disk inode
-  /// size must be adjusted properly beforehand
+  /// size must be adjusted and holes filled properly beforehand
   pub fn write_at(
     &mut self,
     offset: usize,
@@ -459,58 +843,111 @@ impl DiskInode {
   }
 }
 
-/// A directory entry
-#[repr(C)]
+/// A directory entry. On disk it is a header of the inode number, the
+/// length of the record and the length of the name, followed by the name
+/// padded to 4 bytes, and the records of a directory are packed one after
+/// another. A record may be longer than its entry, the rest is free space,
+/// and a record with an empty name is free space as a whole.
 pub struct DirEntry {
-  name: [u8; NAME_LENGTH_LIMIT + 1],
+  name: String,
   inode_number: u32,
 }
-/// Size of a directory entry
-pub const DIRENT_SZ: usize = 32;
+/// Size of the header of a directory entry
+pub const DIRENT_HEADER_SZ: usize = 8;
+/// Largest size of a record, as kept in 16 bits
+pub const MAX_REC_LEN: usize = u16::MAX as usize & !3;
 
 impl DirEntry {
-  /// Create an empty directory entry
-  pub fn empty() -> Self {
-    Self {
-      name: [0u8; NAME_LENGTH_LIMIT + 1],
-      inode_number: 0,
-    }
-  }
-
   /// Crate a directory entry from name and inode number
   pub fn new(name: &str, inode_number: u32) -> Self {
-    let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
-    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    assert!(Self::name_is_valid(name));
     Self {
-      name: bytes,
+      name: String::from(name),
       inode_number,
     }
   }
 
-  /// Serialize into bytes
-  pub fn as_bytes(&self) -> &[u8] {
-    unsafe {
-      core::slice::from_raw_parts(
-        self as *const _ as usize as *const u8,
-        DIRENT_SZ,
-      )
-    }
+  /// Whether `name` can be kept in a directory entry
+  pub fn name_is_valid(name: &str) -> bool {
+    !name.is_empty() && name.len() <= NAME_LENGTH_LIMIT
   }
 
-  /// Serialize into mutable bytes
-  pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-    unsafe {
-      core::slice::from_raw_parts_mut(
-        self as *mut _ as usize as *mut u8,
-        DIRENT_SZ,
-      )
+  /// Get the size of the record of the entry in bytes
+  pub fn rec_len(&self) -> usize {
+    (DIRENT_HEADER_SZ + self.name.len() + 3) & !3
+  }
+
+  /// Serialize into the bytes of a record of `rec_len` bytes
+  pub fn to_bytes(&self, rec_len: usize) -> Vec<u8> {
+    assert!(rec_len >= self.rec_len() && rec_len <= MAX_REC_LEN);
+    let mut bytes = alloc::vec![0u8; self.rec_len()];
+    bytes[0..4].copy_from_slice(&self.inode_number.to_le_bytes());
+    bytes[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    bytes[6] = self.name.len() as u8;
+    bytes[DIRENT_HEADER_SZ..DIRENT_HEADER_SZ + self.name.len()]
+      .copy_from_slice(self.name.as_bytes());
+    bytes
+  }
+
+  /// Serialize the header of a record of `rec_len` bytes of free space
+  pub fn free_header(rec_len: usize) -> [u8; DIRENT_HEADER_SZ] {
+    assert!(rec_len <= MAX_REC_LEN);
+    let mut header = [0u8; DIRENT_HEADER_SZ];
+    header[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    header
+  }
+
+  /// Change the size of the record at byte `offset` of the directory
+  /// `disk_inode` to `rec_len`
+  pub fn set_rec_len(
+    disk_inode: &mut DiskInode,
+    offset: usize,
+    rec_len: usize,
+    block_device: &Arc<dyn BlockDevice>,
+  ) {
+    assert!(rec_len <= MAX_REC_LEN);
+    disk_inode.write_at(
+      offset + 4,
+      &(rec_len as u16).to_le_bytes(),
+      block_device,
+    );
+  }
+
+  /// Read the record at byte `offset` of the directory `disk_inode` and
+  /// return its entry, `None` if it is free space, with its size. `None`
+  /// is returned at the end or if the record is malformed.
+  pub fn read_from(
+    disk_inode: &DiskInode,
+    offset: usize,
+    block_device: &Arc<dyn BlockDevice>,
+  ) -> Option<(Option<Self>, usize)> {
+    let size = disk_inode.size as usize;
+    if offset + DIRENT_HEADER_SZ > size {
+      return None;
+    }
+    let mut header = [0u8; DIRENT_HEADER_SZ];
+    disk_inode.read_at(offset, &mut header, block_device);
+    let inode_number = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let rec_len = u16::from_le_bytes(header[4..6].try_into().unwrap()) as usize;
+    let name_len = header[6] as usize;
+    if rec_len < DIRENT_HEADER_SZ + name_len
+      || rec_len % 4 != 0
+      || offset + rec_len > size
+    {
+      return None;
+    }
+    if name_len == 0 {
+      return Some((None, rec_len));
     }
+    let mut name = alloc::vec![0u8; name_len];
+    disk_inode.read_at(offset + DIRENT_HEADER_SZ, &mut name, block_device);
+    let name = String::from_utf8(name).ok()?;
+    Some((Some(Self { name, inode_number }), rec_len))
   }
 
   /// Get name of the entry
   pub fn name(&self) -> &str {
-    let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-    core::str::from_utf8(&self.name[..len]).unwrap()
+    &self.name
   }
 
   /// Get inode number of the entry
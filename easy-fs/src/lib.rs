@@ -5,14 +5,24 @@ mod bitmap;
 mod block_cache;
 mod block_dev;
 mod efs;
+mod fsck;
+mod journal;
 mod layout;
 mod vfs;
 
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::{
+  block_cache_clear, block_cache_dirty_count, block_cache_sync_all,
+  get_block_cache, set_block_cache_capacity,
+};
+pub use block_cache::{block_cache_stats, CacheStats, DEFAULT_CACHE_BLOCKS};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use fsck::FsckProblem;
 use layout::*;
-pub use vfs::Inode;
+pub use layout::{
+  DiskInodeType, InodeFormat, SuperBlock, MAX_FILE_SIZE, NAME_LENGTH_LIMIT,
+};
+pub use vfs::{DirEntryInfo, Inode, Metadata, ATIME_INTERVAL};
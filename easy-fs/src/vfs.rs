@@ -1,13 +1,46 @@
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use spin::{Mutex, MutexGuard};
 
 use crate::{
-  block_cache_sync_all, block_dev::BlockDevice, efs::EasyFileSystem,
-  get_block_cache, DirEntry, DiskInode, DiskInodeType, DIRENT_SZ,
+  block_dev::BlockDevice,
+  efs::{EasyFileSystem, INDEX_RESERVE_BLOCKS},
+  get_block_cache, DirEntry, DiskInode, DiskInodeType, BLOCK_SZ, MAX_REC_LEN,
 };
 
+/// Number of blocks a step of a large write covers, whose metadata fits in
+/// a transaction together with what an operation may have left pending
+const WRITE_STEP_BLOCKS: usize = 16;
+
+/// Age in milliseconds after which a read updates the access time anyway
+pub const ATIME_INTERVAL: u64 = 24 * 60 * 60 * 1000;
+
+/// Metadata of an inode, times are in milliseconds since the Unix epoch
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+  pub inode_id: u32,
+  pub type_: DiskInodeType,
+  /// Permission bits
+  pub mode: u32,
+  pub nlink: u32,
+  pub uid: u32,
+  pub gid: u32,
+  pub size: u32,
+  pub atime: u64,
+  pub mtime: u64,
+  pub ctime: u64,
+}
+
+/// A directory entry read by [`Inode::read_dirent`]
+#[derive(Clone, Debug)]
+pub struct DirEntryInfo {
+  pub name: String,
+  pub inode_id: u32,
+  pub type_: DiskInodeType,
+}
+
 /// Virtual filesystem layer over easy-fs
 pub struct Inode {
+  inode_id: u32,
   block_id: usize,
   block_offset: usize,
   fs: Arc<Mutex<EasyFileSystem>>,
@@ -15,14 +48,17 @@ pub struct Inode {
 }
 
 impl Inode {
-  /// Create a vfs inode
-  pub fn new(
+  /// Create a vfs inode, the caller counts it in
+  /// [`EasyFileSystem::open_inode`].
+  pub(crate) fn new(
+    inode_id: u32,
     block_id: u32,
     block_offset: usize,
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
   ) -> Self {
     Self {
+      inode_id,
       block_id: block_id as usize,
       block_offset,
       fs,
@@ -30,6 +66,42 @@ impl Inode {
     }
   }
 
+  /// Get the inode number
+  pub fn inode_id(&self) -> u32 {
+    self.inode_id
+  }
+
+  /// Whether this inode is a directory
+  pub fn is_dir(&self) -> bool {
+    self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+  }
+
+  /// Whether this inode is a symbolic link
+  pub fn is_symlink(&self) -> bool {
+    self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
+  }
+
+  /// Get the max size of current inode in bytes
+  pub fn max_size(&self) -> usize {
+    self.read_disk_inode(|disk_inode| disk_inode.max_size())
+  }
+
+  /// Get the metadata of current inode
+  pub fn metadata(&self) -> Metadata {
+    self.read_disk_inode(|disk_inode| Metadata {
+      inode_id: self.inode_id,
+      type_: disk_inode.type_(),
+      mode: disk_inode.mode,
+      nlink: disk_inode.nlink,
+      uid: disk_inode.uid,
+      gid: disk_inode.gid,
+      size: disk_inode.size,
+      atime: disk_inode.atime,
+      mtime: disk_inode.mtime,
+      ctime: disk_inode.ctime,
+    })
+  }
+
   /// Call a function over a disk inode to read it
   fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
     get_block_cache(self.block_id, Arc::clone(&self.block_device))
@@ -46,167 +118,755 @@ impl Inode {
 
   /// Find inode under current inode by name
   pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-    let fs = self.fs.lock();
+    let mut fs = self.fs.lock();
     self.read_disk_inode(|disk_inode| {
-      self.find_inode_id(name, disk_inode).map(|inode_id| {
-        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-        Arc::new(Self::new(
-          block_id,
-          block_offset,
-          self.fs.clone(),
-          self.block_device.clone(),
-        ))
-      })
+      if !disk_inode.is_dir() {
+        return None;
+      }
+      self
+        .find_inode_id(name, disk_inode)
+        .map(|inode_id| self.get_inode(inode_id, &mut fs))
     })
   }
 
+  /// Find inode by a path relative to current inode, components are
+  /// separated by `/` and may be `.` or `..`.
+  pub fn find_path(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+    let mut inode = Arc::clone(self);
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+      inode = inode.find(name)?;
+    }
+    Some(inode)
+  }
+
+  /// Get the vfs inode of `inode_id`
+  fn get_inode(
+    &self,
+    inode_id: u32,
+    fs: &mut MutexGuard<EasyFileSystem>,
+  ) -> Arc<Inode> {
+    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+    fs.open_inode(inode_id);
+    Arc::new(Self::new(
+      inode_id,
+      block_id,
+      block_offset,
+      self.fs.clone(),
+      self.block_device.clone(),
+    ))
+  }
+
   /// Find inode under a disk inode by name
   fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+    self
+      .find_dirent(name, disk_inode)
+      .map(|(_, inode_id)| inode_id)
+  }
+
+  /// Get the records of a directory disk inode with their offsets and
+  /// sizes, and the entries they hold
+  fn records(
+    &self,
+    disk_inode: &DiskInode,
+  ) -> Vec<(usize, Option<DirEntry>, usize)> {
     // assert it is a directory
     assert!(disk_inode.is_dir());
-    let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-    let mut dirent = DirEntry::empty();
-    for i in 0..file_count {
-      assert_eq!(
-        disk_inode.read_at(
-          DIRENT_SZ * i,
-          dirent.as_bytes_mut(),
-          &self.block_device
-        ),
-        DIRENT_SZ
-      );
-      if dirent.name() == name {
-        return Some(dirent.inode_number());
-      }
+    let mut v = Vec::new();
+    let mut offset = 0;
+    while let Some((dirent, rec_len)) =
+      DirEntry::read_from(disk_inode, offset, &self.block_device)
+    {
+      v.push((offset, dirent, rec_len));
+      offset += rec_len;
     }
-    None
+    v
+  }
+
+  /// Get the entries of a directory disk inode with their offsets
+  fn dirents(&self, disk_inode: &DiskInode) -> Vec<(usize, DirEntry)> {
+    self
+      .records(disk_inode)
+      .into_iter()
+      .filter_map(|(offset, dirent, _)| Some((offset, dirent?)))
+      .collect()
+  }
+
+  /// Find the directory entry `name` under a disk inode, return its offset
+  /// and the inode number it refers to.
+  fn find_dirent(
+    &self,
+    name: &str,
+    disk_inode: &DiskInode,
+  ) -> Option<(usize, u32)> {
+    self
+      .dirents(disk_inode)
+      .into_iter()
+      .find(|(_, dirent)| dirent.name() == name)
+      .map(|(offset, dirent)| (offset, dirent.inode_number()))
   }
 
-  /// Increase the size of a disk inode.
+  /// Back the bytes `[start, end)` of a disk inode with data blocks and
+  /// grow its size to at least `end`. The gap between the old size and
+  /// `start` is left as holes.
   fn increase_size(
     &self,
-    new_size: u32,
+    start: u32,
+    end: u32,
     disk_inode: &mut DiskInode,
     fs: &mut MutexGuard<EasyFileSystem>,
   ) {
-    if new_size < disk_inode.size {
+    if start >= end {
       return;
     }
-    let block_needed = disk_inode.blocks_num_needed(new_size);
-    let mut v: Vec<u32> = Vec::new();
-    for _ in 0..block_needed {
-      v.push(fs.alloc_data());
+    disk_inode.clear_tail(&self.block_device);
+    disk_inode.size = disk_inode.size.max(end);
+    let block_sz = BLOCK_SZ as u32;
+    let unused = disk_inode.fill_holes(
+      start / block_sz,
+      (end + block_sz - 1) / block_sz,
+      &self.block_device,
+      || fs.alloc_data().expect("room is checked before growing"),
+    );
+    for block_id in unused {
+      fs.dealloc_data(block_id);
+    }
+  }
+
+  /// Get how many of the `len` bytes from `start` of a disk inode a write
+  /// can cover, the holes among them are allocated and have to fit in the
+  /// data blocks left.
+  fn room_for(
+    &self,
+    start: usize,
+    len: usize,
+    disk_inode: &DiskInode,
+    fs: &EasyFileSystem,
+  ) -> usize {
+    let mut free = fs.free_data_blocks().saturating_sub(INDEX_RESERVE_BLOCKS);
+    for inner_id in start / BLOCK_SZ..(start + len + BLOCK_SZ - 1) / BLOCK_SZ {
+      if disk_inode.get_block_id(inner_id as u32, &self.block_device) == 0 {
+        if free == 0 {
+          return (inner_id * BLOCK_SZ).max(start) - start;
+        }
+        free -= 1;
+      }
+    }
+    len
+  }
+
+  /// Let the data blocks a write to the bytes `[start, end)` of a disk
+  /// inode touches bypass the journal, including the block the tail
+  /// after its size is cleared in. Holes are allocated fresh and need no
+  /// marking.
+  fn mark_data(
+    &self,
+    start: u32,
+    end: u32,
+    disk_inode: &DiskInode,
+    fs: &MutexGuard<EasyFileSystem>,
+  ) {
+    let block_sz = BLOCK_SZ as u32;
+    let first = start.min(disk_inode.size) / block_sz;
+    let last = (end + block_sz - 1) / block_sz;
+    for inner_id in first..last {
+      let block_id = disk_inode.get_block_id(inner_id, &self.block_device);
+      if block_id != 0 {
+        fs.mark_data(block_id);
+      }
     }
-    disk_inode.increase_size(new_size, v, &self.block_device);
   }
 
   /// List inodes under current inode
   pub fn ls(&self) -> Vec<String> {
     let _fs = self.fs.lock();
     self.read_disk_inode(|disk_inode| {
-      let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-      let mut v: Vec<String> = Vec::new();
-      for i in 0..file_count {
-        let mut dirent = DirEntry::empty();
-        assert_eq!(
-          disk_inode.read_at(
-            i * DIRENT_SZ,
-            dirent.as_bytes_mut(),
-            &self.block_device
-          ),
-          DIRENT_SZ
-        );
-        v.push(String::from(dirent.name()));
+      self
+        .dirents(disk_inode)
+        .into_iter()
+        .map(|(_, dirent)| String::from(dirent.name()))
+        .collect()
+    })
+  }
+
+  /// Read the directory entry at byte `offset` of current directory,
+  /// skipping free space, return it with the offset of the next record,
+  /// or `None` at the end.
+  pub fn read_dirent(&self, offset: usize) -> Option<(DirEntryInfo, usize)> {
+    let fs = self.fs.lock();
+    let (dirent, next) = self.read_disk_inode(|disk_inode| {
+      if !disk_inode.is_dir() {
+        return None;
+      }
+      let mut offset = offset;
+      loop {
+        let (dirent, rec_len) =
+          DirEntry::read_from(disk_inode, offset, &self.block_device)?;
+        offset += rec_len;
+        if let Some(dirent) = dirent {
+          return Some((dirent, offset));
+        }
+      }
+    })?;
+    let inode_id = dirent.inode_number();
+    let type_ =
+      self.read_inode_of(inode_id, &fs, |disk_inode| disk_inode.type_());
+    let info = DirEntryInfo {
+      name: String::from(dirent.name()),
+      inode_id,
+      type_,
+    };
+    Some((info, next))
+  }
+
+  /// Add a directory entry to a directory disk inode, in the free space
+  /// of a record if one has enough, otherwise at the end.
+  fn append_dirent(
+    &self,
+    name: &str,
+    inode_id: u32,
+    dir_inode: &mut DiskInode,
+    fs: &mut MutexGuard<EasyFileSystem>,
+  ) {
+    let dirent = DirEntry::new(name, inode_id);
+    let free = self.records(dir_inode).into_iter().find_map(
+      |(offset, entry, rec_len)| {
+        let used = entry.map_or(0, |entry| entry.rec_len());
+        (rec_len - used >= dirent.rec_len()).then_some((offset, used, rec_len))
+      },
+    );
+    let (offset, rec_len) = match free {
+      // the record keeps its entry and gives the rest to the new one
+      Some((offset, used, rec_len)) => {
+        if used > 0 {
+          DirEntry::set_rec_len(dir_inode, offset, used, &self.block_device);
+        }
+        (offset + used, rec_len - used)
+      }
+      None => {
+        let offset = dir_inode.size;
+        // increase size
+        let new_size = offset + dirent.rec_len() as u32;
+        self.increase_size(offset, new_size, dir_inode, fs);
+        (offset as usize, dirent.rec_len())
+      }
+    };
+    // write dirent
+    dir_inode.write_at(offset, &dirent.to_bytes(rec_len), &self.block_device);
+    dir_inode.touch(fs.now());
+  }
+
+  /// Remove the directory entry at `offset` from a directory disk inode.
+  /// Its record becomes free space taken by the record before it, as in
+  /// ext2, so the other entries stay where they are. Free space at the
+  /// end is cut off and the data blocks no longer used are deallocated.
+  fn remove_dirent(
+    &self,
+    offset: usize,
+    dir_inode: &mut DiskInode,
+    fs: &mut MutexGuard<EasyFileSystem>,
+  ) {
+    let records = self.records(dir_inode);
+    let idx = records.iter().position(|(o, _, _)| *o == offset).unwrap();
+    let rec_len = records[idx].2;
+    // a reader left at `offset` skips the free record
+    dir_inode.write_at(
+      offset,
+      &DirEntry::free_header(rec_len),
+      &self.block_device,
+    );
+    // `.` comes first and is never removed
+    let (prev_offset, _, prev_len) = records[idx - 1];
+    if prev_len + rec_len <= MAX_REC_LEN {
+      DirEntry::set_rec_len(
+        dir_inode,
+        prev_offset,
+        prev_len + rec_len,
+        &self.block_device,
+      );
+    }
+    if offset + rec_len == dir_inode.size as usize {
+      let (last_offset, last_len) = records[..idx]
+        .iter()
+        .rev()
+        .find_map(|(offset, dirent, _)| {
+          Some((*offset, dirent.as_ref()?.rec_len()))
+        })
+        .unwrap();
+      DirEntry::set_rec_len(
+        dir_inode,
+        last_offset,
+        last_len,
+        &self.block_device,
+      );
+      let new_size = (last_offset + last_len) as u32;
+      for data_block in dir_inode.decrease_size(new_size, &self.block_device) {
+        fs.dealloc_data(data_block);
       }
-      v
+    }
+    dir_inode.touch(fs.now());
+  }
+
+  /// Call a function over the disk inode of `inode_id` to read it
+  fn read_inode_of<V>(
+    &self,
+    inode_id: u32,
+    fs: &MutexGuard<EasyFileSystem>,
+    f: impl FnOnce(&DiskInode) -> V,
+  ) -> V {
+    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+    get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+      .lock()
+      .read(block_offset, f)
+  }
+
+  /// Call a function over the disk inode of `inode_id` to modify it
+  fn modify_inode_of<V>(
+    &self,
+    inode_id: u32,
+    fs: &MutexGuard<EasyFileSystem>,
+    f: impl FnOnce(&mut DiskInode) -> V,
+  ) -> V {
+    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+    get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+      .lock()
+      .modify(block_offset, f)
+  }
+
+  /// Whether the directory `inode_id` holds nothing but `.` and `..`
+  fn is_empty_dir(
+    &self,
+    inode_id: u32,
+    fs: &MutexGuard<EasyFileSystem>,
+  ) -> bool {
+    self.read_inode_of(inode_id, fs, |disk_inode| {
+      self.dirents(disk_inode).len() <= 2
     })
   }
 
-  /// Create inode under current inode by name
-  pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+  /// Deallocate the data blocks of inode `inode_id` and the inode itself
+  fn release_inode(&self, inode_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+    let data_blocks_dealloc =
+      self.modify_inode_of(inode_id, fs, |disk_inode| {
+        disk_inode.clear_size(&self.block_device)
+      });
+    for data_block in data_blocks_dealloc.into_iter() {
+      fs.dealloc_data(data_block);
+    }
+    fs.dealloc_inode(inode_id);
+  }
+
+  /// Drop a link to inode `inode_id`, it is released with its last link
+  /// unless a vfs inode of it is still alive. Return `false` and leave it
+  /// as it is if it has no link to drop, as in a corrupted image.
+  fn drop_link(
+    &self,
+    inode_id: u32,
+    fs: &mut MutexGuard<EasyFileSystem>,
+  ) -> bool {
+    let now = fs.now();
+    let nlink = self.modify_inode_of(inode_id, fs, |disk_inode| {
+      disk_inode.nlink = disk_inode.nlink.checked_sub(1)?;
+      disk_inode.ctime = now;
+      Some(disk_inode.nlink)
+    });
+    match nlink {
+      None => false,
+      Some(0) if !fs.is_open(inode_id) => {
+        self.release_inode(inode_id, fs);
+        true
+      }
+      Some(_) => true,
+    }
+  }
+
+  /// Remove the entry `name` from current directory and drop the link
+  /// to the inode it refers to, which must be a directory iff `dir` is
+  /// set.
+  fn remove_inode(&self, name: &str, dir: bool) -> bool {
+    if name == "." || name == ".." {
+      return false;
+    }
+    let mut fs = self.fs.lock();
+    let found = self.read_disk_inode(|disk_inode| {
+      if !disk_inode.is_dir() {
+        return None;
+      }
+      self.find_dirent(name, disk_inode)
+    });
+    let (offset, inode_id) = match found {
+      Some(found) => found,
+      None => return false,
+    };
+    if self.read_inode_of(inode_id, &fs, |disk_inode| disk_inode.is_dir())
+      != dir
+      || (dir && !self.is_empty_dir(inode_id, &fs))
+    {
+      return false;
+    }
+    if !self.drop_link(inode_id, &mut fs) {
+      return false;
+    }
+    self.modify_disk_inode(|dir_inode| {
+      self.remove_dirent(offset, dir_inode, &mut fs);
+    });
+    fs.end_op();
+    true
+  }
+
+  /// Remove a file under current inode by name, its data blocks and
+  /// inode are deallocated once it has no links and is not open.
+  pub fn unlink(&self, name: &str) -> bool {
+    self.remove_inode(name, false)
+  }
+
+  /// Remove an empty directory under current inode by name.
+  pub fn rmdir(&self, name: &str) -> bool {
+    self.remove_inode(name, true)
+  }
+
+  /// Add a hard link `name` under current inode to the file `inode`.
+  pub fn link(&self, name: &str, inode: &Inode) -> bool {
+    if !DirEntry::name_is_valid(name) || name == "." || name == ".." {
+      return false;
+    }
+    let mut fs = self.fs.lock();
+    let op = |dir_inode: &DiskInode| {
+      dir_inode.is_dir() && self.find_inode_id(name, dir_inode).is_none()
+    };
+    if !self.read_disk_inode(op)
+      || inode.read_disk_inode(|d| d.is_dir())
+      || !fs.has_room(1)
+    {
+      return false;
+    }
+    self.modify_disk_inode(|dir_inode| {
+      self.append_dirent(name, inode.inode_id, dir_inode, &mut fs);
+    });
+    inode.modify_disk_inode(|disk_inode| {
+      disk_inode.nlink += 1;
+      disk_inode.ctime = fs.now();
+    });
+    fs.end_op();
+    true
+  }
+
+  /// Get the number of links to current inode
+  pub fn nlink(&self) -> u32 {
+    self.read_disk_inode(|disk_inode| disk_inode.nlink)
+  }
+
+  /// Move the entry `old_name` under current inode to `new_name` under
+  /// `new_dir`. An existing `new_name` is replaced if it is of the same
+  /// type, and a directory it replaces must be empty. A directory cannot
+  /// be moved into itself or one of its subdirectories.
+  pub fn rename(
+    &self,
+    old_name: &str,
+    new_dir: &Inode,
+    new_name: &str,
+  ) -> bool {
+    if [old_name, new_name].iter().any(|name| {
+      !DirEntry::name_is_valid(name) || *name == "." || *name == ".."
+    }) {
+      return false;
+    }
+    let mut fs = self.fs.lock();
+    let found = self.read_disk_inode(|disk_inode| {
+      if !disk_inode.is_dir() {
+        return None;
+      }
+      self.find_inode_id(old_name, disk_inode)
+    });
+    let inode_id = match found {
+      Some(inode_id) => inode_id,
+      None => return false,
+    };
+    if !new_dir.read_disk_inode(|disk_inode| disk_inode.is_dir())
+      || !fs.has_room(1)
+    {
+      return false;
+    }
+    let is_dir =
+      self.read_inode_of(inode_id, &fs, |disk_inode| disk_inode.is_dir());
+    if is_dir {
+      // walk up from the new parent to the root
+      let mut ancestor = new_dir.inode_id;
+      loop {
+        if ancestor == inode_id {
+          return false;
+        }
+        let parent = self.read_inode_of(ancestor, &fs, |disk_inode| {
+          self.find_inode_id("..", disk_inode).unwrap()
+        });
+        if parent == ancestor {
+          break;
+        }
+        ancestor = parent;
+      }
+    }
+    let target = new_dir
+      .read_disk_inode(|disk_inode| self.find_dirent(new_name, disk_inode));
+    match target {
+      Some((_, target_id)) if target_id == inode_id => return true,
+      Some((offset, target_id)) => {
+        let target_is_dir =
+          self.read_inode_of(target_id, &fs, |disk_inode| disk_inode.is_dir());
+        if target_is_dir != is_dir
+          || (target_is_dir && !self.is_empty_dir(target_id, &fs))
+        {
+          return false;
+        }
+        if !self.drop_link(target_id, &mut fs) {
+          return false;
+        }
+        new_dir.modify_disk_inode(|dir_inode| {
+          self.remove_dirent(offset, dir_inode, &mut fs);
+          self.append_dirent(new_name, inode_id, dir_inode, &mut fs);
+        });
+      }
+      None => new_dir.modify_disk_inode(|dir_inode| {
+        self.append_dirent(new_name, inode_id, dir_inode, &mut fs);
+      }),
+    }
+    self.modify_disk_inode(|dir_inode| {
+      let (offset, _) = self.find_dirent(old_name, dir_inode).unwrap();
+      self.remove_dirent(offset, dir_inode, &mut fs);
+    });
+    // a moved directory has a new parent
+    if is_dir && new_dir.inode_id != self.inode_id {
+      // the inode number leads the record, which keeps its size
+      self.modify_inode_of(inode_id, &fs, |disk_inode| {
+        let (offset, _) = self.find_dirent("..", disk_inode).unwrap();
+        disk_inode.write_at(
+          offset,
+          &new_dir.inode_id.to_le_bytes(),
+          &self.block_device,
+        );
+        disk_inode.ctime = fs.now();
+      });
+    }
+    fs.end_op();
+    true
+  }
+
+  /// Create inode of `type_` under current inode by name, with `data` as
+  /// its content written in the same transaction
+  fn create_inode(
+    &self,
+    name: &str,
+    type_: DiskInodeType,
+    data: &[u8],
+  ) -> Option<Arc<Inode>> {
+    if !DirEntry::name_is_valid(name)
+      || data.len() > WRITE_STEP_BLOCKS * BLOCK_SZ
+    {
+      return None;
+    }
     let mut fs = self.fs.lock();
     let op = |root_inode: &DiskInode| {
-      // assert it is a directory
-      assert!(root_inode.is_dir());
       // has the file been created?
-      self.find_inode_id(name, root_inode)
+      root_inode.is_dir() && self.find_inode_id(name, root_inode).is_none()
     };
-    if self.read_disk_inode(op).is_some() {
+    // the new entry, the first block of a new directory and the data
+    let data_blocks = (data.len() + BLOCK_SZ - 1) / BLOCK_SZ;
+    if !self.read_disk_inode(op) || !fs.has_room(2 + data_blocks) {
       return None;
     }
     // create a new file
     // alloc a inode with an indirect block
-    let new_inode_id = fs.alloc_inode();
+    let new_inode_id = fs.alloc_inode()?;
     // initialize inode
     let (new_inode_block_id, new_inode_block_offset) =
       fs.get_disk_inode_pos(new_inode_id);
+    let is_dir = type_ == DiskInodeType::Directory;
     get_block_cache(
       new_inode_block_id as usize,
       Arc::clone(&self.block_device),
     )
     .lock()
     .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-      new_inode.initialize(DiskInodeType::File);
+      new_inode.initialize(type_, fs.inode_format(), fs.now());
+      // a directory starts with entries of itself and its parent
+      if is_dir {
+        self.append_dirent(".", new_inode_id, new_inode, &mut fs);
+        self.append_dirent("..", self.inode_id, new_inode, &mut fs);
+      }
+      // room for the data is checked above, it is written in full
+      self.increase_size(0, data.len() as u32, new_inode, &mut fs);
+      new_inode.write_at(0, data, &self.block_device);
     });
     self.modify_disk_inode(|root_inode| {
       // append file in the dirent
-      let file_count = (root_inode.size as usize) / DIRENT_SZ;
-      let new_size = (file_count + 1) * DIRENT_SZ;
-      // increase size
-      self.increase_size(new_size as u32, root_inode, &mut fs);
-      // write dirent
-      let dirent = DirEntry::new(name, new_inode_id);
-      root_inode.write_at(
-        file_count * DIRENT_SZ,
-        dirent.as_bytes(),
-        &self.block_device,
-      );
+      self.append_dirent(name, new_inode_id, root_inode, &mut fs);
     });
 
-    block_cache_sync_all();
-    let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
+    fs.end_op();
     // return inode
-    Some(Arc::new(Self::new(
-      block_id,
-      block_offset,
-      self.fs.clone(),
-      self.block_device.clone(),
-    )))
+    Some(self.get_inode(new_inode_id, &mut fs))
     // release efs lock automatically by compiler
   }
 
+  /// Add the `.` and `..` entries to the root directory, both of them
+  /// refer to the root itself.
+  pub(crate) fn init_root(&self) {
+    let mut fs = self.fs.lock();
+    self.modify_disk_inode(|root_inode| {
+      self.append_dirent(".", self.inode_id, root_inode, &mut fs);
+      self.append_dirent("..", self.inode_id, root_inode, &mut fs);
+    });
+    fs.end_op();
+  }
+
+  /// Create a file under current inode by name
+  pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+    self.create_inode(name, DiskInodeType::File, &[])
+  }
+
+  /// Create a directory under current inode by name, it comes with the
+  /// `.` and `..` entries.
+  pub fn mkdir(&self, name: &str) -> Option<Arc<Inode>> {
+    self.create_inode(name, DiskInodeType::Directory, &[])
+  }
+
+  /// Create a symbolic link to `target` under current inode by name, the
+  /// target is not required to exist. The link and its target are written
+  /// in one transaction, so a target longer than a write step is refused.
+  pub fn symlink(&self, name: &str, target: &str) -> Option<Arc<Inode>> {
+    self.create_inode(name, DiskInodeType::SymLink, target.as_bytes())
+  }
+
+  /// Read the target path of a symbolic link
+  pub fn read_link(&self) -> Option<String> {
+    let _fs = self.fs.lock();
+    self.read_disk_inode(|disk_inode| {
+      if !disk_inode.is_symlink() {
+        return None;
+      }
+      let mut buf = vec![0u8; disk_inode.size as usize];
+      disk_inode.read_at(0, &mut buf, &self.block_device);
+      String::from_utf8(buf).ok()
+    })
+  }
+
   /// Clear the data in current inode
   pub fn clear(&self) {
     let mut fs = self.fs.lock();
     self.modify_disk_inode(|disk_inode| {
-      let size = disk_inode.size;
       let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-      assert!(
-        data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize
-      );
       for data_block in data_blocks_dealloc.into_iter() {
         fs.dealloc_data(data_block);
       }
+      disk_inode.touch(fs.now());
+    });
+    fs.end_op();
+  }
+
+  /// Set the size of a regular file to `new_size`.
+  ///
+  /// Shrinking frees the data blocks past the new end, while growing
+  /// only records the size and leaves the new range as a hole that
+  /// reads as zeros until it is written.
+  pub fn truncate(&self, new_size: usize) -> bool {
+    let mut fs = self.fs.lock();
+    let truncated = self.modify_disk_inode(|disk_inode| {
+      if disk_inode.type_() != DiskInodeType::File
+        || new_size > disk_inode.max_size()
+      {
+        return false;
+      }
+      let new_size = new_size as u32;
+      if new_size < disk_inode.size {
+        for data_block in disk_inode.decrease_size(new_size, &self.block_device)
+        {
+          fs.dealloc_data(data_block);
+        }
+      } else {
+        self.mark_data(disk_inode.size, disk_inode.size, disk_inode, &fs);
+        disk_inode.clear_tail(&self.block_device);
+        disk_inode.size = new_size;
+      }
+      disk_inode.touch(fs.now());
+      true
     });
-    block_cache_sync_all();
+    fs.end_op();
+    truncated
   }
 
-  /// Read data from current inode
+  /// Read data from current inode. Like `relatime`, the access time is
+  /// only updated if it is not later than the last modification or change
+  /// or if it is older than [`ATIME_INTERVAL`], so that most reads do not
+  /// write anything.
   pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-    let _fs = self.fs.lock();
-    self.read_disk_inode(|disk_inode| {
-      disk_inode.read_at(offset, buf, &self.block_device)
-    })
+    let mut fs = self.fs.lock();
+    let now = fs.now();
+    let (size, stale) = self.read_disk_inode(|disk_inode| {
+      let atime = disk_inode.atime;
+      let stale = now > atime
+        && (atime <= disk_inode.mtime.max(disk_inode.ctime)
+          || now - atime >= ATIME_INTERVAL);
+      (disk_inode.read_at(offset, buf, &self.block_device), stale)
+    });
+    if stale && !fs.noatime() {
+      self.modify_disk_inode(|disk_inode| disk_inode.atime = now);
+      fs.end_op();
+    }
+    size
   }
 
-  /// Write data to current inode
+  /// Write data to current inode, as much of it as fits below the maximum
+  /// size of the inode and in the free data blocks. Return the number of
+  /// bytes written.
+  ///
+  /// A large write goes in steps of `WRITE_STEP_BLOCKS` blocks, which
+  /// are committed in parts if they outgrow a transaction.
   pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
     let mut fs = self.fs.lock();
-    let size = self.modify_disk_inode(|disk_inode| {
-      self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-      disk_inode.write_at(offset, buf, &self.block_device)
-    });
-    block_cache_sync_all();
+    let max_size = self.read_disk_inode(|disk_inode| disk_inode.max_size());
+    if offset >= max_size {
+      return 0;
+    }
+    let buf = &buf[..buf.len().min(max_size - offset)];
+    let mut size = 0;
+    loop {
+      let step = &buf[size..buf.len().min(size + WRITE_STEP_BLOCKS * BLOCK_SZ)];
+      let start = offset + size;
+      let len = self.read_disk_inode(|disk_inode| {
+        self.room_for(start, step.len(), disk_inode, &fs)
+      });
+      let full = len == step.len();
+      if len == 0 && !full {
+        break;
+      }
+      let step = &step[..len];
+      let (start, end) = (start as u32, (start + len) as u32);
+      size += self.modify_disk_inode(|disk_inode| {
+        if !disk_inode.is_dir() {
+          self.mark_data(start, end, disk_inode, &fs);
+        }
+        self.increase_size(start, end, disk_inode, &mut fs);
+        disk_inode.touch(fs.now());
+        disk_inode.write_at(start as usize, step, &self.block_device)
+      });
+      // a step cut short has run out of space
+      if size == buf.len() || !full {
+        break;
+      }
+      fs.commit_if_full();
+    }
+    fs.end_op();
     size
   }
 }
+
+impl Drop for Inode {
+  /// An inode whose last link is gone is released with its last vfs inode.
+  fn drop(&mut self) {
+    let mut fs = self.fs.lock();
+    if fs.close_inode(self.inode_id)
+      && self.read_disk_inode(|disk_inode| disk_inode.nlink == 0)
+    {
+      self.release_inode(self.inode_id, &mut fs);
+      fs.end_op();
+    }
+  }
+}
@@ -7,7 +7,29 @@ pub const MEMORY_END: usize = 0x80800000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
+/// Start of the user heap grown by `sys_brk`/`sys_sbrk`, far above
+/// the program image and the user stacks of its threads.
+pub const USER_HEAP_BASE: usize = 0x4000_0000;
+/// Upper bound of the user heap.
+pub const USER_HEAP_LIMIT: usize = 0x8000_0000;
+/// `sys_mmap` places mappings without a usable hint from here.
+pub const USER_MMAP_BASE: usize = 0x1_0000_0000;
+/// Upper bound of mappings, the end of the lower half of SV39.
+pub const USER_MMAP_TOP: usize = 0x40_0000_0000;
+
+/// Pages of the swap device, 16 MiB as created by the Makefile.
+pub const SWAP_PAGES: usize = 0x1000;
+
+/// Blocks the filesystem block cache holds.
+pub const FS_CACHE_BLOCKS: usize = 64;
+/// Changes to the filesystem are committed to disk this often.
+pub const FS_FLUSH_INTERVAL_MS: usize = 1000;
+
+/// Harts the kernel runs on at most, as many boot stacks are reserved
+/// in `entry.asm`.
+pub const MAX_HARTS: usize = 4;
+
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
-pub use crate::qemu::{CLOCK_FREQ, MMIO};
+pub use crate::qemu::{CLOCK_FREQ, MMIO, RTC_BASE};
@@ -1,8 +1,12 @@
 //! SBI console driver, for test output
 
-use crate::sbi::console_putchar;
+use crate::{sbi::console_putchar, sync::SpinLock};
 use core::fmt::{self, Write};
 
+/// keeps lines printed by different harts apart
+static STDOUT_LOCK: SpinLock<()> =
+  unsafe { SpinLock::new(()) };
+
 struct Stdout;
 
 impl Write for Stdout {
@@ -15,6 +19,7 @@ impl Write for Stdout {
 }
 
 pub fn print(args: fmt::Arguments) {
+  let _lock = STDOUT_LOCK.exclusive_access();
   Stdout.write_fmt(args).unwrap();
 }
 
@@ -3,12 +3,16 @@ use alloc::sync::Arc;
 use easy_fs::BlockDevice;
 use lazy_static::lazy_static;
 pub use virtio_blk::VirtIOBlock;
+use virtio_blk::VIRTIO1;
 
 use crate::qemu::BlockDeviceImpl;
 
 lazy_static! {
   pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> =
     Arc::new(BlockDeviceImpl::new());
+  /// the block device holding swapped out user pages
+  pub static ref SWAP_DEVICE: Arc<dyn BlockDevice> =
+    Arc::new(BlockDeviceImpl::with_base(VIRTIO1));
 }
 
 #[allow(unused)]
@@ -3,7 +3,7 @@ use crate::{
     frame_alloc, frame_dealloc, kernel_token, FrameTracker,
     PageTable, PhysAddr, PhysPageNum, StepByOne, VirtAddr,
   },
-  sync::UPSafeCell,
+  sync::SpinLock,
 };
 use alloc::vec::Vec;
 use easy_fs::BlockDevice;
@@ -11,14 +11,16 @@ use lazy_static::lazy_static;
 use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
 
 const VIRTIO0: usize = 0x1000_1000;
+/// the second virtio block device, used as swap space
+pub const VIRTIO1: usize = 0x1000_2000;
 
 lazy_static! {
-  static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> =
-    unsafe { UPSafeCell::new(Vec::new()) };
+  static ref QUEUE_FRAMES: SpinLock<Vec<FrameTracker>> =
+    unsafe { SpinLock::new(Vec::new()) };
 }
 
 pub struct VirtIOBlock(
-  UPSafeCell<VirtIOBlk<'static, VirtioHal>>,
+  SpinLock<VirtIOBlk<'static, VirtioHal>>,
 );
 
 impl BlockDevice for VirtIOBlock {
@@ -42,10 +44,15 @@ impl BlockDevice for VirtIOBlock {
 impl VirtIOBlock {
   #[allow(unused)]
   pub fn new() -> Self {
+    Self::with_base(VIRTIO0)
+  }
+
+  /// Create the device whose MMIO registers start at `base`.
+  pub fn with_base(base: usize) -> Self {
     unsafe {
-      Self(UPSafeCell::new(
+      Self(SpinLock::new(
         VirtIOBlk::<VirtioHal>::new(
-          &mut *(VIRTIO0 as *mut VirtIOHeader),
+          &mut *(base as *mut VirtIOHeader),
         )
         .unwrap(),
       ))
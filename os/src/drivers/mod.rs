@@ -1,3 +1,5 @@
 pub mod block;
+pub mod rtc;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
+pub use rtc::rtc_time_ms;
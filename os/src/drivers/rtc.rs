@@ -0,0 +1,25 @@
+//! Goldfish real time clock of the virt machine
+
+use crate::config::RTC_BASE;
+
+/// Low 32 bits of the time, reading it latches the high bits
+const TIME_LOW: usize = 0x00;
+/// High 32 bits of the time
+const TIME_HIGH: usize = 0x04;
+const NSEC_PER_MSEC: u64 = 1_000_000;
+
+/// Nanoseconds since the Unix epoch.
+pub fn rtc_time_ns() -> u64 {
+  unsafe {
+    let low =
+      ((RTC_BASE + TIME_LOW) as *const u32).read_volatile();
+    let high = ((RTC_BASE + TIME_HIGH) as *const u32)
+      .read_volatile();
+    ((high as u64) << 32) | low as u64
+  }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn rtc_time_ms() -> u64 {
+  rtc_time_ns() / NSEC_PER_MSEC
+}
@@ -2,14 +2,52 @@
 # it can be teh entry point of kernel(at a lower address).
   .section .text.entry 
   .global _start # we declare the symbol `_start` is global, it can used by other files.
+
+# a0 = hartid, kept in `tp` while running in the kernel.
+# every hart gets its own boot stack.
+.macro SET_BOOT_STACK
+  mv tp, a0
+  addi t0, a0, 1
+  li t1, 4096 * 16
+  mul t0, t0, t1
+  la sp, boot_stack_lower_bound
+  add sp, sp, t0
+.endm
+
+# harts with an id of at least `MAX_HARTS` (4) have no boot stack
+# and no `Processor`, they do not run the kernel.
 # declare a symbol
 _start:
-  la sp, boot_stack_top
+  li t0, 4
+  bltu a0, t0, 1f
+  # the boot hart is an extra one, hand the boot over to hart 0 with
+  # the SBI HSM `hart_start` call, and park if it can not be started.
+  li a7, 0x48534D
+  li a6, 0
+  li a0, 0
+  la a1, _start
+  li a2, 0
+  ecall
+  j park
+1:
+  SET_BOOT_STACK
   call rust_main
 
+# the other harts started by `sbi_rt::hart_start` come here
+  .global _start_secondary
+_start_secondary:
+  li t0, 4
+  bgeu a0, t0, park
+  SET_BOOT_STACK
+  call rust_main_secondary
+
+park:
+  wfi
+  j park
+
   .section .bss.stack
   .global boot_stack_lower_bound
 boot_stack_lower_bound:
-  .space 4096 * 16 # 64 KiB space for application stack space.
+  .space 4096 * 16 * 4 # 64 KiB space for each of `MAX_HARTS` harts.
   .global boot_stack_top
 boot_stack_top:
\ No newline at end of file
@@ -1,24 +1,78 @@
 //! `Arc<Inode>` -> `OSInodeInner`:
 //! In order to open files concurrently,
-//! we need to wrap `Inode` into `Arc`, but `Mutex(UPSafeCell)`
+//! we need to wrap `Inode` into `Arc`, but `Mutex(SpinLock)`
 //! in `Inode` prevents file system from being accessed simultaneously.
 //!
-//! `UPSafeCell<OSInodeInner>` -> `OSInode`:
+//! `SpinLock<OSInodeInner>` -> `OSInode`:
 //! for static `ROOT_INODE`, we need to wrap
-//! `OSInodeInner` into `UPSafeCell`
-use alloc::{sync::Arc, vec::Vec};
-use easy_fs::{EasyFileSystem, Inode};
+//! `OSInodeInner` into `SpinLock`
+use alloc::{
+  collections::VecDeque, string::String, sync::Arc,
+  vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use easy_fs::{
+  block_cache_stats, DiskInodeType, EasyFileSystem, Inode,
+};
 use lazy_static::lazy_static;
+use spin::Mutex;
 
-use crate::{drivers::BLOCK_DEVICE, sync::UPSafeCell};
+use crate::{
+  config::{FS_CACHE_BLOCKS, FS_FLUSH_INTERVAL_MS},
+  drivers::{rtc_time_ms, BLOCK_DEVICE},
+  mm::UserBuffer,
+  sync::SpinLock,
+  timer::get_time_ms,
+};
 
-use super::File;
+use super::{
+  File, Stat, StatMode, SEEK_CUR, SEEK_END, SEEK_SET,
+};
 
 lazy_static! {
-  pub static ref ROOT_INODE: Arc<Inode> = {
-    let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-    Arc::new(EasyFileSystem::root_inode(&efs))
+  /// The filesystem on the block device, operations on it are
+  /// committed to disk by `flush_fs` rather than as they finish
+  static ref EFS: Arc<Mutex<EasyFileSystem>> = {
+    let efs = EasyFileSystem::open_with_cache(
+      BLOCK_DEVICE.clone(),
+      FS_CACHE_BLOCKS,
+    );
+    efs.lock().set_clock(rtc_time_ms);
+    efs.lock().set_write_back(true);
+    efs
   };
+  pub static ref ROOT_INODE: Arc<Inode> =
+    Arc::new(EasyFileSystem::root_inode(&EFS));
+}
+
+/// Time of the last commit of the filesystem in milliseconds
+static LAST_FLUSH_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// Commit the changes to the filesystem once `FS_FLUSH_INTERVAL_MS`
+/// passed since the last commit, called on timer interrupts. A busy
+/// filesystem is left alone until the next tick.
+pub fn flush_fs() {
+  let now = get_time_ms();
+  if now - LAST_FLUSH_MS.load(Ordering::Relaxed)
+    < FS_FLUSH_INTERVAL_MS
+  {
+    return;
+  }
+  if let Some(mut efs) = EFS.try_lock() {
+    efs.commit();
+    LAST_FLUSH_MS.store(now, Ordering::Relaxed);
+  }
+}
+
+/// Commit all the changes to the filesystem, before shutting down
+pub fn sync_fs() {
+  EFS.lock().commit();
+  LAST_FLUSH_MS.store(get_time_ms(), Ordering::Relaxed);
+  let stats = block_cache_stats();
+  println!(
+    "[kernel] block cache: {} hits, {} misses, {} evictions, {} write backs",
+    stats.hits, stats.misses, stats.evictions, stats.write_backs
+  );
 }
 
 pub fn list_apps() {
@@ -29,15 +83,65 @@ pub fn list_apps() {
   println!("**************/");
 }
 
+/// Size of `d_ino`, `d_off`, `d_reclen` and `d_type` of a
+/// `linux_dirent64` record, the name follows them
+const DIRENT64_HEADER_SZ: usize = 19;
+/// `d_type` of a directory
+const DT_DIR: u8 = 4;
+/// `d_type` of a regular file
+const DT_REG: u8 = 8;
+/// `d_type` of a symbolic link
+const DT_LNK: u8 = 10;
+
+/// Read from `inode` at `offset` into `buf` until it is full or the
+/// end of the file.
+fn read_buffer(
+  inode: &Inode,
+  mut offset: usize,
+  mut buf: UserBuffer,
+) -> usize {
+  let mut total_read_size = 0usize;
+  for slice in buf.buffers.iter_mut() {
+    let read_size = inode.read_at(offset, slice);
+    offset += read_size;
+    total_read_size += read_size;
+    if read_size < slice.len() {
+      break;
+    }
+  }
+  total_read_size
+}
+
+/// Write `buf` into `inode` at `offset` until all of it is written or
+/// the file cannot grow any more.
+fn write_buffer(
+  inode: &Inode,
+  mut offset: usize,
+  buf: UserBuffer,
+) -> usize {
+  let mut total_write_size = 0usize;
+  for slice in buf.buffers.iter() {
+    let write_size = inode.write_at(offset, slice);
+    offset += write_size;
+    total_write_size += write_size;
+    if write_size < slice.len() {
+      break;
+    }
+  }
+  total_write_size
+}
+
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct OSInode {
   readable: bool,
   writable: bool,
-  inner: UPSafeCell<OSInodeInner>,
+  /// whether every write goes to the end of the file
+  append: bool,
+  inner: SpinLock<OSInodeInner>,
 }
 
-/// The OS inode inner in `UPSafeCell`
+/// The OS inode inner in `SpinLock`
 pub struct OSInodeInner {
   offset: usize,
   inode: Arc<Inode>,
@@ -48,17 +152,24 @@ impl OSInode {
   pub fn new(
     readable: bool,
     writable: bool,
+    append: bool,
     inode: Arc<Inode>,
   ) -> Self {
     Self {
       readable,
       writable,
+      append,
       inner: unsafe {
-        UPSafeCell::new(OSInodeInner { offset: 0, inode })
+        SpinLock::new(OSInodeInner { offset: 0, inode })
       },
     }
   }
 
+  /// Whether the inode is a directory
+  pub fn is_dir(&self) -> bool {
+    self.inner.exclusive_access().inode.is_dir()
+  }
+
   /// Read all data inside a inode into vector
   pub fn read_all(&self) -> Vec<u8> {
     let mut inner = self.inner.exclusive_access();
@@ -86,32 +197,129 @@ impl File for OSInode {
     self.writable
   }
 
-  fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
+  fn read(&self, buf: UserBuffer) -> usize {
     let mut inner = self.inner.exclusive_access();
-    let mut total_read_size = 0usize;
-    for slice in buf.buffers.iter_mut() {
-      let read_size =
-        inner.inode.read_at(inner.offset, slice);
-      if read_size == 0 {
-        break;
-      }
-      inner.offset += read_size;
-      total_read_size += read_size;
+    let read_size =
+      read_buffer(&inner.inode, inner.offset, buf);
+    inner.offset += read_size;
+    read_size
+  }
+
+  fn write(&self, buf: UserBuffer) -> usize {
+    let mut inner = self.inner.exclusive_access();
+    if self.append {
+      inner.offset = inner.inode.metadata().size as usize;
     }
-    total_read_size
+    let write_size =
+      write_buffer(&inner.inode, inner.offset, buf);
+    inner.offset += write_size;
+    write_size
+  }
+
+  fn seek(
+    &self,
+    offset: isize,
+    whence: usize,
+  ) -> Option<usize> {
+    let mut inner = self.inner.exclusive_access();
+    let base = match whence {
+      SEEK_SET => 0,
+      SEEK_CUR => inner.offset,
+      SEEK_END => inner.inode.metadata().size as usize,
+      _ => return None,
+    };
+    let offset = if offset < 0 {
+      base.checked_sub(offset.unsigned_abs())?
+    } else {
+      base.checked_add(offset as usize)?
+    };
+    inner.offset = offset;
+    Some(offset)
+  }
+
+  fn pread(
+    &self,
+    buf: UserBuffer,
+    offset: usize,
+  ) -> Option<usize> {
+    let inner = self.inner.exclusive_access();
+    Some(read_buffer(&inner.inode, offset, buf))
+  }
+
+  fn pwrite(
+    &self,
+    buf: UserBuffer,
+    offset: usize,
+  ) -> Option<usize> {
+    let inner = self.inner.exclusive_access();
+    Some(write_buffer(&inner.inode, offset, buf))
   }
 
-  fn write(&self, buf: crate::mm::UserBuffer) -> usize {
+  fn truncate(&self, len: usize) -> bool {
+    self.writable
+      && self.inner.exclusive_access().inode.truncate(len)
+  }
+
+  fn inode(&self) -> Option<Arc<Inode>> {
+    Some(Arc::clone(&self.inner.exclusive_access().inode))
+  }
+
+  fn getdents(&self, len: usize) -> Option<Vec<u8>> {
     let mut inner = self.inner.exclusive_access();
-    let mut total_write_size = 0usize;
-    for slice in buf.buffers.iter() {
-      let write_size =
-        inner.inode.write_at(inner.offset, slice);
-      assert_eq!(write_size, slice.len());
-      inner.offset += write_size;
-      total_write_size += write_size;
+    if !inner.inode.is_dir() {
+      return None;
     }
-    total_write_size
+    let mut v: Vec<u8> = Vec::new();
+    while let Some((entry, next)) =
+      inner.inode.read_dirent(inner.offset)
+    {
+      // the name is followed by a `\0`, records are 8-byte aligned
+      let reclen =
+        (DIRENT64_HEADER_SZ + entry.name.len() + 8) & !7;
+      if v.len() + reclen > len {
+        if v.is_empty() {
+          return None;
+        }
+        break;
+      }
+      let start = v.len();
+      v.extend_from_slice(
+        &(entry.inode_id as u64).to_le_bytes(),
+      );
+      v.extend_from_slice(&(next as i64).to_le_bytes());
+      v.extend_from_slice(&(reclen as u16).to_le_bytes());
+      v.push(match entry.type_ {
+        DiskInodeType::File => DT_REG,
+        DiskInodeType::Directory => DT_DIR,
+        DiskInodeType::SymLink => DT_LNK,
+      });
+      v.extend_from_slice(entry.name.as_bytes());
+      v.resize(start + reclen, 0);
+      inner.offset = next;
+    }
+    Some(v)
+  }
+
+  fn stat(&self) -> Option<Stat> {
+    let meta =
+      self.inner.exclusive_access().inode.metadata();
+    let type_ = match meta.type_ {
+      DiskInodeType::File => StatMode::FILE,
+      DiskInodeType::Directory => StatMode::DIR,
+      DiskInodeType::SymLink => StatMode::LINK,
+    };
+    Some(Stat {
+      dev: 0,
+      ino: meta.inode_id as u64,
+      mode: type_.bits() | meta.mode,
+      nlink: meta.nlink,
+      uid: meta.uid,
+      gid: meta.gid,
+      size: meta.size as u64,
+      atime_ms: meta.atime,
+      mtime_ms: meta.mtime,
+      ctime_ms: meta.ctime,
+    })
   }
 }
 
@@ -128,6 +336,8 @@ bitflags! {
     const CREATE = 1 << 9;
     /// Clear file and return an empty one
     const TRUNC = 1 << 10;
+    /// Write at the end of the file
+    const APPEND = 1 << 11;
   }
 }
 
@@ -145,31 +355,173 @@ impl OpenFlags {
   }
 }
 
-/// Open file with flags
+/// The absolute path of the directory `dir`, found by walking up its
+/// `..` entries, or `None` if it has been removed.
+pub fn dir_path(dir: &Arc<Inode>) -> Option<String> {
+  let mut names = Vec::new();
+  let mut inode = Arc::clone(dir);
+  while inode.inode_id() != ROOT_INODE.inode_id() {
+    if inode.nlink() == 0 {
+      return None;
+    }
+    let parent = inode.find("..")?;
+    let mut offset = 0;
+    let name = loop {
+      let (dirent, next) = parent.read_dirent(offset)?;
+      if dirent.inode_id == inode.inode_id()
+        && dirent.name != "."
+        && dirent.name != ".."
+      {
+        break dirent.name;
+      }
+      offset = next;
+    };
+    names.push(name);
+    inode = parent;
+  }
+  let mut path = String::new();
+  for name in names.iter().rev() {
+    path.push('/');
+    path.push_str(name);
+  }
+  if path.is_empty() {
+    path.push('/');
+  }
+  Some(path)
+}
+
+/// Split a path into its parent directory and its last component.
+fn split_path(path: &str) -> (&str, &str) {
+  let path = path.trim_end_matches('/');
+  match path.rfind('/') {
+    Some(idx) => (&path[..idx], &path[idx + 1..]),
+    None => ("", path),
+  }
+}
+
+/// The most symbolic links followed while resolving one path, a path
+/// needing more is taken to loop.
+const MAX_SYMLINKS: usize = 40;
+
+/// Find the inode at `path` from the directory `dir`, following the
+/// symbolic links met on the way, and the one `path` ends with if
+/// `follow_last` is set. An absolute target restarts from the root
+/// directory, a relative one from the directory holding the link.
+fn walk_path(
+  dir: &Arc<Inode>,
+  path: &str,
+  follow_last: bool,
+) -> Option<Arc<Inode>> {
+  let mut inode = if path.starts_with('/') {
+    Arc::clone(&ROOT_INODE)
+  } else if dir.nlink() == 0 {
+    // nothing is found from a removed directory
+    return None;
+  } else {
+    Arc::clone(dir)
+  };
+  let mut names: VecDeque<String> = path
+    .split('/')
+    .filter(|name| !name.is_empty())
+    .map(String::from)
+    .collect();
+  let mut followed = 0;
+  while let Some(name) = names.pop_front() {
+    let next = inode.find(&name)?;
+    if next.is_symlink()
+      && (follow_last || !names.is_empty())
+    {
+      followed += 1;
+      if followed > MAX_SYMLINKS {
+        return None;
+      }
+      let target = next.read_link()?;
+      if target.starts_with('/') {
+        inode = Arc::clone(&ROOT_INODE);
+      }
+      for name in target
+        .split('/')
+        .rev()
+        .filter(|name| !name.is_empty())
+      {
+        names.push_front(String::from(name));
+      }
+    } else {
+      inode = next;
+    }
+  }
+  Some(inode)
+}
+
+/// Find the inode at `path` from the directory `dir`, following
+/// symbolic links.
+pub fn find_inode(
+  dir: &Arc<Inode>,
+  path: &str,
+) -> Option<Arc<Inode>> {
+  walk_path(dir, path, true)
+}
+
+/// Find the parent directory of `path` from the directory `dir`, along
+/// with the last component of `path`, which is not followed if it is a
+/// symbolic link.
+pub fn find_parent(
+  dir: &Arc<Inode>,
+  path: &str,
+) -> Option<(Arc<Inode>, String)> {
+  let (parent, name) = split_path(path);
+  let parent = walk_path(dir, parent, true)?;
+  Some((parent, String::from(name)))
+}
+
+/// Create a directory at `path` from the directory `dir`, fail if its
+/// parent does not exist or it exists already.
+pub fn make_dir(
+  dir: &Arc<Inode>,
+  path: &str,
+) -> Option<Arc<Inode>> {
+  let (parent, name) = find_parent(dir, path)?;
+  if name.is_empty() || name == "." || name == ".." {
+    return None;
+  }
+  parent.mkdir(&name)
+}
+
+/// Open file at `path` from the directory `dir` with flags, following
+/// symbolic links, directories can only be opened read-only.
 pub fn open_file(
-  name: &str,
+  dir: &Arc<Inode>,
+  path: &str,
   flags: OpenFlags,
 ) -> Option<Arc<OSInode>> {
   let (readable, writable) = flags.read_write();
-  if flags.contains(OpenFlags::CREATE) {
-    if let Some(inode) = ROOT_INODE.find(name) {
-      // clear size
-      inode.clear();
-      Some(Arc::new(OSInode::new(
-        readable, writable, inode,
-      )))
-    } else {
-      // create file
-      ROOT_INODE.create(name).map(|inode| {
-        Arc::new(OSInode::new(readable, writable, inode))
-      })
-    }
-  } else {
-    ROOT_INODE.find(name).map(|inode| {
-      if flags.contains(OpenFlags::TRUNC) {
+  let inode = match find_inode(dir, path) {
+    Some(inode) => {
+      if inode.is_dir() {
+        if writable
+          || flags.intersects(
+            OpenFlags::CREATE | OpenFlags::TRUNC,
+          )
+        {
+          return None;
+        }
+      } else if flags.contains(OpenFlags::TRUNC) {
+        // clear size
         inode.clear();
       }
-      Arc::new(OSInode::new(readable, writable, inode))
-    })
-  }
+      inode
+    }
+    None if flags.contains(OpenFlags::CREATE) => {
+      // create file
+      let (parent, name) = find_parent(dir, path)?;
+      parent.create(&name)?
+    }
+    None => return None,
+  };
+  Some(Arc::new(OSInode::new(
+    readable,
+    writable,
+    flags.contains(OpenFlags::APPEND),
+    inode,
+  )))
 }
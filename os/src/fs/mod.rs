@@ -1,12 +1,28 @@
+use alloc::{sync::Arc, vec::Vec};
+use easy_fs::Inode;
+
 use crate::mm::UserBuffer;
 
 mod inode;
 mod pipe;
 mod stdio;
 
-pub use inode::{list_apps, open_file, OSInode, OpenFlags};
+pub use inode::{
+  dir_path, find_inode, find_parent, flush_fs, list_apps,
+  make_dir, open_file, sync_fs, OSInode, OpenFlags,
+  ROOT_INODE,
+};
 pub use pipe::{make_pipe, Pipe};
-pub use stdio::{Stdin, Stdout};
+pub use stdio::{
+  poll_console, set_foreground_pgid, Stdin, Stdout,
+};
+
+/// `whence` of `File::seek` taking the offset from the start
+pub const SEEK_SET: usize = 0;
+/// `whence` of `File::seek` taking the offset from the current offset
+pub const SEEK_CUR: usize = 1;
+/// `whence` of `File::seek` taking the offset from the end
+pub const SEEK_END: usize = 2;
 
 /// File trait
 pub trait File: Send + Sync {
@@ -18,4 +34,83 @@ pub trait File: Send + Sync {
   fn read(&self, buf: UserBuffer) -> usize;
   /// Write `UserBuffer` to file
   fn write(&self, buf: UserBuffer) -> usize;
+  /// The easy-fs inode behind the file, which can be mapped by `mmap`
+  fn inode(&self) -> Option<Arc<Inode>> {
+    None
+  }
+  /// Status of the file, `None` if it is not on the file system
+  fn stat(&self) -> Option<Stat> {
+    None
+  }
+  /// Move the offset to `offset` bytes from the start, the current
+  /// offset or the end as `whence` is `SEEK_SET`, `SEEK_CUR` or
+  /// `SEEK_END`, return the new offset, `None` if it is negative or the
+  /// file cannot seek.
+  fn seek(
+    &self,
+    _offset: isize,
+    _whence: usize,
+  ) -> Option<usize> {
+    None
+  }
+  /// Read at `offset` without moving the offset, `None` if the file
+  /// cannot seek
+  fn pread(
+    &self,
+    _buf: UserBuffer,
+    _offset: usize,
+  ) -> Option<usize> {
+    None
+  }
+  /// Write at `offset` without moving the offset, `None` if the file
+  /// cannot seek
+  fn pwrite(
+    &self,
+    _buf: UserBuffer,
+    _offset: usize,
+  ) -> Option<usize> {
+    None
+  }
+  /// Set the size of the file to `len`, `false` if it is not a
+  /// writable regular file
+  fn truncate(&self, _len: usize) -> bool {
+    false
+  }
+  /// Read the entries of a directory from the current offset as
+  /// `linux_dirent64` records of at most `len` bytes in total, `None`
+  /// if it is not a directory or the next record does not fit.
+  fn getdents(&self, _len: usize) -> Option<Vec<u8>> {
+    None
+  }
+}
+
+/// File status returned by `sys_fstat`, times are in milliseconds since
+/// the Unix epoch
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+  /// ID of the device holding the file
+  pub dev: u64,
+  /// Inode number
+  pub ino: u64,
+  /// File type and permission bits
+  pub mode: u32,
+  /// Number of hard links
+  pub nlink: u32,
+  pub uid: u32,
+  pub gid: u32,
+  /// Size in bytes
+  pub size: u64,
+  pub atime_ms: u64,
+  pub mtime_ms: u64,
+  pub ctime_ms: u64,
+}
+
+bitflags! {
+  /// File type bits of `Stat::mode`, the permission bits are below them
+  pub struct StatMode: u32 {
+    const DIR = 0o040000;
+    const FILE = 0o100000;
+    const LINK = 0o120000;
+  }
 }
@@ -2,7 +2,7 @@ use alloc::sync::{Arc, Weak};
 
 use super::File;
 use crate::{
-  sync::UPSafeCell, task::suspend_current_and_run_next,
+  sync::SpinLock, task::suspend_current_and_run_next,
 };
 
 /// Maximum size of pipe ring buffer.
@@ -12,13 +12,13 @@ const RING_BUFFER_SIZE: usize = 32;
 pub struct Pipe {
   readable: bool,
   writable: bool,
-  buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+  buffer: Arc<SpinLock<PipeRingBuffer>>,
 }
 
 impl Pipe {
   /// Set the pipe only readable.
   pub fn read_end_with_buffer(
-    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinLock<PipeRingBuffer>>,
   ) -> Self {
     Self {
       readable: true,
@@ -29,7 +29,7 @@ impl Pipe {
 
   /// Set the pipe only writable.
   pub fn write_end_with_buffer(
-    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinLock<PipeRingBuffer>>,
   ) -> Self {
     Self {
       readable: false,
@@ -217,7 +217,7 @@ impl PipeRingBuffer {
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
   let buffer = Arc::new(unsafe {
-    UPSafeCell::new(PipeRingBuffer::new())
+    SpinLock::new(PipeRingBuffer::new())
   });
   let read_end =
     Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
@@ -1,11 +1,61 @@
 //! Stdin && Stdout
 
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+
 use crate::{
-  sbi::console_getchar, task::suspend_current_and_run_next,
+  sbi::console_getchar,
+  sync::SpinLock,
+  task::{
+    signal_process_group, suspend_current_and_run_next,
+    SignalFlags,
+  },
 };
 
 use super::File;
 
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1a;
+
+lazy_static! {
+  /// Characters typed on the console and not read yet.
+  static ref CONSOLE_INPUT: SpinLock<VecDeque<u8>> =
+    unsafe { SpinLock::new(VecDeque::new()) };
+}
+
+/// Process group in the foreground of the console, 0 for none.
+static FOREGROUND_PGID: AtomicUsize = AtomicUsize::new(0);
+
+/// Put the process group `pgid` in the foreground of the console.
+pub fn set_foreground_pgid(pgid: usize) {
+  FOREGROUND_PGID.store(pgid, Ordering::Relaxed);
+}
+
+/// Move the characters typed on the console to the input buffer,
+/// `^C` and `^Z` send `SIGINT` and `SIGTSTP` to the foreground
+/// process group instead.
+pub fn poll_console() {
+  loop {
+    let c = console_getchar();
+    if c == 0 {
+      break;
+    }
+    let signal = match c as u8 {
+      CTRL_C => SignalFlags::SIGINT,
+      CTRL_Z => SignalFlags::SIGTSTP,
+      ch => {
+        CONSOLE_INPUT.exclusive_access().push_back(ch);
+        continue;
+      }
+    };
+    let pgid = FOREGROUND_PGID.load(Ordering::Relaxed);
+    if pgid != 0 {
+      signal_process_group(pgid, signal);
+    }
+  }
+}
+
 /// Standard input
 pub struct Stdin;
 
@@ -27,17 +77,15 @@ impl File for Stdin {
   ) -> usize {
     assert_eq!(user_buf.len(), 1);
     // busy loop
-    let mut c: usize;
-    loop {
-      c = console_getchar();
-      if c == 0 {
-        suspend_current_and_run_next();
-        continue;
-      } else {
-        break;
+    let ch = loop {
+      poll_console();
+      if let Some(ch) =
+        CONSOLE_INPUT.exclusive_access().pop_front()
+      {
+        break ch;
       }
-    }
-    let ch = c as u8;
+      suspend_current_and_run_next();
+    };
     unsafe {
       user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
     }
@@ -15,7 +15,11 @@
 //! executing from `entry.asm`, after which [`rust_main()`] is called to
 //! initialize various pieces of functionality.
 //!
-//! We then call [`task::run_first_task()`] and for the first time go to user-space.
+//! The other harts are started afterwards and enter [`rust_main_secondary()`].
+//! Only harts with an id below [`config::MAX_HARTS`] run the kernel, a boot
+//! hart beyond them hands the boot over to hart 0 in `entry.asm`.
+//!
+//! We then call [`task::run_tasks()`] on every hart and for the first time go to user-space.
 
 #![no_std]
 #![no_main]
@@ -51,7 +55,7 @@ global_asm!(include_str!("entry.asm"));
 
 #[no_mangle]
 /// The rust entry-point of os
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize) -> ! {
   clear_bss();
   println!("[kernel] Kernel started.");
   println!("[kernel] memory init.");
@@ -68,11 +72,39 @@ pub fn rust_main() -> ! {
   fs::list_apps();
   println!("[kernel] add init process.");
   task::add_initproc();
+  println!("[kernel] start other harts.");
+  start_other_harts(hart_id);
   println!("[kernel] run tasks.");
   task::run_tasks();
   panic!("Unreachable in rust_main!");
 }
 
+#[no_mangle]
+/// The rust entry-point of the harts started by the boot hart
+pub fn rust_main_secondary(hart_id: usize) -> ! {
+  mm::KERNEL_SPACE.exclusive_access().activate();
+  trap::init();
+  trap::enable_timer_interrupt();
+  timer::set_next_trigger();
+  println!("[kernel] hart {} started.", hart_id);
+  task::run_tasks();
+  panic!("Unreachable in rust_main_secondary!");
+}
+
+/// Start the other harts, they enter `_start_secondary` in `entry.asm`.
+/// Harts beyond `MAX_HARTS` are left stopped.
+fn start_other_harts(boot_hart_id: usize) {
+  extern "C" {
+    fn _start_secondary();
+  }
+  for hart_id in 0..config::MAX_HARTS {
+    // harts the machine does not have fail to start
+    if hart_id != boot_hart_id {
+      sbi::hart_start(hart_id, _start_secondary as usize);
+    }
+  }
+}
+
 /// Clear BSS segment
 fn clear_bss() {
   extern "C" {
@@ -5,15 +5,15 @@ use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::lazy_static;
 
-use crate::{config::MEMORY_END, sync::UPSafeCell};
+use crate::{config::MEMORY_END, sync::SpinLock};
 type FrameAllocatorImpl = StackFrameAllocator;
 
 use super::{PhysAddr, PhysPageNum};
 
 lazy_static! {
   /// frame allocator instance through lazy_static!
-  pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-    unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+  pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+    unsafe { SpinLock::new(FrameAllocatorImpl::new()) };
 }
 
 trait FrameAllocator {
@@ -41,6 +41,11 @@ pub fn frame_alloc() -> Option<FrameTracker> {
     .map(FrameTracker::new)
 }
 
+/// number of frames left for allocation
+pub fn frame_free_count() -> usize {
+  FRAME_ALLOCATOR.exclusive_access().free_count()
+}
+
 /// deallocate a frame
 pub fn frame_dealloc(ppn: PhysPageNum) {
   FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
@@ -61,6 +66,10 @@ impl StackFrameAllocator {
       self.end - self.current
     );
   }
+
+  pub fn free_count(&self) -> usize {
+    self.end - self.current + self.recycled.len()
+  }
 }
 
 impl FrameAllocator for StackFrameAllocator {
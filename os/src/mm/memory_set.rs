@@ -1,19 +1,23 @@
 use core::arch::asm;
 
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use easy_fs::Inode;
 use lazy_static::lazy_static;
 use riscv::register::satp;
 
 use crate::{
-  config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE},
+  config::{
+    MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_HEAP_BASE,
+  },
   mm::address::StepByOne,
-  sync::UPSafeCell,
+  sync::SpinLock,
+  task::swap_out_any,
 };
 
 use super::{
-  frame_alloc, FrameTracker, PTEFlags, PageTable,
-  PageTableEntry, PhysAddr, PhysPageNum, VPNRange,
-  VirtAddr, VirtPageNum,
+  frame_alloc, frame_free_count, swap::SwapSlot,
+  FrameTracker, PTEFlags, PageTable, PageTableEntry,
+  PhysAddr, PhysPageNum, VPNRange, VirtAddr, VirtPageNum,
 };
 
 extern "C" {
@@ -31,16 +35,53 @@ extern "C" {
 
 lazy_static! {
   /// a memory set instance through `lazy_static!` managing kernel space
-  pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
-    Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+  pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> =
+    Arc::new(unsafe { SpinLock::new(MemorySet::new_kernel()) });
 }
 
 /// map area structure, controls a contiguous piece of virtual memory
 pub struct MapArea {
   vpn_range: VPNRange,
-  data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+  /// frames are reference counted, a frame shared by `fork` stays
+  /// alive until the last address space mapping it is gone.
+  data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+  /// pages evicted to the swap device
+  swapped: BTreeMap<VirtPageNum, SwapSlot>,
   map_type: MapType,
   map_perm: MapPermission,
+  /// the file this area maps, if it is created by `mmap` on a file
+  file: Option<MmapFile>,
+}
+
+/// The file backing a `MapArea` created by `mmap`
+#[derive(Clone)]
+pub struct MmapFile {
+  inode: Arc<Inode>,
+  /// file offset of the first page of the area
+  offset: usize,
+  /// bytes of the file covered by the area, the rest reads as zeros
+  len: usize,
+  /// stores go back to the file (`MAP_SHARED`)
+  shared: bool,
+  /// whether the file is opened writable
+  writable: bool,
+}
+
+impl MmapFile {
+  pub fn new(
+    inode: Arc<Inode>,
+    offset: usize,
+    shared: bool,
+    writable: bool,
+  ) -> Self {
+    Self {
+      inode,
+      offset,
+      len: 0,
+      shared,
+      writable,
+    }
+  }
 }
 
 impl MapArea {
@@ -56,8 +97,10 @@ impl MapArea {
     MapArea {
       vpn_range: VPNRange::new(start_vpn, end_vpn),
       data_frames: BTreeMap::new(),
+      swapped: BTreeMap::new(),
       map_type,
       map_perm,
+      file: None,
     }
   }
 
@@ -68,35 +111,61 @@ impl MapArea {
         another.vpn_range.get_end(),
       ),
       data_frames: BTreeMap::new(),
+      swapped: BTreeMap::new(),
       map_type: another.map_type,
       map_perm: another.map_perm,
+      file: another.file.clone(),
     }
   }
 
+  /// Map the page `vpn`, return `false` if there is no frame for it.
   #[allow(unused)]
   pub fn map_one(
     &mut self,
     page_table: &mut PageTable,
     vpn: VirtPageNum,
-  ) {
+  ) -> bool {
     let ppn: PhysPageNum;
     match self.map_type {
       MapType::Identical => ppn = PhysPageNum(vpn.0),
-      MapType::Framed => {
-        let frame = frame_alloc().unwrap();
+      MapType::Framed | MapType::Lazy => {
+        let frame =
+          if self.map_perm.contains(MapPermission::U) {
+            user_frame_alloc()
+          } else {
+            frame_alloc()
+          };
+        let frame = match frame {
+          Some(frame) => frame,
+          None => return false,
+        };
         ppn = frame.ppn;
-        self.data_frames.insert(vpn, frame);
+        self.data_frames.insert(vpn, Arc::new(frame));
       }
     }
     let pte_flags =
       PTEFlags::from_bits(self.map_perm.bits).unwrap();
     page_table.map(vpn, ppn, pte_flags);
+    true
   }
 
-  pub fn map(&mut self, page_table: &mut PageTable) {
+  /// Map every page of the area, return `false` if memory runs out,
+  /// the pages mapped so far are unmapped again.
+  pub fn map(
+    &mut self,
+    page_table: &mut PageTable,
+  ) -> bool {
+    // lazy pages are mapped on their first access
+    if self.map_type == MapType::Lazy {
+      return true;
+    }
     for vpn in self.vpn_range {
-      self.map_one(page_table, vpn);
+      if !self.map_one(page_table, vpn) {
+        self.unmap(page_table);
+        return false;
+      }
     }
+    true
   }
 
   #[allow(unused)]
@@ -105,8 +174,16 @@ impl MapArea {
     page_table: &mut PageTable,
     vpn: VirtPageNum,
   ) {
-    if self.map_type == MapType::Framed {
-      self.data_frames.remove(&vpn);
+    match self.map_type {
+      MapType::Framed | MapType::Lazy => {
+        // a lazy page never touched or a page swapped out has
+        // nothing mapped, dropping its slot frees the slot
+        if self.data_frames.remove(&vpn).is_none() {
+          self.swapped.remove(&vpn);
+          return;
+        }
+      }
+      MapType::Identical => {}
     }
     page_table.unmap(vpn)
   }
@@ -118,6 +195,234 @@ impl MapArea {
     }
   }
 
+  /// Shrink the area to end at `new_end`, pages beyond are unmapped.
+  pub fn shrink_to(
+    &mut self,
+    page_table: &mut PageTable,
+    new_end: VirtPageNum,
+  ) {
+    for vpn in
+      VPNRange::new(new_end, self.vpn_range.get_end())
+    {
+      self.unmap_one(page_table, vpn);
+    }
+    self.vpn_range =
+      VPNRange::new(self.vpn_range.get_start(), new_end);
+  }
+
+  /// Extend the area to end at `new_end`, return `false` and keep
+  /// the old end if memory runs out.
+  pub fn append_to(
+    &mut self,
+    page_table: &mut PageTable,
+    new_end: VirtPageNum,
+  ) -> bool {
+    let old_end = self.vpn_range.get_end();
+    self.vpn_range =
+      VPNRange::new(self.vpn_range.get_start(), new_end);
+    if self.map_type == MapType::Lazy {
+      return true;
+    }
+    for vpn in VPNRange::new(old_end, new_end) {
+      if !self.map_one(page_table, vpn) {
+        self.shrink_to(page_table, old_end);
+        return false;
+      }
+    }
+    true
+  }
+
+  /// Whether this area is a `MAP_SHARED` file mapping, its frames
+  /// are shared writable by `fork` instead of copy-on-write.
+  fn is_shared(&self) -> bool {
+    self.file.as_ref().map_or(false, |file| file.shared)
+  }
+
+  /// Whether pages of this area are shared copy-on-write by `fork`,
+  /// kernel-only areas such as trap contexts are always copied since
+  /// the kernel writes them through their physical addresses.
+  fn is_cow_shareable(&self) -> bool {
+    self.map_type != MapType::Identical
+      && self.map_perm.contains(MapPermission::U)
+      && !self.is_shared()
+  }
+
+  /// Load the mapped part of the file into the frames of this area.
+  fn load_file(&mut self, page_table: &PageTable) {
+    let file = self.file.as_mut().unwrap();
+    file.len = 0;
+    for (i, vpn) in self.vpn_range.into_iter().enumerate() {
+      let page = page_table.translate(vpn).unwrap().ppn();
+      let len = file.inode.read_at(
+        file.offset + i * PAGE_SIZE,
+        page.get_bytes_array(),
+      );
+      file.len += len;
+      if len < PAGE_SIZE {
+        break;
+      }
+    }
+  }
+
+  /// Write dirty pages of a shared file mapping back to the file.
+  fn sync_file(&self, page_table: &PageTable) {
+    let file = match &self.file {
+      Some(file) if file.shared => file,
+      _ => return,
+    };
+    for (vpn, frame) in self.data_frames.iter() {
+      let dirty = page_table
+        .translate(*vpn)
+        .map_or(false, |pte| pte.dirty());
+      let offset =
+        (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+      if !dirty || offset >= file.len {
+        continue;
+      }
+      let len = PAGE_SIZE.min(file.len - offset);
+      file.inode.write_at(
+        file.offset + offset,
+        &frame.ppn.get_bytes_array()[..len],
+      );
+    }
+  }
+
+  /// Split the area at `vpn`, this one keeps `[start, vpn)` and the
+  /// returned one takes over `[vpn, end)`.
+  fn split_off(&mut self, vpn: VirtPageNum) -> MapArea {
+    let start = self.vpn_range.get_start();
+    let end = self.vpn_range.get_end();
+    let mut file = self.file.clone();
+    if let Some(file) = file.as_mut() {
+      let skipped = (vpn.0 - start.0) * PAGE_SIZE;
+      file.offset += skipped;
+      file.len = file.len.saturating_sub(skipped);
+    }
+    if let Some(file) = self.file.as_mut() {
+      file.len =
+        file.len.min((vpn.0 - start.0) * PAGE_SIZE);
+    }
+    self.vpn_range = VPNRange::new(start, vpn);
+    MapArea {
+      vpn_range: VPNRange::new(vpn, end),
+      data_frames: self.data_frames.split_off(&vpn),
+      swapped: self.swapped.split_off(&vpn),
+      map_type: self.map_type,
+      map_perm: self.map_perm,
+      file,
+    }
+  }
+
+  /// Change the permission of this area and of its mapped pages,
+  /// pages still shared copy-on-write stay read-only.
+  fn set_perm(
+    &mut self,
+    page_table: &mut PageTable,
+    map_perm: MapPermission,
+  ) {
+    self.map_perm = map_perm;
+    let pte_flags =
+      PTEFlags::from_bits(map_perm.bits).unwrap();
+    for (vpn, frame) in self.data_frames.iter() {
+      let mut flags = pte_flags;
+      if !self.is_shared() && Arc::strong_count(frame) > 1 {
+        flags.remove(PTEFlags::W);
+      }
+      page_table.unmap(*vpn);
+      page_table.map(*vpn, frame.ppn, flags);
+    }
+  }
+
+  /// Resolve a page fault at `vpn` inside this area, a lazy page is
+  /// allocated on its first access, a swapped out page is read back
+  /// and a store to a copy-on-write page copies it.
+  fn page_fault(
+    &mut self,
+    page_table: &mut PageTable,
+    vpn: VirtPageNum,
+    is_write: bool,
+  ) -> bool {
+    if self.data_frames.contains_key(&vpn) {
+      return is_write && self.cow_fault(page_table, vpn);
+    }
+    if (self.map_type != MapType::Lazy
+      && !self.swapped.contains_key(&vpn))
+      || (is_write
+        && !self.map_perm.contains(MapPermission::W))
+    {
+      return false;
+    }
+    let frame = match user_frame_alloc() {
+      Some(frame) => frame,
+      None => return false,
+    };
+    if let Some(slot) = self.swapped.remove(&vpn) {
+      slot.read(frame.ppn);
+    }
+    let pte_flags =
+      PTEFlags::from_bits(self.map_perm.bits).unwrap();
+    page_table.map(vpn, frame.ppn, pte_flags);
+    self.data_frames.insert(vpn, Arc::new(frame));
+    true
+  }
+
+  /// Write the resident page `vpn` to the swap device and unmap it,
+  /// `false` if the swap device is full.
+  fn swap_out(
+    &mut self,
+    page_table: &mut PageTable,
+    vpn: VirtPageNum,
+  ) -> bool {
+    let frame = self.data_frames.get(&vpn).unwrap();
+    let slot = match SwapSlot::write(frame.ppn) {
+      Some(slot) => slot,
+      None => return false,
+    };
+    self.data_frames.remove(&vpn);
+    page_table.unmap(vpn);
+    self.swapped.insert(vpn, slot);
+    true
+  }
+
+  /// Resolve a store fault on a copy-on-write page `vpn`.
+  /// The last owner of a shared frame takes it over directly,
+  /// the others get a private copy of it.
+  fn cow_fault(
+    &mut self,
+    page_table: &mut PageTable,
+    vpn: VirtPageNum,
+  ) -> bool {
+    if !self.is_cow_shareable()
+      || !self.map_perm.contains(MapPermission::W)
+    {
+      return false;
+    }
+    let frame = match self.data_frames.get(&vpn) {
+      Some(frame) => frame,
+      None => return false,
+    };
+    let pte_flags =
+      PTEFlags::from_bits(self.map_perm.bits).unwrap();
+    let ppn = if Arc::strong_count(frame) == 1 {
+      frame.ppn
+    } else {
+      let new_frame = match user_frame_alloc() {
+        Some(frame) => frame,
+        None => return false,
+      };
+      new_frame
+        .ppn
+        .get_bytes_array()
+        .copy_from_slice(frame.ppn.get_bytes_array());
+      let ppn = new_frame.ppn;
+      self.data_frames.insert(vpn, Arc::new(new_frame));
+      ppn
+    };
+    page_table.unmap(vpn);
+    page_table.map(vpn, ppn, pte_flags);
+    true
+  }
+
   /// data: start-aligned but maybe with shorter length
   /// assume that all frames were cleared before
   pub fn copy_data(
@@ -151,6 +456,8 @@ impl MapArea {
 pub enum MapType {
   Identical,
   Framed,
+  /// framed, but frames are allocated on the first page fault
+  Lazy,
 }
 
 bitflags! {
@@ -172,6 +479,8 @@ bitflags! {
 pub struct MemorySet {
   page_table: PageTable,
   areas: Vec<MapArea>,
+  /// where the clock of page replacement continues
+  clock_hand: VirtPageNum,
 }
 
 impl MemorySet {
@@ -180,6 +489,7 @@ impl MemorySet {
     MemorySet {
       page_table: PageTable::new(),
       areas: Vec::new(),
+      clock_hand: VirtPageNum(0),
     }
   }
 
@@ -188,13 +498,14 @@ impl MemorySet {
     self.page_table.token()
   }
 
-  /// Assume that no conflicts.
+  /// Assume that no conflicts, return `false` if there is no memory
+  /// for the area.
   pub fn insert_framed_area(
     &mut self,
     start_va: VirtAddr,
     end_va: VirtAddr,
     permission: MapPermission,
-  ) {
+  ) -> bool {
     self.push(
       MapArea::new(
         start_va,
@@ -203,7 +514,177 @@ impl MemorySet {
         permission,
       ),
       None,
+    )
+  }
+
+  /// Assume that no conflicts, frames of the area are allocated
+  /// on demand by [`MemorySet::handle_page_fault`].
+  pub fn insert_lazy_area(
+    &mut self,
+    start_va: VirtAddr,
+    end_va: VirtAddr,
+    permission: MapPermission,
+  ) {
+    self.push(
+      MapArea::new(
+        start_va,
+        end_va,
+        MapType::Lazy,
+        permission,
+      ),
+      None,
+    );
+  }
+
+  /// Map `[start_va, end_va)` to `file` with its data loaded now,
+  /// assume that no conflicts. Return `false` if there is no memory
+  /// for the area.
+  pub fn insert_file_area(
+    &mut self,
+    start_va: VirtAddr,
+    end_va: VirtAddr,
+    permission: MapPermission,
+    file: MmapFile,
+  ) -> bool {
+    let mut map_area = MapArea::new(
+      start_va,
+      end_va,
+      MapType::Framed,
+      permission,
     );
+    map_area.file = Some(file);
+    if !map_area.map(&mut self.page_table) {
+      return false;
+    }
+    map_area.load_file(&self.page_table);
+    self.areas.push(map_area);
+    true
+  }
+
+  /// Find `pages` free pages starting from `hint`, the areas are not
+  /// sorted so just move past every area in the way until none is.
+  pub fn find_free_area(
+    &self,
+    hint: VirtPageNum,
+    pages: usize,
+  ) -> VirtPageNum {
+    let mut start = hint;
+    while let Some(area) = self.areas.iter().find(|area| {
+      area.vpn_range.get_start().0 < start.0 + pages
+        && start < area.vpn_range.get_end()
+    }) {
+      start = area.vpn_range.get_end();
+    }
+    start
+  }
+
+  /// Whether `[start, end)` overlaps any area.
+  pub fn is_overlapped(
+    &self,
+    start: VirtPageNum,
+    end: VirtPageNum,
+  ) -> bool {
+    self.areas.iter().any(|area| {
+      area.vpn_range.get_start() < end
+        && start < area.vpn_range.get_end()
+    })
+  }
+
+  /// Split user areas so that none of them crosses a bound of
+  /// `[start, end)`, then return the indexes of the areas inside.
+  /// Return `None` if the range touches a kernel-only area.
+  fn split_user_areas(
+    &mut self,
+    start: VirtPageNum,
+    end: VirtPageNum,
+  ) -> Option<Vec<usize>> {
+    if self.areas.iter().any(|area| {
+      !area.map_perm.contains(MapPermission::U)
+        && area.vpn_range.get_start() < end
+        && start < area.vpn_range.get_end()
+    }) {
+      return None;
+    }
+    for bound in [start, end] {
+      if let Some(area) =
+        self.areas.iter_mut().find(|area| {
+          area.vpn_range.get_start() < bound
+            && bound < area.vpn_range.get_end()
+        })
+      {
+        let tail = area.split_off(bound);
+        self.areas.push(tail);
+      }
+    }
+    Some(
+      (0..self.areas.len())
+        .filter(|idx| {
+          let range = self.areas[*idx].vpn_range;
+          start <= range.get_start()
+            && range.get_end() <= end
+        })
+        .collect(),
+    )
+  }
+
+  /// Unmap user pages in `[start, end)`, dirty pages of shared file
+  /// mappings are written back first.
+  pub fn munmap(
+    &mut self,
+    start: VirtPageNum,
+    end: VirtPageNum,
+  ) -> bool {
+    let idxs = match self.split_user_areas(start, end) {
+      Some(idxs) => idxs,
+      None => return false,
+    };
+    for idx in idxs.into_iter().rev() {
+      let mut area = self.areas.remove(idx);
+      area.sync_file(&self.page_table);
+      area.unmap(&mut self.page_table);
+    }
+    true
+  }
+
+  /// Change the permission of user pages in `[start, end)`, return
+  /// `false` if some of them are not mapped or the permission is not
+  /// allowed by the mapped file.
+  pub fn mprotect(
+    &mut self,
+    start: VirtPageNum,
+    end: VirtPageNum,
+    permission: MapPermission,
+  ) -> bool {
+    let idxs = match self.split_user_areas(start, end) {
+      Some(idxs) => idxs,
+      None => return false,
+    };
+    let mapped: usize = idxs
+      .iter()
+      .map(|idx| {
+        let range = self.areas[*idx].vpn_range;
+        range.get_end().0 - range.get_start().0
+      })
+      .sum();
+    if mapped != end.0 - start.0
+      || idxs.iter().any(|idx| {
+        self.areas[*idx].file.as_ref().map_or(
+          false,
+          |file| {
+            file.shared
+              && !file.writable
+              && permission.contains(MapPermission::W)
+          },
+        )
+      })
+    {
+      return false;
+    }
+    for idx in idxs {
+      self.areas[idx]
+        .set_perm(&mut self.page_table, permission);
+    }
+    true
   }
 
   /// Remove `MapArea` that starts with `start_vpn`
@@ -221,16 +702,56 @@ impl MemorySet {
     }
   }
 
+  /// Shrink the `MapArea` starting at `start` to end at `new_end`,
+  /// return `false` if there is no such area.
+  pub fn shrink_to(
+    &mut self,
+    start: VirtAddr,
+    new_end: VirtAddr,
+  ) -> bool {
+    if let Some(area) = self.areas.iter_mut().find(|area| {
+      area.vpn_range.get_start() == start.floor()
+    }) {
+      area.shrink_to(&mut self.page_table, new_end.ceil());
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Extend the `MapArea` starting at `start` to end at `new_end`,
+  /// return `false` if there is no such area or no memory for it.
+  pub fn append_to(
+    &mut self,
+    start: VirtAddr,
+    new_end: VirtAddr,
+  ) -> bool {
+    self
+      .areas
+      .iter_mut()
+      .find(|area| {
+        area.vpn_range.get_start() == start.floor()
+      })
+      .map_or(false, |area| {
+        area.append_to(&mut self.page_table, new_end.ceil())
+      })
+  }
+
+  /// Map `map_area` and add it to this space, return `false` and
+  /// drop it if there is no memory for it.
   fn push(
     &mut self,
     mut map_area: MapArea,
     data: Option<&[u8]>,
-  ) {
-    map_area.map(&mut self.page_table);
+  ) -> bool {
+    if !map_area.map(&mut self.page_table) {
+      return false;
+    }
     if let Some(data) = data {
       map_area.copy_data(&mut self.page_table, data);
     }
     self.areas.push(map_area);
+    true
   }
 
   /// Mention that trampoline is not collected by areas.
@@ -324,14 +845,19 @@ impl MemorySet {
           MapPermission::R | MapPermission::W,
         ),
         None,
-      )
+      );
     }
     memory_set
   }
 
   /// Include sections in elf and trampoline and TrapContext and TrapContext and user stack,
   /// also returns `user_sp` and `entry point`.
-  pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+  ///
+  /// Pages of a segment beyond its file data (BSS) are mapped lazily.
+  /// Return `None` if there is no memory for the segments.
+  pub fn from_elf(
+    elf_data: &[u8],
+  ) -> Option<(Self, usize, usize)> {
     let mut memory_set = Self::new_bare();
     // map trampoline
     memory_set.map_trampoline();
@@ -369,44 +895,121 @@ impl MemorySet {
         if ph_flags.is_read() {
           map_perm |= MapPermission::X;
         }
-        let map_area = MapArea::new(
-          start_va,
-          end_va,
-          MapType::Framed,
-          map_perm,
-        );
-        max_end_vpn = map_area.vpn_range.get_end();
-        memory_set.push(
-          map_area,
-          Some(
-            &elf.input[ph.offset() as usize
-              ..(ph.offset() + ph.file_size()) as usize],
-          ),
-        );
+        // pages holding file data are loaded now, the zeroed rest
+        // of the segment is left to demand paging.
+        let file_end_vpn = if ph.file_size() == 0 {
+          start_va.floor()
+        } else {
+          VirtAddr::from(
+            (ph.virtual_addr() + ph.file_size()) as usize,
+          )
+          .ceil()
+        };
+        if start_va.floor() < file_end_vpn
+          && !memory_set.push(
+            MapArea::new(
+              start_va,
+              file_end_vpn.into(),
+              MapType::Framed,
+              map_perm,
+            ),
+            Some(
+              &elf.input[ph.offset() as usize
+                ..(ph.offset() + ph.file_size()) as usize],
+            ),
+          )
+        {
+          return None;
+        }
+        if file_end_vpn < end_va.ceil() {
+          memory_set.push(
+            MapArea::new(
+              file_end_vpn.into(),
+              end_va,
+              MapType::Lazy,
+              map_perm,
+            ),
+            None,
+          );
+        }
+        max_end_vpn = end_va.ceil();
       }
     }
+    // the heap is empty until the program grows it with `sbrk`
+    memory_set.insert_lazy_area(
+      USER_HEAP_BASE.into(),
+      USER_HEAP_BASE.into(),
+      MapPermission::R
+        | MapPermission::W
+        | MapPermission::U,
+    );
     // map user stack with U flags
     let max_end_va: VirtAddr = max_end_vpn.into();
     let mut user_stack_base: usize = max_end_va.into();
     user_stack_base += PAGE_SIZE;
-    (
+    Some((
       memory_set,
       user_stack_base,
       elf.header.pt2.entry_point() as usize,
-    )
+    ))
   }
 
-  /// Clone a same `MemorySet`
+  /// Clone a same `MemorySet`, user pages are shared copy-on-write:
+  /// both spaces map the same frames read-only and the first store
+  /// to such a page copies it in [`MemorySet::handle_page_fault`].
+  /// Return `None` if there is no memory or swap space for the copy.
   pub fn from_existed_user(
-    user_space: &MemorySet,
-  ) -> MemorySet {
+    user_space: &mut MemorySet,
+  ) -> Option<MemorySet> {
     let mut memory_set = Self::new_bare();
     // map trampoline
     memory_set.map_trampoline();
-    // copy data sections/trap_context/user_stack
+    // share data sections/user_stack, copy trap_context
     for area in user_space.areas.iter() {
-      let new_area = MapArea::from_another(area);
-      memory_set.push(new_area, None);
+      let mut new_area = MapArea::from_another(area);
+      if area.is_shared() {
+        // both spaces keep writing the same frames
+        let pte_flags =
+          PTEFlags::from_bits(area.map_perm.bits).unwrap();
+        for (vpn, frame) in area.data_frames.iter() {
+          memory_set
+            .page_table
+            .map(*vpn, frame.ppn, pte_flags);
+          new_area
+            .data_frames
+            .insert(*vpn, Arc::clone(frame));
+        }
+        memory_set.areas.push(new_area);
+        continue;
+      }
+      if area.is_cow_shareable() {
+        let mut pte_flags =
+          PTEFlags::from_bits(area.map_perm.bits).unwrap();
+        pte_flags.remove(PTEFlags::W);
+        for (vpn, frame) in area.data_frames.iter() {
+          // the parent loses its write permission as well,
+          // TLB is flushed when returning to user space.
+          user_space.page_table.unmap(*vpn);
+          user_space
+            .page_table
+            .map(*vpn, frame.ppn, pte_flags);
+          memory_set
+            .page_table
+            .map(*vpn, frame.ppn, pte_flags);
+          new_area
+            .data_frames
+            .insert(*vpn, Arc::clone(frame));
+        }
+        for (vpn, slot) in area.swapped.iter() {
+          new_area.swapped.insert(*vpn, slot.duplicate()?);
+        }
+        memory_set.areas.push(new_area);
+        continue;
+      }
+      memory_set.reserve_frame();
+      if !memory_set.push(new_area, None) {
+        return None;
+      }
       // copy data from another space
       for vpn in area.vpn_range {
         let src_ppn =
@@ -418,7 +1021,107 @@ impl MemorySet {
           .copy_from_slice(src_ppn.get_bytes_array());
       }
     }
-    memory_set
+    Some(memory_set)
+  }
+
+  /// Handle a page fault at `vpn`, return `false` if it is not caused
+  /// by a lazy or copy-on-write page, i.e. a real access violation.
+  pub fn handle_page_fault(
+    &mut self,
+    vpn: VirtPageNum,
+    is_write: bool,
+  ) -> bool {
+    self.reserve_frame();
+    let page_table = &mut self.page_table;
+    self
+      .areas
+      .iter_mut()
+      .find(|area| {
+        area.vpn_range.get_start() <= vpn
+          && vpn < area.vpn_range.get_end()
+      })
+      .map_or(false, |area| {
+        area.page_fault(page_table, vpn, is_write)
+      })
+  }
+
+  /// Get the frame mapped at `vpn` to keep it resident.
+  pub fn pin_page(
+    &self,
+    vpn: VirtPageNum,
+  ) -> Option<Arc<FrameTracker>> {
+    self
+      .areas
+      .iter()
+      .find_map(|area| area.data_frames.get(&vpn))
+      .map(Arc::clone)
+  }
+
+  /// Make frames available for a page fault of this address space,
+  /// or for its kernel-only pages such as trap contexts.
+  /// The faulting process is busy in the kernel and skipped by
+  /// [`swap_out_any`], so its own pages are evicted as the last resort.
+  /// Not for the kernel space, the swap device needs its lock.
+  pub fn reserve_frame(&mut self) {
+    while frame_free_count() < RESERVED_FRAMES {
+      if !swap_out_any() && !self.swap_out_one() {
+        break;
+      }
+    }
+  }
+
+  /// Evict a private user page to the swap device with the enhanced
+  /// clock algorithm. Sweeping from the clock hand, the first pass
+  /// looks for a page neither accessed nor dirty, the second one for
+  /// a page not accessed and clears the accessed bits on its way, so
+  /// that the next two passes always find a victim.
+  pub fn swap_out_one(&mut self) -> bool {
+    let mut candidates: Vec<(usize, VirtPageNum)> =
+      Vec::new();
+    for (i, area) in self.areas.iter().enumerate() {
+      // frames shared with other spaces or pinned by the kernel
+      // are left resident
+      if !area.is_cow_shareable() {
+        continue;
+      }
+      candidates.extend(
+        area
+          .data_frames
+          .iter()
+          .filter(|(_, frame)| {
+            Arc::strong_count(frame) == 1
+          })
+          .map(|(vpn, _)| (i, *vpn)),
+      );
+    }
+    if candidates.is_empty() {
+      return false;
+    }
+    candidates.sort_by_key(|(_, vpn)| *vpn);
+    let hand = candidates
+      .iter()
+      .position(|(_, vpn)| *vpn >= self.clock_hand)
+      .unwrap_or(0);
+    candidates.rotate_left(hand);
+    for pass in 0..4 {
+      for (i, vpn) in candidates.iter() {
+        let flags =
+          self.page_table.translate(*vpn).unwrap().flags();
+        if flags.contains(PTEFlags::A) {
+          if pass % 2 == 1 {
+            self.page_table.clear_accessed(*vpn);
+          }
+          continue;
+        }
+        if pass % 2 == 0 && flags.contains(PTEFlags::D) {
+          continue;
+        }
+        self.clock_hand = VirtPageNum(vpn.0 + 1);
+        return self.areas[*i]
+          .swap_out(&mut self.page_table, *vpn);
+      }
+    }
+    false
   }
 
   /// Refresh TLB with `sfence.vma`
@@ -438,12 +1141,37 @@ impl MemorySet {
     self.page_table.translate(vpn)
   }
 
-  /// Remove all `MapArea`
+  /// Remove all `MapArea`, dirty pages of shared file mappings are
+  /// written back first.
   pub fn recycle_data_pages(&mut self) {
+    for area in self.areas.iter() {
+      area.sync_file(&self.page_table);
+    }
     self.areas.clear();
   }
 }
 
+impl Drop for MemorySet {
+  /// A replaced address space (e.g. by `exec`) writes back its shared
+  /// file mappings as well.
+  fn drop(&mut self) {
+    self.recycle_data_pages();
+  }
+}
+
+/// Frames kept free for page tables and kernel stacks, user pages
+/// are evicted to the swap device before the others run out.
+const RESERVED_FRAMES: usize = 8;
+
+/// Allocate a frame for a user page, pages of other processes are
+/// evicted when physical memory is running out.
+fn user_frame_alloc() -> Option<FrameTracker> {
+  while frame_free_count() < RESERVED_FRAMES
+    && swap_out_any()
+  {}
+  frame_alloc()
+}
+
 /// Get kernel space root ppn
 pub fn kernel_token() -> usize {
   KERNEL_SPACE.exclusive_access().token()
@@ -10,17 +10,20 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod swap;
 
 use address::VPNRange;
 pub use address::{
   PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum,
 };
+use frame_allocator::frame_free_count;
 pub use frame_allocator::{
   frame_alloc, frame_dealloc, FrameTracker,
 };
 pub use memory_set::remap_test;
 pub use memory_set::{
-  kernel_token, MapPermission, MemorySet, KERNEL_SPACE,
+  kernel_token, MapPermission, MemorySet, MmapFile,
+  KERNEL_SPACE,
 };
 use page_table::PTEFlags;
 pub use page_table::{
@@ -41,4 +44,5 @@ pub fn init() {
   println!("---- frame allocator testing end ----");
 
   KERNEL_SPACE.exclusive_access().activate();
+  swap::init();
 }
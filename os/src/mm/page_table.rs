@@ -1,13 +1,16 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
 use alloc::vec::Vec;
-use alloc::{string::String, vec};
+use alloc::{string::String, sync::Arc, vec};
 use bitflags::*;
 
 use super::{
   frame_alloc, FrameTracker, PhysAddr, PhysPageNum,
   StepByOne, VirtAddr, VirtPageNum,
 };
+use crate::task::{
+  current_handle_page_fault, current_pin_page,
+};
 
 // Page Table Entry, PTE
 // 63      54 53    28 27    19 18    10 9   8 7 6 5 4 3 2 1 0
@@ -87,6 +90,11 @@ impl PageTableEntry {
   pub fn executable(&self) -> bool {
     (self.flags() & PTEFlags::X) != PTEFlags::empty()
   }
+
+  /// Check PTE dirty
+  pub fn dirty(&self) -> bool {
+    (self.flags() & PTEFlags::D) != PTEFlags::empty()
+  }
 }
 
 ///Record root ppn and has the same lifetime as 1 and 2 level `PageTableEntry`
@@ -192,6 +200,13 @@ impl PageTable {
     *pte = PageTableEntry::empty();
   }
 
+  /// Clear the accessed bit of the mapping of `vpn`, used by the
+  /// clock of page replacement.
+  pub fn clear_accessed(&mut self, vpn: VirtPageNum) {
+    let pte = self.find_pte(vpn).unwrap();
+    pte.bits &= !(PTEFlags::A.bits as usize);
+  }
+
   /// Translate `VirtPageNum` to `PageTableEntry`
   pub fn translate(
     &self,
@@ -223,14 +238,12 @@ impl PageTable {
 pub struct UserBuffer {
   /// u8 vec
   pub buffers: Vec<&'static mut [u8]>,
+  /// frames of `buffers` pinned against swapping out, since a task
+  /// may sleep with its buffer, e.g. reading an empty pipe.
+  frames: Vec<Arc<FrameTracker>>,
 }
 
 impl UserBuffer {
-  /// Create a `UserBuffer` by parameter
-  pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-    Self { buffers }
-  }
-
   /// Length of `UserBuffer`
   pub fn len(&self) -> usize {
     let mut total: usize = 0;
@@ -247,6 +260,7 @@ impl IntoIterator for UserBuffer {
   fn into_iter(self) -> Self::IntoIter {
     UserBufferIterator {
       buffers: self.buffers,
+      _frames: self.frames,
       current_buffer: 0,
       current_idx: 0,
     }
@@ -256,6 +270,7 @@ impl IntoIterator for UserBuffer {
 /// Iterator of `UserBuffer`
 pub struct UserBufferIterator {
   buffers: Vec<&'static mut [u8]>,
+  _frames: Vec<Arc<FrameTracker>>,
   current_buffer: usize,
   current_idx: usize,
 }
@@ -286,16 +301,18 @@ pub fn translated_byte_buffer(
   token: usize,
   ptr: *const u8,
   len: usize,
-) -> Vec<&'static mut [u8]> {
+) -> UserBuffer {
   let page_table = PageTable::from_token(token);
   let mut start = ptr as usize;
   let end = start + len;
   let mut v: Vec<&'static mut [u8]> = Vec::new();
+  let mut frames: Vec<Arc<FrameTracker>> = Vec::new();
 
   while start < end {
     let start_va = VirtAddr::from(start);
     let mut vpn = start_va.floor();
-    let ppn = page_table.translate(vpn).unwrap().ppn();
+    let ppn = translate_user(&page_table, vpn, true);
+    frames.extend(current_pin_page(vpn));
     vpn.step();
 
     let mut end_va: VirtAddr = vpn.into();
@@ -313,7 +330,51 @@ pub fn translated_byte_buffer(
     }
     start = end_va.into();
   }
-  v
+  UserBuffer { buffers: v, frames }
+}
+
+/// Translate `vpn` for the kernel to access user space. The page
+/// fault the access would raise in user mode is resolved first, so
+/// that a lazy page gets its frame and a write to a copy-on-write
+/// page stays private to the current address space.
+fn translate_user(
+  page_table: &PageTable,
+  vpn: VirtPageNum,
+  is_write: bool,
+) -> PhysPageNum {
+  let accessible = |pte: &PageTableEntry| {
+    pte.is_valid() && (!is_write || pte.writeable())
+  };
+  if !page_table
+    .translate(vpn)
+    .map_or(false, |pte| accessible(&pte))
+  {
+    // buffers of `sys_write` are only read, so a read-only page is
+    // still fine after the write fault fails.
+    if !current_handle_page_fault(vpn, is_write) && is_write
+    {
+      current_handle_page_fault(vpn, false);
+    }
+  }
+  let pte = page_table.find_pte(vpn).unwrap();
+  if is_write && pte.writeable() {
+    // the kernel writes through the physical address, mark the page
+    // dirty as the hardware would do for a store from user mode.
+    pte.bits |= (PTEFlags::A | PTEFlags::D).bits as usize;
+  }
+  pte.ppn()
+}
+
+/// Translate `va` for the kernel to access user space.
+fn translate_user_va(
+  page_table: &PageTable,
+  va: VirtAddr,
+  is_write: bool,
+) -> PhysAddr {
+  let ppn =
+    translate_user(page_table, va.floor(), is_write);
+  let pa: usize = PhysAddr::from(ppn).into();
+  PhysAddr::from(pa + va.page_offset())
 }
 
 /// Translate a pointer to a mutable u8 Vec end with `\0` through page table to a `String`
@@ -325,10 +386,12 @@ pub fn translated_str(
   let mut string = String::new();
   let mut va = ptr as usize;
   loop {
-    let ch: u8 = *(page_table
-      .translate_va(VirtAddr::from(va))
-      .unwrap()
-      .get_mut());
+    let ch: u8 = *translate_user_va(
+      &page_table,
+      VirtAddr::from(va),
+      false,
+    )
+    .get_ref();
     if ch == 0 {
       break;
     } else {
@@ -346,10 +409,12 @@ pub fn translated_ref<T>(
   ptr: *const T,
 ) -> &'static T {
   let page_table = PageTable::from_token(token);
-  page_table
-    .translate_va(VirtAddr::from(ptr as usize))
-    .unwrap()
-    .get_ref()
+  translate_user_va(
+    &page_table,
+    VirtAddr::from(ptr as usize),
+    false,
+  )
+  .get_ref()
 }
 
 /// Translate a generic through page table and return a mutable reference
@@ -358,9 +423,10 @@ pub fn translated_refmut<T>(
   ptr: *mut T,
 ) -> &'static mut T {
   let page_table = PageTable::from_token(token);
-  let va = ptr as usize;
-  page_table
-    .translate_va(VirtAddr::from(va))
-    .unwrap()
-    .get_mut()
+  translate_user_va(
+    &page_table,
+    VirtAddr::from(ptr as usize),
+    true,
+  )
+  .get_mut()
 }
@@ -0,0 +1,118 @@
+//! Swap space on the second virtio block device.
+//!
+//! The device is divided into page sized slots, a [`SwapSlot`] owns
+//! one of them and frees it when dropped, just like a
+//! [`FrameTracker`](super::FrameTracker) does for a frame.
+
+use alloc::vec::Vec;
+use easy_fs::BLOCK_SZ;
+use lazy_static::lazy_static;
+
+use super::PhysPageNum;
+use crate::{
+  config::{PAGE_SIZE, SWAP_PAGES},
+  drivers::SWAP_DEVICE,
+  sync::SpinLock,
+};
+
+/// blocks of the swap device holding one page
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SZ;
+
+lazy_static! {
+  /// swap slot allocator instance through lazy_static!
+  static ref SWAP_ALLOCATOR: SpinLock<SwapAllocator> =
+    unsafe { SpinLock::new(SwapAllocator::new()) };
+}
+
+/// Allocates slots of the swap device, freed slots are reused first.
+struct SwapAllocator {
+  current: usize,
+  recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+  fn new() -> Self {
+    Self {
+      current: 0,
+      recycled: Vec::new(),
+    }
+  }
+
+  fn alloc(&mut self) -> Option<usize> {
+    if let Some(slot) = self.recycled.pop() {
+      Some(slot)
+    } else if self.current == SWAP_PAGES {
+      None
+    } else {
+      self.current += 1;
+      Some(self.current - 1)
+    }
+  }
+
+  fn dealloc(&mut self, slot: usize) {
+    if slot >= self.current
+      || self.recycled.iter().any(|&v| v == slot)
+    {
+      panic!("Swap slot {} has not been allocated!", slot);
+    }
+    self.recycled.push(slot);
+  }
+}
+
+/// A page sized slot of the swap device holding an evicted page.
+pub struct SwapSlot {
+  slot: usize,
+}
+
+impl SwapSlot {
+  /// Write the frame `ppn` to a new slot, `None` if the swap device
+  /// is full.
+  pub fn write(ppn: PhysPageNum) -> Option<Self> {
+    let slot = SWAP_ALLOCATOR.exclusive_access().alloc()?;
+    let page = ppn.get_bytes_array();
+    for (i, block) in page.chunks(BLOCK_SZ).enumerate() {
+      SWAP_DEVICE
+        .write_block(slot * BLOCKS_PER_SLOT + i, block);
+    }
+    Some(Self { slot })
+  }
+
+  /// Read the page of this slot back into the frame `ppn`.
+  pub fn read(&self, ppn: PhysPageNum) {
+    let page = ppn.get_bytes_array();
+    for (i, block) in page.chunks_mut(BLOCK_SZ).enumerate()
+    {
+      SWAP_DEVICE
+        .read_block(self.slot * BLOCKS_PER_SLOT + i, block);
+    }
+  }
+
+  /// Copy the page of this slot to a new slot, used by `fork` for
+  /// pages that are swapped out.
+  pub fn duplicate(&self) -> Option<Self> {
+    let slot = SWAP_ALLOCATOR.exclusive_access().alloc()?;
+    let mut block = [0u8; BLOCK_SZ];
+    for i in 0..BLOCKS_PER_SLOT {
+      SWAP_DEVICE.read_block(
+        self.slot * BLOCKS_PER_SLOT + i,
+        &mut block,
+      );
+      SWAP_DEVICE
+        .write_block(slot * BLOCKS_PER_SLOT + i, &block);
+    }
+    Some(Self { slot })
+  }
+}
+
+impl Drop for SwapSlot {
+  fn drop(&mut self) {
+    SWAP_ALLOCATOR.exclusive_access().dealloc(self.slot);
+  }
+}
+
+/// Bring up the swap device, it allocates its DMA frames now rather
+/// than on the first eviction when no frame is left.
+pub fn init() {
+  lazy_static::initialize(&SWAP_DEVICE);
+  println!("swap space of {} pages.", SWAP_PAGES);
+}
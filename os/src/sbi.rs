@@ -18,6 +18,25 @@ pub fn set_timer(timer: usize) {
   sbi_rt::set_timer(timer as _);
 }
 
+/// use sbi call to start the hart `hart_id` at `start_addr` with
+/// `a0 = hart_id`, return `false` if it can not be started.
+pub fn hart_start(
+  hart_id: usize,
+  start_addr: usize,
+) -> bool {
+  sbi_rt::hart_start(hart_id, start_addr, 0).error == 0
+}
+
+/// use sbi call to flush the TLB entries of `[start, start + size)`
+/// on the harts in `hart_mask`
+pub fn remote_sfence_vma(
+  hart_mask: usize,
+  start: usize,
+  size: usize,
+) {
+  sbi_rt::remote_sfence_vma(hart_mask, 0, start, size);
+}
+
 /// use sbi call to shutdown the kernel
 pub fn shutdown(failure: bool) -> ! {
   use sbi_rt::{
@@ -5,10 +5,10 @@ use crate::task::{
   TaskControlBlock,
 };
 
-use super::{Mutex, UPSafeCell};
+use super::{Mutex, SpinLock};
 
 pub struct Condvar {
-  pub inner: UPSafeCell<CondvarInner>,
+  pub inner: SpinLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -19,7 +19,7 @@ impl Condvar {
   pub fn new() -> Self {
     Self {
       inner: unsafe {
-        UPSafeCell::new(CondvarInner {
+        SpinLock::new(CondvarInner {
           wait_queue: VecDeque::new(),
         })
       },
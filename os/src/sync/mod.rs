@@ -2,8 +2,10 @@
 mod condvar;
 mod mutex;
 mod semaphore;
+mod spin;
 mod up;
 
+pub use self::spin::{SpinLock, SpinLockGuard};
 pub use condvar::Condvar;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
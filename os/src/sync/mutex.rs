@@ -6,7 +6,7 @@ use crate::task::{
   TaskControlBlock,
 };
 
-use super::UPSafeCell;
+use super::SpinLock;
 
 pub trait Mutex: Sync + Send {
   fn lock(&self);
@@ -20,13 +20,13 @@ pub trait Mutex: Sync + Send {
 /// will add into the manager.ready_queue to waiting for next
 /// time being scheduled.
 pub struct MutexSpin {
-  locked: UPSafeCell<bool>,
+  locked: SpinLock<bool>,
 }
 
 impl MutexSpin {
   pub fn new() -> Self {
     Self {
-      locked: unsafe { UPSafeCell::new(false) },
+      locked: unsafe { SpinLock::new(false) },
     }
   }
 }
@@ -62,7 +62,7 @@ impl Mutex for MutexSpin {
 /// is the head of wait_queue in this mutex, current thread(TCB) will recover the
 /// TrapContext and run again.
 pub struct MutexBlocking {
-  inner: UPSafeCell<MutexBlockingInner>,
+  inner: SpinLock<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
@@ -74,7 +74,7 @@ impl MutexBlocking {
   pub fn new() -> Self {
     Self {
       inner: unsafe {
-        UPSafeCell::new(MutexBlockingInner {
+        SpinLock::new(MutexBlockingInner {
           locked: false,
           wait_queue: VecDeque::new(),
         })
@@ -5,10 +5,10 @@ use crate::task::{
   TaskControlBlock,
 };
 
-use super::UPSafeCell;
+use super::SpinLock;
 
 pub struct Semaphore {
-  pub inner: UPSafeCell<SemaphoreInner>,
+  pub inner: SpinLock<SemaphoreInner>,
 }
 
 pub struct SemaphoreInner {
@@ -20,7 +20,7 @@ impl Semaphore {
   pub fn new(res_count: usize) -> Self {
     Self {
       inner: unsafe {
-        UPSafeCell::new(SemaphoreInner {
+        SpinLock::new(SemaphoreInner {
           count: res_count as isize,
           wait_queue: VecDeque::new(),
         })
@@ -0,0 +1,87 @@
+//! Spin lock for data shared by all harts
+use core::{
+  cell::UnsafeCell,
+  hint::spin_loop,
+  ops::{Deref, DerefMut},
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Wrap a static data structure inside it so that harts are able to
+/// access it without any `unsafe`, one at a time.
+///
+/// It takes the place of `UPSafeCell` for data shared between harts:
+/// in order to get mutable reference of inner data, call
+/// `exclusive_access`, which spins until no other hart holds it.
+/// Interrupts are disabled in the kernel, so holding the lock can
+/// not be interrupted on the same hart.
+pub struct SpinLock<T> {
+  locked: AtomicBool,
+  /// inner data
+  inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+  /// User is responsible to guarantee that inner struct may be
+  /// moved between harts.
+  pub const unsafe fn new(value: T) -> Self {
+    Self {
+      locked: AtomicBool::new(false),
+      inner: UnsafeCell::new(value),
+    }
+  }
+
+  /// Exclusive access inner data in SpinLock. Spin until it is not
+  /// held by anyone, a hart accessing it twice deadlocks.
+  pub fn exclusive_access(&self) -> SpinLockGuard<'_, T> {
+    loop {
+      if let Some(guard) = self.try_exclusive_access() {
+        return guard;
+      }
+      while self.locked.load(Ordering::Relaxed) {
+        spin_loop();
+      }
+    }
+  }
+
+  /// Exclusive access inner data in SpinLock, or `None` if it is held.
+  pub fn try_exclusive_access(
+    &self,
+  ) -> Option<SpinLockGuard<'_, T>> {
+    self
+      .locked
+      .compare_exchange(
+        false,
+        true,
+        Ordering::Acquire,
+        Ordering::Relaxed,
+      )
+      .ok()
+      .map(|_| SpinLockGuard { lock: self })
+  }
+}
+
+/// The inner data of a [`SpinLock`], released when dropped.
+pub struct SpinLockGuard<'a, T> {
+  lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    unsafe { &*self.lock.inner.get() }
+  }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.lock.inner.get() }
+  }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+  fn drop(&mut self) {
+    self.lock.locked.store(false, Ordering::Release);
+  }
+}
@@ -4,7 +4,8 @@ use core::cell::{RefCell, RefMut};
 /// Wrap a static data structure inside it so that we are
 /// able to access it without any `unsafe`.
 ///
-/// We should only use it in UniProcessor.
+/// We should only use it in UniProcessor, or for data only accessed by
+/// its own hart such as a per-hart `Processor`.
 ///
 /// In order to get mutable reference of inner data, call
 /// `exclusive_access`.
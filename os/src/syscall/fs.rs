@@ -1,14 +1,22 @@
 //! File and filesystem-related syscalls
 
-use alloc::sync::Arc;
+use alloc::{string::String, sync::Arc};
+use easy_fs::Inode;
 
-use crate::fs::{make_pipe, open_file, OpenFlags};
+use crate::fs::{
+  dir_path, find_inode, find_parent, make_dir, make_pipe,
+  open_file, set_foreground_pgid, OpenFlags, Stat,
+};
 use crate::mm::{
-  translated_byte_buffer, translated_refmut,
-  translated_str, UserBuffer,
+  translated_byte_buffer, translated_refmut, translated_str,
 };
 use crate::task::{current_process, current_user_token};
 
+/// `dirfd` referring to the current working directory
+const AT_FDCWD: isize = -100;
+/// `unlinkat` flag to remove a directory instead of a file
+const AT_REMOVEDIR: u32 = 0x200;
+
 /// write buf of length `len` to a file with `fd`
 pub fn sys_write(
   fd: usize,
@@ -28,9 +36,13 @@ pub fn sys_write(
     let file = file.clone();
     // release current task TCB manually to avoid multi-borrow
     drop(inner);
-    file.write(UserBuffer::new(translated_byte_buffer(
-      token, buf, len,
-    ))) as isize
+    match file
+      .write(translated_byte_buffer(token, buf, len))
+    {
+      // the file is too large to write any of it (EFBIG)
+      0 if len > 0 => -1,
+      size => size as isize,
+    }
   } else {
     -1
   }
@@ -55,19 +67,107 @@ pub fn sys_read(
     }
     // release current task TCB manually to avoid multi-borrow
     drop(inner);
-    file.read(UserBuffer::new(translated_byte_buffer(
-      token, buf, len,
-    ))) as isize
+    file.read(translated_byte_buffer(token, buf, len))
+      as isize
+  } else {
+    -1
+  }
+}
+
+/// Move the offset of the file opened as `fd` as `File::seek` does,
+/// return the new offset or -1.
+pub fn sys_lseek(
+  fd: usize,
+  offset: isize,
+  whence: usize,
+) -> isize {
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  match file.seek(offset, whence) {
+    Some(offset) => offset as isize,
+    None => -1,
+  }
+}
+
+/// Set the size of the file opened as `fd` to `len`, the range past
+/// the old end reads as zeros.
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  if file.truncate(len) {
+    0
   } else {
     -1
   }
 }
 
+/// Read from the file opened as `fd` at `offset` into `buf` without
+/// moving its offset.
+pub fn sys_pread64(
+  fd: usize,
+  buf: *const u8,
+  len: usize,
+  offset: usize,
+) -> isize {
+  let token = current_user_token();
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) if file.readable() => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  match file
+    .pread(translated_byte_buffer(token, buf, len), offset)
+  {
+    Some(size) => size as isize,
+    None => -1,
+  }
+}
+
+/// Write `buf` to the file opened as `fd` at `offset` without moving
+/// its offset.
+pub fn sys_pwrite64(
+  fd: usize,
+  buf: *const u8,
+  len: usize,
+  offset: usize,
+) -> isize {
+  let token = current_user_token();
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) if file.writable() => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  match file
+    .pwrite(translated_byte_buffer(token, buf, len), offset)
+  {
+    Some(0) if len > 0 => -1,
+    Some(size) => size as isize,
+    None => -1,
+  }
+}
+
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
   let process = current_process();
   let token = current_user_token();
   let path = translated_str(token, path);
+  let cwd =
+    Arc::clone(&process.inner_exclusive_access().cwd);
   if let Some(inode) = open_file(
+    &cwd,
     path.as_str(),
     OpenFlags::from_bits(flags).unwrap(),
   ) {
@@ -105,6 +205,8 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
   inner.fd_table[read_fd] = Some(pipe_read);
   let write_fd = inner.alloc_fd();
   inner.fd_table[write_fd] = Some(pipe_write);
+  // release current PCB before writing into user space.
+  drop(inner);
 
   // writing read_end and write_end to the corresponding application space address.
   *translated_refmut(token, pipe) = read_fd;
@@ -128,3 +230,276 @@ pub fn sys_dup(fd: usize) -> isize {
     Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
   new_fd as isize
 }
+
+/// Fill `st` with the status of the file opened as `fd`, fail if it is
+/// not on the file system.
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+  let token = current_user_token();
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  match file.stat() {
+    Some(stat) => {
+      *translated_refmut(token, st) = stat;
+      0
+    }
+    None => -1,
+  }
+}
+
+/// Fill `buf` with `linux_dirent64` records of the directory opened as
+/// `fd` from its offset, return the number of bytes filled, 0 at the
+/// end of the directory, or -1 if `buf` cannot hold the next record.
+pub fn sys_getdents64(
+  fd: usize,
+  buf: *mut u8,
+  len: usize,
+) -> isize {
+  let token = current_user_token();
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  let file = match inner.fd_table.get(fd) {
+    Some(Some(file)) => file.clone(),
+    _ => return -1,
+  };
+  drop(inner);
+  let records = match file.getdents(len) {
+    Some(records) => records,
+    None => return -1,
+  };
+  let buffer =
+    translated_byte_buffer(token, buf, records.len());
+  for (dst, src) in buffer.into_iter().zip(records.iter()) {
+    unsafe {
+      *dst = *src;
+    }
+  }
+  records.len() as isize
+}
+
+/// Create a directory at `path`.
+pub fn sys_mkdir(path: *const u8) -> isize {
+  let token = current_user_token();
+  let path = translated_str(token, path);
+  let process = current_process();
+  let cwd =
+    Arc::clone(&process.inner_exclusive_access().cwd);
+  if make_dir(&cwd, &path).is_some() {
+    0
+  } else {
+    -1
+  }
+}
+
+/// Change the current working directory to `path`.
+pub fn sys_chdir(path: *const u8) -> isize {
+  let token = current_user_token();
+  let path = translated_str(token, path);
+  let process = current_process();
+  let cwd =
+    Arc::clone(&process.inner_exclusive_access().cwd);
+  match find_inode(&cwd, &path) {
+    Some(inode) if inode.is_dir() => {
+      process.inner_exclusive_access().cwd = inode;
+      0
+    }
+    _ => -1,
+  }
+}
+
+/// Copy the path of the current working directory with a trailing `\0`
+/// into `buf`, return its length, or -1 if `buf` is too small or the
+/// directory has been removed.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+  let token = current_user_token();
+  let process = current_process();
+  let cwd =
+    Arc::clone(&process.inner_exclusive_access().cwd);
+  let mut cwd = match dir_path(&cwd) {
+    Some(path) => path,
+    None => return -1,
+  };
+  cwd.push('\0');
+  if cwd.len() > len {
+    return -1;
+  }
+  let buffer =
+    translated_byte_buffer(token, buf, cwd.len());
+  for (dst, src) in buffer.into_iter().zip(cwd.bytes()) {
+    unsafe {
+      *dst = src;
+    }
+  }
+  cwd.len() as isize
+}
+
+/// Find the parent directory of `path` and its last component, a
+/// relative `path` is taken from the directory opened as `dirfd` or
+/// from the current working directory if `dirfd` is `AT_FDCWD`.
+fn find_parent_at(
+  dirfd: isize,
+  path: &str,
+) -> Option<(Arc<Inode>, String)> {
+  let process = current_process();
+  let inner = process.inner_exclusive_access();
+  if dirfd == AT_FDCWD || path.starts_with('/') {
+    let cwd = Arc::clone(&inner.cwd);
+    drop(inner);
+    find_parent(&cwd, path)
+  } else {
+    let dir = inner
+      .fd_table
+      .get(dirfd as usize)?
+      .as_ref()?
+      .inode()?;
+    drop(inner);
+    if !dir.is_dir() {
+      return None;
+    }
+    find_parent(&dir, path)
+  }
+}
+
+/// Remove the file at `path`, or the empty directory at `path` if
+/// `flags` contains `AT_REMOVEDIR`.
+pub fn sys_unlinkat(
+  dirfd: isize,
+  path: *const u8,
+  flags: u32,
+) -> isize {
+  let token = current_user_token();
+  let path = translated_str(token, path);
+  let (parent, name) = match find_parent_at(dirfd, &path) {
+    Some(found) => found,
+    None => return -1,
+  };
+  let removed = if flags & AT_REMOVEDIR != 0 {
+    parent.rmdir(&name)
+  } else {
+    parent.unlink(&name)
+  };
+  if removed {
+    0
+  } else {
+    -1
+  }
+}
+
+/// Add a hard link `new_path` to the file at `old_path`, each of them
+/// is taken relative to its own `dirfd` like `sys_unlinkat`.
+pub fn sys_linkat(
+  old_dirfd: isize,
+  old_path: *const u8,
+  new_dirfd: isize,
+  new_path: *const u8,
+  _flags: u32,
+) -> isize {
+  let token = current_user_token();
+  let old_path = translated_str(token, old_path);
+  let new_path = translated_str(token, new_path);
+  let inode = match find_parent_at(old_dirfd, &old_path)
+    .and_then(|(parent, name)| parent.find(&name))
+  {
+    Some(inode) => inode,
+    None => return -1,
+  };
+  let (new_parent, new_name) =
+    match find_parent_at(new_dirfd, &new_path) {
+      Some(found) => found,
+      None => return -1,
+    };
+  if new_parent.link(&new_name, &inode) {
+    0
+  } else {
+    -1
+  }
+}
+
+/// Move the entry at `old_path` to `new_path`, each of them is taken
+/// relative to its own `dirfd` like `sys_unlinkat`.
+pub fn sys_renameat(
+  old_dirfd: isize,
+  old_path: *const u8,
+  new_dirfd: isize,
+  new_path: *const u8,
+) -> isize {
+  let token = current_user_token();
+  let old_path = translated_str(token, old_path);
+  let new_path = translated_str(token, new_path);
+  let (old_parent, old_name) =
+    match find_parent_at(old_dirfd, &old_path) {
+      Some(found) => found,
+      None => return -1,
+    };
+  let (new_parent, new_name) =
+    match find_parent_at(new_dirfd, &new_path) {
+      Some(found) => found,
+      None => return -1,
+    };
+  if old_parent.rename(&old_name, &new_parent, &new_name) {
+    0
+  } else {
+    -1
+  }
+}
+
+/// Create a symbolic link at `path` to `target`, which is not required
+/// to exist, `path` is taken relative to `dirfd` like `sys_unlinkat`.
+pub fn sys_symlinkat(
+  target: *const u8,
+  dirfd: isize,
+  path: *const u8,
+) -> isize {
+  let token = current_user_token();
+  let target = translated_str(token, target);
+  let path = translated_str(token, path);
+  match find_parent_at(dirfd, &path) {
+    Some((parent, name)) if !target.is_empty() => {
+      if parent.symlink(&name, &target).is_some() {
+        0
+      } else {
+        -1
+      }
+    }
+    _ => -1,
+  }
+}
+
+/// Copy the target of the symbolic link at `path` into `buf` without a
+/// trailing `\0`, truncated to `len` bytes, return the number of bytes
+/// copied, or -1 if it is not a symbolic link.
+pub fn sys_readlinkat(
+  dirfd: isize,
+  path: *const u8,
+  buf: *mut u8,
+  len: usize,
+) -> isize {
+  let token = current_user_token();
+  let path = translated_str(token, path);
+  let target = match find_parent_at(dirfd, &path)
+    .and_then(|(parent, name)| parent.find(&name))
+    .and_then(|inode| inode.read_link())
+  {
+    Some(target) => target,
+    None => return -1,
+  };
+  let len = target.len().min(len);
+  let buffer = translated_byte_buffer(token, buf, len);
+  for (dst, src) in buffer.into_iter().zip(target.bytes()) {
+    unsafe {
+      *dst = src;
+    }
+  }
+  len as isize
+}
+
+/// Put the process group `pgid` in the foreground of the console, it
+/// receives `SIGINT` and `SIGTSTP` when `^C` and `^Z` are typed.
+pub fn sys_tcsetpgrp(pgid: usize) -> isize {
+  set_foreground_pgid(pgid);
+  0
+}
@@ -19,20 +19,48 @@ pub use process::*;
 pub use sync::*;
 pub use thread::*;
 
+use crate::fs::Stat;
+use crate::task::SignalAction;
+
+const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_MKDIR: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_FTRUNCATE: usize = 46;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_GET_PRIORITY: usize = 141;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -46,39 +74,126 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
+const SYSCALL_SBRK: usize = 1040;
+const SYSCALL_SCHED_STAT: usize = 1050;
+const SYSCALL_TCSETPGRP: usize = 1060;
 
 /// handle syscall exception with `syscall_id` and other arguments.
 pub fn syscall(
   syscall_id: usize,
-  args: [usize; 3],
+  args: [usize; 6],
 ) -> isize {
   match syscall_id {
+    SYSCALL_GETCWD => {
+      sys_getcwd(args[0] as *mut u8, args[1])
+    }
     SYSCALL_DUP => sys_dup(args[0]),
+    SYSCALL_MKDIR => sys_mkdir(args[0] as *const u8),
+    SYSCALL_UNLINKAT => sys_unlinkat(
+      args[0] as isize,
+      args[1] as *const u8,
+      args[2] as u32,
+    ),
+    SYSCALL_SYMLINKAT => sys_symlinkat(
+      args[0] as *const u8,
+      args[1] as isize,
+      args[2] as *const u8,
+    ),
+    SYSCALL_LINKAT => sys_linkat(
+      args[0] as isize,
+      args[1] as *const u8,
+      args[2] as isize,
+      args[3] as *const u8,
+      args[4] as u32,
+    ),
+    SYSCALL_RENAMEAT => sys_renameat(
+      args[0] as isize,
+      args[1] as *const u8,
+      args[2] as isize,
+      args[3] as *const u8,
+    ),
+    SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1]),
+    SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
     SYSCALL_OPEN => {
       sys_open(args[0] as *const u8, args[1] as u32)
     }
     SYSCALL_CLOSE => sys_close(args[0]),
     SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
+    SYSCALL_GETDENTS64 => {
+      sys_getdents64(args[0], args[1] as *mut u8, args[2])
+    }
+    SYSCALL_LSEEK => {
+      sys_lseek(args[0], args[1] as isize, args[2])
+    }
     SYSCALL_READ => {
       sys_read(args[0], args[1] as *const u8, args[2])
     }
     SYSCALL_WRITE => {
       sys_write(args[0], args[1] as *const u8, args[2])
     }
+    SYSCALL_PREAD64 => sys_pread64(
+      args[0],
+      args[1] as *const u8,
+      args[2],
+      args[3],
+    ),
+    SYSCALL_PWRITE64 => sys_pwrite64(
+      args[0],
+      args[1] as *const u8,
+      args[2],
+      args[3],
+    ),
+    SYSCALL_READLINKAT => sys_readlinkat(
+      args[0] as isize,
+      args[1] as *const u8,
+      args[2] as *mut u8,
+      args[3],
+    ),
+    SYSCALL_FSTAT => {
+      sys_fstat(args[0], args[1] as *mut Stat)
+    }
     SYSCALL_EXIT => sys_exit(args[0] as i32),
     SYSCALL_SLEEP => sys_sleep(args[0]),
     SYSCALL_YIELD => sys_yield(),
     SYSCALL_KILL => sys_kill(args[0], args[1] as u32),
+    SYSCALL_SIGACTION => sys_sigaction(
+      args[0] as i32,
+      args[1] as *const SignalAction,
+      args[2] as *mut SignalAction,
+    ),
+    SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
+    SYSCALL_SIGRETURN => sys_sigreturn(),
+    SYSCALL_SET_PRIORITY => {
+      sys_set_priority(args[0] as isize)
+    }
+    SYSCALL_GET_PRIORITY => sys_get_priority(),
+    SYSCALL_SETPGID => sys_setpgid(args[0], args[1]),
+    SYSCALL_GETPGID => sys_getpgid(args[0]),
     SYSCALL_GET_TIME => sys_get_time(),
     SYSCALL_GETPID => sys_getpid(),
+    SYSCALL_BRK => sys_brk(args[0]),
+    SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
     SYSCALL_FORK => sys_fork(),
     SYSCALL_EXEC => sys_exec(
       args[0] as *const u8,
       args[1] as *const usize,
     ),
-    SYSCALL_WAITPID => {
-      sys_waitpid(args[0] as isize, args[1] as *mut i32)
+    SYSCALL_MMAP => sys_mmap(
+      args[0],
+      args[1],
+      args[2] as u32,
+      args[3] as u32,
+      args[4],
+      args[5],
+    ),
+    SYSCALL_MPROTECT => {
+      sys_mprotect(args[0], args[1], args[2] as u32)
     }
+    SYSCALL_WAITPID => sys_waitpid(
+      args[0] as isize,
+      args[1] as *mut i32,
+      args[2] as u32,
+    ),
     SYSCALL_THREAD_CREATE => {
       sys_thread_create(args[0], args[1])
     }
@@ -97,6 +212,11 @@ pub fn syscall(
     SYSCALL_CONDVAR_WAIT => {
       sys_condvar_wait(args[0], args[1])
     }
+    SYSCALL_SBRK => sys_sbrk(args[0] as isize),
+    SYSCALL_SCHED_STAT => {
+      sys_sched_stat(args[0] as *mut SchedStat)
+    }
+    SYSCALL_TCSETPGRP => sys_tcsetpgrp(args[0]),
     _ => panic!("Unsupported syscall_id: {}", syscall_id),
   }
 }
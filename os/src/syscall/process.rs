@@ -2,12 +2,17 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
 
 use crate::{
-  fs::{open_file, OpenFlags},
-  mm::{translated_ref, translated_refmut, translated_str},
+  config::{PAGE_SIZE, USER_MMAP_BASE, USER_MMAP_TOP},
+  fs::{open_file, OpenFlags, ROOT_INODE},
+  mm::{
+    translated_ref, translated_refmut, translated_str,
+    MapPermission, MmapFile, VirtAddr, VirtPageNum,
+  },
   task::{
     current_process, current_task, current_user_token,
     exit_current_and_run_next, pid2process,
-    suspend_current_and_run_next, SignalFlags,
+    suspend_current_and_run_next, SignalAction,
+    SignalFlags, WaitEvent, MIN_PRIORITY,
   },
   timer::get_time_ms,
 };
@@ -19,6 +24,20 @@ pub struct TimeVal {
   pub usec: usize,
 }
 
+/// Scheduling statistics of a thread filled by `sched_stat`
+#[repr(C)]
+#[derive(Debug)]
+pub struct SchedStat {
+  /// Time in milliseconds the thread has run before it was last
+  /// switched out
+  pub run_time: usize,
+  /// Number of times the thread has been scheduled
+  pub dispatch_count: usize,
+  /// Queue level of MLFQ scheduling, 0 is the highest and the only
+  /// one under stride scheduling
+  pub level: usize,
+}
+
 /// task exits and submit an exit code.
 pub fn sys_exit(exit_code: i32) -> ! {
   exit_current_and_run_next(exit_code);
@@ -36,6 +55,40 @@ pub fn sys_get_time() -> isize {
   get_time_ms() as isize
 }
 
+/// set the scheduling priority of the current thread, it must be at
+/// least [`MIN_PRIORITY`], return the new priority or -1.
+pub fn sys_set_priority(priority: isize) -> isize {
+  if priority < MIN_PRIORITY as isize {
+    return -1;
+  }
+  current_task()
+    .unwrap()
+    .inner_exclusive_access()
+    .priority = priority as usize;
+  priority
+}
+
+/// get the scheduling priority of the current thread
+pub fn sys_get_priority() -> isize {
+  current_task().unwrap().inner_exclusive_access().priority
+    as isize
+}
+
+/// get the scheduling statistics of the current thread
+pub fn sys_sched_stat(stat: *mut SchedStat) -> isize {
+  let token = current_user_token();
+  let task = current_task().unwrap();
+  let inner = task.inner_exclusive_access();
+  let sched_stat = SchedStat {
+    run_time: inner.run_time,
+    dispatch_count: inner.dispatch_count,
+    level: inner.level,
+  };
+  drop(inner);
+  *translated_refmut(token, stat) = sched_stat;
+  0
+}
+
 pub fn sys_getpid() -> isize {
   current_task()
     .unwrap()
@@ -47,7 +100,10 @@ pub fn sys_getpid() -> isize {
 
 pub fn sys_fork() -> isize {
   let current_process = current_process();
-  let new_process = current_process.fork();
+  let new_process = match current_process.fork() {
+    Some(new_process) => new_process,
+    None => return -1,
+  };
   let new_pid = new_process.getpid();
   // modify trap context of new_task, because it returns immediately immediately after switching
   let new_process_inner =
@@ -82,13 +138,26 @@ pub fn sys_exec(
     }
   }
 
-  if let Some(app_inode) =
-    open_file(path.as_str(), OpenFlags::RDONLY)
-  {
+  let process = current_process();
+  let cwd =
+    Arc::clone(&process.inner_exclusive_access().cwd);
+  // apps live in the root directory, a bare name finds them from
+  // any working directory.
+  let app_inode = open_file(&cwd, &path, OpenFlags::RDONLY)
+    .or_else(|| {
+      if path.contains('/') {
+        None
+      } else {
+        open_file(&ROOT_INODE, &path, OpenFlags::RDONLY)
+      }
+    })
+    .filter(|inode| !inode.is_dir());
+  if let Some(app_inode) = app_inode {
     let all_data = app_inode.read_all();
-    let process = current_process();
     let argc = args_vec.len();
-    process.exec(all_data.as_slice(), args_vec);
+    if !process.exec(all_data.as_slice(), args_vec) {
+      return -1;
+    }
     // return arc because cx.x[10]  will be covered with it later
     argc as isize
   } else {
@@ -96,12 +165,42 @@ pub fn sys_exec(
   }
 }
 
+bitflags! {
+  /// Options of `waitpid`
+  pub struct WaitFlags: u32 {
+    /// Return at once if no child has changed, the caller loops anyway
+    const NOHANG = 1 << 0;
+    /// Also report children which have stopped
+    const UNTRACED = 1 << 1;
+    /// Also report stopped children which have been continued
+    const CONTINUED = 1 << 3;
+    /// Write the status encoded as in POSIX even without `UNTRACED`
+    /// or `CONTINUED`
+    const STATUS = 1 << 16;
+  }
+}
+
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
+///
+/// With [`WaitFlags::UNTRACED`] or [`WaitFlags::CONTINUED`] stops and
+/// continues are reported as well. With them or [`WaitFlags::STATUS`]
+/// a status encoded as in POSIX is written instead: `code << 8` for an exit, `signum` for a death by
+/// signal, `signum << 8 | 0x7f` for a stop and `0xffff` for a continue.
 pub fn sys_waitpid(
   pid: isize,
   exit_code_ptr: *mut i32,
+  options: u32,
 ) -> isize {
+  let options = match WaitFlags::from_bits(options) {
+    Some(options) => options,
+    None => return -1,
+  };
+  let encode = options.intersects(
+    WaitFlags::UNTRACED
+      | WaitFlags::CONTINUED
+      | WaitFlags::STATUS,
+  );
   let process = current_process();
   // find a child process
 
@@ -117,37 +216,331 @@ pub fn sys_waitpid(
   let pair =
     inner.children.iter().enumerate().find(|(_, p)| {
       // ++++ temporarily access child PCB lock exclusively
+      // the exiting hart (or one briefly looking the child up) may
+      // still hold the child, it is reaped once only the children
+      // list refers to it.
       p.inner_exclusive_access().is_zombie
+        && Arc::strong_count(p) == 1
         && (pid == -1 || pid as usize == p.getpid())
       // ++++ release child PCB
     });
-  if let Some((idx, _)) = pair {
+  let (found_pid, status) = if let Some((idx, _)) = pair {
     let child = inner.children.remove(idx);
-    // confirm that child will be deallocated after removing from children list
-    assert_eq!(Arc::strong_count(&child), 1);
-    let found_pid = child.getpid();
     // ++++ temporarily access child PCB exclusively
     let exit_code =
       child.inner_exclusive_access().exit_code;
     // ++++ release child PCB
-    *translated_refmut(
-      inner.memory_set.token(),
-      exit_code_ptr,
-    ) = exit_code;
-    found_pid as isize
+    let term_signal =
+      child.inner_exclusive_access().term_signal;
+    let status = match term_signal {
+      Some(signum) if encode => signum as i32 & 0x7f,
+      _ if encode => (exit_code & 0xff) << 8,
+      _ => exit_code,
+    };
+    (child.getpid(), status)
+  } else if let Some((found_pid, event)) =
+    inner.children.iter().find_map(|p| {
+      if pid != -1 && pid as usize != p.getpid() {
+        return None;
+      }
+      let mut child_inner = p.inner_exclusive_access();
+      let event = match child_inner.wait_event {
+        Some(WaitEvent::Stopped(signum))
+          if options.contains(WaitFlags::UNTRACED) =>
+        {
+          (signum as i32) << 8 | 0x7f
+        }
+        Some(WaitEvent::Continued)
+          if options.contains(WaitFlags::CONTINUED) =>
+        {
+          0xffff
+        }
+        _ => return None,
+      };
+      child_inner.wait_event = None;
+      Some((p.getpid(), event))
+    })
+  {
+    (found_pid, event)
   } else {
-    -2
-  }
+    return -2;
+  };
+  let token = inner.memory_set.token();
+  // release current PCB since writing into a copy-on-write
+  // page of user space needs to access it again.
+  drop(inner);
+  *translated_refmut(token, exit_code_ptr) = status;
+  found_pid as isize
   // ---- release current PCB lock automatically
 }
 
+/// Set the end of the heap to `addr`, and return the new end.
+/// Passing 0 only queries the current end, -1 is returned if `addr`
+/// is out of the heap range.
+pub fn sys_brk(addr: usize) -> isize {
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  let old_brk = inner.program_brk;
+  if addr == 0 {
+    return old_brk as isize;
+  }
+  if !inner.set_program_brk(addr) {
+    return -1;
+  }
+  drop(inner);
+  if addr < old_brk {
+    process.flush_remote_tlb(addr, old_brk - addr);
+  }
+  addr as isize
+}
+
+/// Grow (or shrink with a negative `increment`) the heap, and return
+/// the old end of the heap, or -1 if the new end is out of range.
+pub fn sys_sbrk(increment: isize) -> isize {
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  let old_brk = inner.program_brk;
+  let new_brk = old_brk as isize + increment;
+  if new_brk < 0 || !inner.set_program_brk(new_brk as usize)
+  {
+    return -1;
+  }
+  drop(inner);
+  if increment < 0 {
+    process.flush_remote_tlb(
+      new_brk as usize,
+      old_brk - new_brk as usize,
+    );
+  }
+  old_brk as isize
+}
+
+bitflags! {
+  /// Protection of a memory mapping
+  pub struct MmapProt: u32 {
+    const READ = 1 << 0;
+    const WRITE = 1 << 1;
+    const EXEC = 1 << 2;
+  }
+}
+
+bitflags! {
+  /// Flags of a memory mapping
+  pub struct MmapFlags: u32 {
+    /// Stores are visible to other mappings and go back to the file
+    const SHARED = 1 << 0;
+    /// Stores are private to this mapping
+    const PRIVATE = 1 << 1;
+    /// Place the mapping exactly at the given address
+    const FIXED = 1 << 4;
+    /// Not backed by any file, pages are zeroed
+    const ANONYMOUS = 1 << 5;
+  }
+}
+
+impl MmapProt {
+  /// Return the `MapPermission` for user pages, RISC-V does not allow
+  /// write-only pages so writable ones are readable as well.
+  /// `PROT_NONE` is not supported.
+  fn map_permission(&self) -> Option<MapPermission> {
+    if self.is_empty() {
+      return None;
+    }
+    let mut map_perm = MapPermission::U;
+    if self.intersects(MmapProt::READ | MmapProt::WRITE) {
+      map_perm |= MapPermission::R;
+    }
+    if self.contains(MmapProt::WRITE) {
+      map_perm |= MapPermission::W;
+    }
+    if self.contains(MmapProt::EXEC) {
+      map_perm |= MapPermission::X;
+    }
+    Some(map_perm)
+  }
+}
+
+/// Return the page range of `[addr, addr + len)` in user space, or
+/// `None` if `addr` is not page aligned or the range is out of it.
+fn user_page_range(
+  addr: usize,
+  len: usize,
+) -> Option<(VirtPageNum, VirtPageNum)> {
+  if addr % PAGE_SIZE != 0
+    || len == 0
+    || addr >= USER_MMAP_TOP
+    || len > USER_MMAP_TOP - addr
+  {
+    return None;
+  }
+  Some((
+    VirtAddr::from(addr).floor(),
+    VirtAddr::from(addr + len).ceil(),
+  ))
+}
+
+/// Map `len` bytes of the file `fd` from `offset`, or zeroed memory
+/// with `MAP_ANONYMOUS`, into the address space. `addr` is a hint of
+/// the start address, unless `MAP_FIXED` is given, in which case the
+/// range must not be mapped yet. Mappings stay in
+/// `[USER_MMAP_BASE, USER_MMAP_TOP)`, clear of the heap and the user
+/// stacks of threads created later.
+/// Return the start address of the mapping, or -1 on failure.
+pub fn sys_mmap(
+  addr: usize,
+  len: usize,
+  prot: u32,
+  flags: u32,
+  fd: usize,
+  offset: usize,
+) -> isize {
+  let map_perm = match MmapProt::from_bits(prot)
+    .and_then(|prot| prot.map_permission())
+  {
+    Some(map_perm) => map_perm,
+    None => return -1,
+  };
+  let flags = match MmapFlags::from_bits(flags) {
+    Some(flags) => flags,
+    None => return -1,
+  };
+  let shared = flags.contains(MmapFlags::SHARED);
+  // exactly one of `MAP_SHARED` and `MAP_PRIVATE`,
+  // anonymous shared mappings are not supported.
+  if shared == flags.contains(MmapFlags::PRIVATE)
+    || (shared && flags.contains(MmapFlags::ANONYMOUS))
+    || offset % PAGE_SIZE != 0
+    || len == 0
+  {
+    return -1;
+  }
+  let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  let file = if flags.contains(MmapFlags::ANONYMOUS) {
+    None
+  } else {
+    let file = match inner.fd_table.get(fd) {
+      Some(Some(file)) => file,
+      _ => return -1,
+    };
+    let inode = match file.inode() {
+      Some(inode) => inode,
+      None => return -1,
+    };
+    if !file.readable()
+      || (shared
+        && map_perm.contains(MapPermission::W)
+        && !file.writable())
+    {
+      return -1;
+    }
+    Some(MmapFile::new(
+      inode,
+      offset,
+      shared,
+      file.writable(),
+    ))
+  };
+  let start = if flags.contains(MmapFlags::FIXED) {
+    match user_page_range(addr, len) {
+      Some((start, end))
+        if addr >= USER_MMAP_BASE
+          && !inner
+            .memory_set
+            .is_overlapped(start, end) =>
+      {
+        start
+      }
+      _ => return -1,
+    }
+  } else {
+    let hint =
+      if addr < USER_MMAP_BASE || addr % PAGE_SIZE != 0 {
+        USER_MMAP_BASE
+      } else {
+        addr
+      };
+    inner
+      .memory_set
+      .find_free_area(VirtAddr::from(hint).floor(), pages)
+  };
+  let start_va: VirtAddr = start.into();
+  let end_va: VirtAddr =
+    VirtPageNum(start.0 + pages).into();
+  if usize::from(end_va) > USER_MMAP_TOP {
+    return -1;
+  }
+  if let Some(file) = file {
+    if !inner
+      .memory_set
+      .insert_file_area(start_va, end_va, map_perm, file)
+    {
+      return -1;
+    }
+  } else {
+    inner
+      .memory_set
+      .insert_lazy_area(start_va, end_va, map_perm);
+  }
+  usize::from(start_va) as isize
+}
+
+/// Unmap the pages in `[addr, addr + len)`, dirty pages of shared file
+/// mappings are written back to the file.
+/// Return -1 if `addr` is not page aligned or the range covers
+/// kernel-only pages, otherwise 0.
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+  let (start, end) = match user_page_range(addr, len) {
+    Some(range) => range,
+    None => return -1,
+  };
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  if inner.memory_set.munmap(start, end) {
+    drop(inner);
+    process.flush_remote_tlb(addr, len);
+    0
+  } else {
+    -1
+  }
+}
+
+/// Change the protection of the pages in `[addr, addr + len)`.
+/// Return -1 if some page in the range is not mapped or the new
+/// protection is not allowed, otherwise 0.
+pub fn sys_mprotect(
+  addr: usize,
+  len: usize,
+  prot: u32,
+) -> isize {
+  let map_perm = match MmapProt::from_bits(prot)
+    .and_then(|prot| prot.map_permission())
+  {
+    Some(map_perm) => map_perm,
+    None => return -1,
+  };
+  let (start, end) = match user_page_range(addr, len) {
+    Some(range) => range,
+    None => return -1,
+  };
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  if inner.memory_set.mprotect(start, end, map_perm) {
+    drop(inner);
+    process.flush_remote_tlb(addr, len);
+    0
+  } else {
+    -1
+  }
+}
+
 pub fn sys_kill(pid: usize, signal: u32) -> isize {
   // get the PCB by pid
   // then insert the kill flag into its `signals` field.
   if let Some(process) = pid2process(pid) {
     if let Some(flag) = SignalFlags::from_bits(signal) {
-      // insert the signal
-      process.inner_exclusive_access().signals |= flag;
+      process.send_signal(flag);
       0
     } else {
       -1
@@ -156,3 +549,99 @@ pub fn sys_kill(pid: usize, signal: u32) -> isize {
     -1
   }
 }
+
+/// Put the process `pid` (0 for the current one) into the process
+/// group `pgid` (0 for the group led by `pid`). Only the current
+/// process and its children can be moved, otherwise -1 is returned.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+  let current = current_process();
+  let pid = if pid == 0 { current.getpid() } else { pid };
+  let pgid = if pgid == 0 { pid } else { pgid };
+  let process = if pid == current.getpid() {
+    current
+  } else {
+    let child = current
+      .inner_exclusive_access()
+      .children
+      .iter()
+      .find(|child| child.getpid() == pid)
+      .cloned();
+    match child {
+      Some(child) => child,
+      None => return -1,
+    }
+  };
+  process.inner_exclusive_access().pgid = pgid;
+  0
+}
+
+/// Return the process group of the process `pid` (0 for the current
+/// one), or -1 if it does not exist.
+pub fn sys_getpgid(pid: usize) -> isize {
+  let process = if pid == 0 {
+    current_process()
+  } else {
+    match pid2process(pid) {
+      Some(process) => process,
+      None => return -1,
+    }
+  };
+  let pgid = process.inner_exclusive_access().pgid;
+  pgid as isize
+}
+
+/// Install `action` for signal `signum` and save the previous one into
+/// `old_action`, either of them may be null.
+pub fn sys_sigaction(
+  signum: i32,
+  action: *const SignalAction,
+  old_action: *mut SignalAction,
+) -> isize {
+  match SignalFlags::from_signum(signum as usize) {
+    Some(signal)
+      if !signal.intersects(SignalFlags::UNCATCHABLE) => {}
+    _ => return -1,
+  }
+  // translate before locking the process, a page fault needs it.
+  let token = current_user_token();
+  let action = (!action.is_null())
+    .then(|| *translated_ref(token, action));
+  let old_action = (!old_action.is_null())
+    .then(|| translated_refmut(token, old_action));
+
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  let sig = signum as usize;
+  if let Some(old_action) = old_action {
+    *old_action = inner.signal_actions.table[sig];
+  }
+  if let Some(action) = action {
+    inner.signal_actions.table[sig] = action;
+  }
+  0
+}
+
+/// Set the blocked signals of the current process to `mask`, return the
+/// previous mask.
+pub fn sys_sigprocmask(mask: u32) -> isize {
+  let process = current_process();
+  let mut inner = process.inner_exclusive_access();
+  let old_mask = inner.signal_mask;
+  inner.signal_mask = SignalFlags::from_bits_truncate(mask)
+    - SignalFlags::UNCATCHABLE;
+  old_mask.bits() as isize
+}
+
+/// Return from a signal handler to where the thread was interrupted.
+pub fn sys_sigreturn() -> isize {
+  let task = current_task().unwrap();
+  let mut inner = task.inner_exclusive_access();
+  if let Some(trap_cx) = inner.trap_ctx_backup.take() {
+    inner.handling_sig = None;
+    *inner.get_trap_cx() = trap_cx;
+    // keep a0 of the interrupted context instead of a return value
+    trap_cx.x[10] as isize
+  } else {
+    -1
+  }
+}
@@ -6,7 +6,8 @@ use crate::{
   trap::{trap_handler, TrapContext},
 };
 
-/// Create a new thread in current process.
+/// Create a new thread in current process, return its tid or -1 if
+/// there is no memory for it.
 pub fn sys_thread_create(
   entry: usize,
   arg: usize,
@@ -16,7 +17,7 @@ pub fn sys_thread_create(
   let process = task.process.upgrade().unwrap();
 
   // create a new thread
-  let new_task = Arc::new(TaskControlBlock::new(
+  let new_task = match TaskControlBlock::new(
     Arc::clone(&process),
     task
       .inner_exclusive_access()
@@ -25,7 +26,10 @@ pub fn sys_thread_create(
       .unwrap()
       .ustack_base(),
     true,
-  ));
+  ) {
+    Some(new_task) => Arc::new(new_task),
+    None => return -1,
+  };
 
   // add new task to scheduler
   add_task(Arc::clone(&new_task));
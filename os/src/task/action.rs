@@ -1,5 +1,10 @@
 use super::{SignalFlags, MAX_SIG};
 
+/// Handler of a [`SignalAction`] taking the default action of the signal.
+pub const SIG_DFL: usize = 0;
+/// Handler of a [`SignalAction`] ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
 #[repr(C, align(16))]
 #[derive(Debug, Clone, Copy)]
 /// We align it to 16 bytes so that it doesn't cross virtual pages.
@@ -10,18 +10,19 @@ use crate::{
     TRAP_CONTEXT_BASE, USER_STACK_SIZE,
   },
   mm::{
-    MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE,
+    MapPermission, MemorySet, PhysPageNum, VirtAddr,
+    KERNEL_SPACE,
   },
-  sync::UPSafeCell,
+  sync::SpinLock,
 };
 
 use super::ProcessControlBlock;
 
 lazy_static! {
-  pub static ref PID_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-    unsafe { UPSafeCell::new(RecycleAllocator::new()) };
-  pub static ref KSTACK_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-    unsafe { UPSafeCell::new(RecycleAllocator::new()) };
+  pub static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> =
+    unsafe { SpinLock::new(RecycleAllocator::new()) };
+  pub static ref KSTACK_ALLOCATOR: SpinLock<RecycleAllocator> =
+    unsafe { SpinLock::new(RecycleAllocator::new()) };
 }
 
 /// Allocate a pid from PID_ALLOCATOR
@@ -88,18 +89,21 @@ pub fn kernel_stack_position(
   (bottom, top)
 }
 
-/// Allocate a Kernel Stack
-pub fn kstack_alloc() -> KernelStack {
+/// Allocate a Kernel Stack, `None` if there is no memory for it.
+pub fn kstack_alloc() -> Option<KernelStack> {
   let kstack_id =
     KSTACK_ALLOCATOR.exclusive_access().alloc();
   let (kstack_bottom, kstack_top) =
     kernel_stack_position(kstack_id);
-  KERNEL_SPACE.exclusive_access().insert_framed_area(
-    kstack_bottom.into(),
-    kstack_top.into(),
-    MapPermission::R | MapPermission::W,
-  );
-  KernelStack(kstack_id)
+  // dropped on failure to give back the id
+  let kstack = KernelStack(kstack_id);
+  let mapped =
+    KERNEL_SPACE.exclusive_access().insert_framed_area(
+      kstack_bottom.into(),
+      kstack_top.into(),
+      MapPermission::R | MapPermission::W,
+    );
+  mapped.then_some(kstack)
 }
 
 impl KernelStack {
@@ -165,17 +169,53 @@ fn ustack_bottom_from_tid(
   ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
 }
 
+/// Mapping the UserStack and TrapContext of thread `tid` in `memory_set`,
+/// return `false` and leave `memory_set` unchanged if there is no memory
+/// for them.
+pub fn map_user_res(
+  memory_set: &mut MemorySet,
+  ustack_base: usize,
+  tid: usize,
+) -> bool {
+  // alloc user stack, pages are allocated on demand
+  let ustack_bottom =
+    ustack_bottom_from_tid(ustack_base, tid);
+  let ustack_top = ustack_bottom + USER_STACK_SIZE;
+  memory_set.insert_lazy_area(
+    ustack_bottom.into(),
+    ustack_top.into(),
+    MapPermission::R | MapPermission::W | MapPermission::U,
+  );
+
+  // alloc trap_cx, user pages are evicted for it first
+  let trap_cx_bottom = trap_cx_bottom_from_tid(tid);
+  let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+  memory_set.reserve_frame();
+  if !memory_set.insert_framed_area(
+    trap_cx_bottom.into(),
+    trap_cx_top.into(),
+    MapPermission::R | MapPermission::W,
+  ) {
+    let ustack_bottom_va: VirtAddr = ustack_bottom.into();
+    memory_set
+      .remove_area_with_start_vpn(ustack_bottom_va.into());
+    return false;
+  }
+  true
+}
+
 impl TaskUserRes {
   /// Create a new thread,
   /// `alloc_user_res` controlling whether mapping a new UserStack and TrapContext.
   /// Such as, in the operation of `fork`, child process fork a new process, we don't
   /// have to allocate a new UserStack and TrapContext, because the child process has
   /// copied its father address space, here, the `alloc_user_res` is false.
+  /// Return `None` if there is no memory for them.
   pub fn new(
     process: Arc<ProcessControlBlock>,
     ustack_base: usize,
     alloc_user_res: bool,
-  ) -> Self {
+  ) -> Option<Self> {
     let tid = process.inner_exclusive_access().alloc_tid();
     let task_user_res = Self {
       tid,
@@ -183,37 +223,23 @@ impl TaskUserRes {
       process: Arc::downgrade(&process),
     };
 
-    if alloc_user_res {
-      task_user_res.alloc_user_res();
+    // the tid is given back when `task_user_res` is dropped
+    if alloc_user_res && !task_user_res.alloc_user_res() {
+      return None;
     }
-    task_user_res
+    Some(task_user_res)
   }
 
-  /// Mapping the UserStack and TrapContext of thread in current process space.
-  pub fn alloc_user_res(&self) {
+  /// Mapping the UserStack and TrapContext of thread in current process space,
+  /// return `false` if there is no memory for them.
+  pub fn alloc_user_res(&self) -> bool {
     let process = self.process.upgrade().unwrap();
     let mut process_inner =
       process.inner_exclusive_access();
-
-    // alloc user stack
-    let ustack_bottom =
-      ustack_bottom_from_tid(self.ustack_base, self.tid);
-    let ustack_top = ustack_bottom + USER_STACK_SIZE;
-    process_inner.memory_set.insert_framed_area(
-      ustack_bottom.into(),
-      ustack_top.into(),
-      MapPermission::R
-        | MapPermission::W
-        | MapPermission::U,
-    );
-
-    // alloc trap_cx
-    let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
-    let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
-    process_inner.memory_set.insert_framed_area(
-      trap_cx_bottom.into(),
-      trap_cx_top.into(),
-      MapPermission::R | MapPermission::W,
+    map_user_res(
+      &mut process_inner.memory_set,
+      self.ustack_base,
+      self.tid,
     )
   }
 
@@ -1,58 +1,74 @@
 //! Implementation of [`TaskManager`]
-use alloc::{
-  collections::{BTreeMap, VecDeque},
-  sync::Arc,
-};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::Ordering;
 
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use lazy_static::lazy_static;
 
+#[cfg(feature = "mlfq")]
+use super::scheduler::MlfqScheduler;
+#[cfg(not(feature = "mlfq"))]
+use super::scheduler::StrideScheduler;
 use super::{
-  ProcessControlBlock, TaskControlBlock, TaskStatus,
+  scheduler::Scheduler, ProcessControlBlock, SignalFlags,
+  TaskControlBlock, TaskStatus,
 };
 
+/// The scheduling policy in use.
+#[cfg(not(feature = "mlfq"))]
+pub type SchedulerImpl = StrideScheduler;
+/// The scheduling policy in use.
+#[cfg(feature = "mlfq")]
+pub type SchedulerImpl = MlfqScheduler;
+
 /// A array of `TaskControlBlock` that is thread-safe
 pub struct TaskManager {
-  ready_queue: VecDeque<Arc<TaskControlBlock>>,
+  scheduler: SchedulerImpl,
 }
 
-/// A simple FIFO scheduler.
+/// Ready tasks, scheduled by [`SchedulerImpl`].
 impl TaskManager {
   /// Create an empty TaskManager
   pub fn new() -> Self {
     Self {
-      ready_queue: VecDeque::new(),
+      scheduler: SchedulerImpl::new(),
     }
   }
 
   /// Add a task to `TaskManager`
   pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-    self.ready_queue.push_back(task);
+    self.scheduler.add(task);
   }
 
-  /// Remove the first task and return it, or `None` if `TaskManager` is empty
+  /// Remove the task to run next and return it, or `None` if `TaskManager` is empty
   pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-    self.ready_queue.pop_front()
+    self.scheduler.fetch()
   }
 
-  /// Remove the specified task by matching its reference pointer.
-  pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-    if let Some((id, _)) = self
-      .ready_queue
-      .iter()
-      .enumerate()
-      .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
-    {
-      self.ready_queue.remove(id);
-    }
+  /// Remove the specified task by matching its reference pointer,
+  /// return whether it was ready.
+  pub fn remove(
+    &mut self,
+    task: Arc<TaskControlBlock>,
+  ) -> bool {
+    self.scheduler.remove(&task)
+  }
+
+  /// Account a timer tick to the running `task`, return whether it
+  /// should give up the CPU.
+  pub fn tick(
+    &mut self,
+    task: &Arc<TaskControlBlock>,
+  ) -> bool {
+    self.scheduler.tick(task)
   }
 }
 
 lazy_static! {
-  pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
-    unsafe { UPSafeCell::new(TaskManager::new()) };
-  pub static ref PID2PCB: UPSafeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-    unsafe { UPSafeCell::new(BTreeMap::new()) };
+  pub static ref TASK_MANAGER: SpinLock<TaskManager> =
+    unsafe { SpinLock::new(TaskManager::new()) };
+  pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+    unsafe { SpinLock::new(BTreeMap::new()) };
 }
 
 /// Interface offered to add task(thread).
@@ -60,6 +76,11 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
   TASK_MANAGER.exclusive_access().add(task);
 }
 
+/// Interface offered to account a timer tick to the running task.
+pub fn tick_task(task: &Arc<TaskControlBlock>) -> bool {
+  TASK_MANAGER.exclusive_access().tick(task)
+}
+
 /// Interface offered to wake up task(thread).
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
   let mut task_inner = task.inner_exclusive_access();
@@ -73,6 +94,16 @@ pub fn remove_task(task: Arc<TaskControlBlock>) {
   TASK_MANAGER.exclusive_access().remove(task);
 }
 
+/// Interface offered to stop a ready task, it is taken out of
+/// `TaskManager` until it is woken up again.
+pub fn stop_ready_task(task: &Arc<TaskControlBlock>) {
+  let mut manager = TASK_MANAGER.exclusive_access();
+  if manager.remove(Arc::clone(task)) {
+    task.inner_exclusive_access().task_status =
+      TaskStatus::Stopped;
+  }
+}
+
 /// Interface offered to pop the first task.
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
   TASK_MANAGER.exclusive_access().fetch()
@@ -94,6 +125,46 @@ pub fn insert_into_pid2process(
   PID2PCB.exclusive_access().insert(pid, process);
 }
 
+/// Interface offered to send `flag` to every process of the group
+/// `pgid`.
+pub fn signal_process_group(
+  pgid: usize,
+  flag: SignalFlags,
+) {
+  let group: Vec<_> = PID2PCB
+    .exclusive_access()
+    .values()
+    .filter(|process| {
+      process.inner_exclusive_access().pgid == pgid
+    })
+    .cloned()
+    .collect();
+  for process in group {
+    process.send_signal(flag);
+  }
+}
+
+/// Evict a page of some process to the swap device, processes busy in
+/// the kernel with their PCB borrowed are skipped, and so are those
+/// running on a hart which may have the page in its TLB.
+pub fn swap_out_any() -> bool {
+  // `PID2PCB` is not held while writing to the swap device
+  let processes: Vec<_> =
+    PID2PCB.exclusive_access().values().cloned().collect();
+  processes.iter().any(|process| {
+    process.inner_try_exclusive_access().map_or(
+      false,
+      |mut inner| {
+        let running =
+          inner.tasks.iter().flatten().any(|task| {
+            task.on_cpu.load(Ordering::Acquire)
+          });
+        !running && inner.memory_set.swap_out_one()
+      },
+    )
+  })
+}
+
 /// Interface offered to remove the task PCB by pid.
 pub fn remove_from_pid2task(pid: usize) {
   let mut map = PID2PCB.exclusive_access();
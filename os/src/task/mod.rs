@@ -6,8 +6,8 @@
 //! A single global instance of [`TaskManager`] called `TASK_MANAGER` controls
 //! all the tasks in the operating system.
 //!
-//! A single global instance of [`Processor`]  called `PROCESSOR` monitors running
-//! task(s) for each core.
+//! One instance of [`Processor`] for each hart in `PROCESSORS` monitors the
+//! task running on that core.
 //!
 //! A single global instance of [`PidAllocator`] called `PID_ALLOCATOR` allocates
 //! pid for user apps.
@@ -15,11 +15,16 @@
 //! Be careful when you see `__switch` ASM function in `switch.S`. Control flow around this function
 //! might not be what you expect.
 
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{
+  open_file, sync_fs, OpenFlags, ROOT_INODE,
+};
+use crate::mm::{FrameTracker, VirtPageNum};
 use crate::sbi::shutdown;
 use crate::timer::remove_timer;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, Ordering};
 use lazy_static::lazy_static;
 
 mod action;
@@ -28,6 +33,7 @@ mod id;
 mod manager;
 mod process;
 mod processor;
+mod scheduler;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
@@ -39,6 +45,7 @@ pub use id::*;
 pub use manager::*;
 pub use process::*;
 pub use processor::*;
+pub use scheduler::{DEFAULT_PRIORITY, MIN_PRIORITY};
 pub use signal::*;
 pub use task::*;
 
@@ -75,6 +82,14 @@ pub fn block_current_and_run_next() {
   schedule(task_cx_ptr);
 }
 
+/// Exit the current thread as its process is terminated by the signal
+/// `signum`, which `waitpid` reports to the parent.
+pub fn kill_current_and_run_next(signum: usize) {
+  current_process().inner_exclusive_access().term_signal =
+    Some(signum);
+  exit_current_and_run_next(-(signum as i32));
+}
+
 /// pid of usertests app in make run TEST=1
 pub const IDLE_PID: usize = 0;
 
@@ -100,6 +115,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         "[kernel] Idle process exit witch exit_code {} ...",
         exit_code
       );
+      sync_fs();
       if exit_code != 0 {
         // crate::qemu::QEMU_EXIT_HANDLE.exit_failure();
         shutdown(true);
@@ -130,21 +146,42 @@ pub fn exit_current_and_run_next(exit_code: i32) {
       }
     }
 
+    // other threads may be running on other harts, they exit at
+    // their next trap as the process is a zombie. Wait for them to
+    // leave their harts before the address space goes away.
+    let others: Vec<_> = process_inner
+      .tasks
+      .iter()
+      .skip(1)
+      .flatten()
+      .cloned()
+      .collect();
+    drop(process_inner);
+    // pairs with the fence in `run_tasks`, a hart either sees the
+    // zombie and does not run the thread, or has marked it `on_cpu`
+    fence(Ordering::SeqCst);
+    for task in others {
+      // if other tasks are Ready in TaskManger or waiting for a timer to be
+      // expired, we should remove them.
+      //
+      // Mention that we do not need to consider Mutex/Semaphore since they
+      // are limited in a single process. Therefore, the blocked tasks are
+      // removed when the PCB is deallocated.
+      remove_inactive_task(Arc::clone(&task));
+      while task.on_cpu.load(Ordering::Acquire) {
+        spin_loop();
+      }
+    }
+
     // deallocate user resource (including tid/trap_cx/ustack) of all threads
     // it has to be done before we dealloc the whole memory_set
     // otherwise they will be deallocated twice
+    let process_inner = process.inner_exclusive_access();
     let mut recycle_res = Vec::<TaskUserRes>::new();
     for task in
       process_inner.tasks.iter().filter(|t| t.is_some())
     {
       let task = task.as_ref().unwrap();
-      // if other tasks are Ready in TaskManger or waiting for a timer to be
-      // expired, we should remove them.
-      //
-      // Mention that we do not need to consider Mutex/Semaphore since they
-      // are limited in a single process. Therefore, the blocked tasks are
-      // removed when the PCB is deallocated.
-      remove_inactive_task(Arc::clone(task));
       let mut task_inner = task.inner_exclusive_access();
       if let Some(res) = task_inner.res.take() {
         recycle_res.push(res);
@@ -165,6 +202,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     process_inner.fd_table.clear();
     // remove all tasks
     process_inner.tasks.clear();
+    drop(process_inner);
+    process.notify_parent();
   }
 
   drop(process);
@@ -176,7 +215,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
 lazy_static! {
   /// Global process that init user shell
   pub static ref INITPROC: Arc<ProcessControlBlock> = {
-    let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+    let inode = open_file(&ROOT_INODE, "initproc", OpenFlags::RDONLY).unwrap();
     let v = inode.read_all();
     ProcessControlBlock::new(v.as_slice())
   };
@@ -186,11 +225,113 @@ pub fn add_initproc() {
   let _initproc = INITPROC.clone();
 }
 
-pub fn check_signals_of_current(
-) -> Option<(i32, &'static str)> {
+/// Deliver the pending signals of the current process before the
+/// current thread returns to user space.
+///
+/// A thread of a stopped process stops here until the process is
+/// continued. Return the exit code and message if a signal terminates
+/// the process.
+pub fn handle_signals() -> Option<(i32, &'static str)> {
+  loop {
+    if let Some(error) = check_pending_signals() {
+      return Some(error);
+    }
+    if !stop_current_and_run_next() {
+      return None;
+    }
+  }
+}
+
+/// Stop the current task if its process is stopped, it is not
+/// scheduled until the process is continued. Return whether it has
+/// been stopped.
+fn stop_current_and_run_next() -> bool {
   let process = current_process();
   let process_inner = process.inner_exclusive_access();
-  process_inner.signals.check_error()
+  // checked with the process locked, so that a `SIGCONT` does not
+  // slip in before the task is marked as stopped.
+  if !process_inner.stopped {
+    return false;
+  }
+  let task = take_current_task().unwrap();
+  let mut task_inner = task.inner_exclusive_access();
+  let task_cx_ptr =
+    &mut task_inner.task_cx as *mut TaskContext;
+  task_inner.task_status = TaskStatus::Stopped;
+  drop(task_inner);
+  drop(task);
+  drop(process_inner);
+  drop(process);
+  schedule(task_cx_ptr);
+  true
+}
+
+/// Take the default action of every pending signal which is not
+/// blocked, stop at the first one with a user handler and make the
+/// current thread enter the handler.
+fn check_pending_signals() -> Option<(i32, &'static str)> {
+  let process = current_process();
+  let task = current_task().unwrap();
+  let mut process_inner = process.inner_exclusive_access();
+  let mut task_inner = task.inner_exclusive_access();
+
+  let mut blocked = process_inner.signal_mask;
+  if let Some(sig) = task_inner.handling_sig {
+    blocked |= process_inner.signal_actions.table[sig].mask
+      | SignalFlags::from_signum(sig).unwrap();
+  }
+  blocked -= SignalFlags::UNCATCHABLE;
+
+  for sig in 1..=MAX_SIG {
+    let signal = SignalFlags::from_signum(sig).unwrap();
+    if !process_inner.signals.contains(signal)
+      || blocked.contains(signal)
+    {
+      continue;
+    }
+    let handler =
+      if signal.intersects(SignalFlags::UNCATCHABLE) {
+        SIG_DFL
+      } else {
+        process_inner.signal_actions.table[sig].handler
+      };
+    match handler {
+      SIG_IGN => {}
+      SIG_DFL => match signal.default_action() {
+        SignalDefault::Ignore => {}
+        // the signal stays pending, so that every thread of the
+        // process exits when it traps into the kernel.
+        SignalDefault::Terminate => {
+          return signal.check_error()
+        }
+        SignalDefault::Stop => {
+          process_inner.signals.remove(signal);
+          drop(task_inner);
+          process_inner.stop(sig);
+          drop(process_inner);
+          process.notify_parent();
+          return None;
+        }
+        // already continued when the signal was sent
+        SignalDefault::Continue => {}
+      },
+      handler => {
+        // no nesting of handlers, it stays pending until `sigreturn`
+        if task_inner.handling_sig.is_some() {
+          continue;
+        }
+        let trap_cx = task_inner.get_trap_cx();
+        task_inner.trap_ctx_backup = Some(*trap_cx);
+        task_inner.handling_sig = Some(sig);
+        trap_cx.sepc = handler;
+        trap_cx.x[10] = sig;
+        process_inner.signals.remove(signal);
+        return None;
+      }
+    }
+    process_inner.signals.remove(signal);
+  }
+  None
 }
 
 pub fn current_add_signal(signal: SignalFlags) {
@@ -199,6 +340,33 @@ pub fn current_add_signal(signal: SignalFlags) {
   process_inner.signals |= signal;
 }
 
+/// Resolve a page fault of the current process at `vpn`, return
+/// `false` if it is a real access violation.
+pub fn current_handle_page_fault(
+  vpn: VirtPageNum,
+  is_write: bool,
+) -> bool {
+  let process = current_process();
+  let mut process_inner = process.inner_exclusive_access();
+  process_inner.memory_set.handle_page_fault(vpn, is_write)
+}
+
+/// Pin the frame mapped at `vpn` of the current process, it is not
+/// swapped out while the kernel keeps the returned reference.
+pub fn current_pin_page(
+  vpn: VirtPageNum,
+) -> Option<Arc<FrameTracker>> {
+  let process = current_process();
+  let process_inner = process.inner_exclusive_access();
+  process_inner.memory_set.pin_page(vpn)
+}
+
+/// Whether the process of the current thread is a zombie, whose main
+/// thread has exited while this one was running.
+pub fn current_process_exited() -> bool {
+  current_process().inner_exclusive_access().is_zombie
+}
+
 pub fn remove_inactive_task(task: Arc<TaskControlBlock>) {
   remove_task(Arc::clone(&task));
   remove_timer(Arc::clone(&task));
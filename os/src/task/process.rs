@@ -1,4 +1,5 @@
-use core::cell::RefMut;
+use crate::sync::SpinLockGuard;
+use easy_fs::Inode;
 
 use alloc::string::String;
 use alloc::vec;
@@ -7,15 +8,23 @@ use alloc::{
   vec::Vec,
 };
 
-use crate::fs::{Stdin, Stdout};
-use crate::mm::{translated_refmut, KERNEL_SPACE};
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::config::{USER_HEAP_BASE, USER_HEAP_LIMIT};
+use crate::fs::{Stdin, Stdout, ROOT_INODE};
+use crate::mm::{
+  translated_refmut, VirtAddr, KERNEL_SPACE,
+};
+use crate::sbi::remote_sfence_vma;
 use crate::sync::{Condvar, Mutex, Semaphore};
 use crate::trap::{trap_handler, TrapContext};
-use crate::{fs::File, mm::MemorySet, sync::UPSafeCell};
+use crate::{fs::File, mm::MemorySet, sync::SpinLock};
 
 use super::{
-  add_task, insert_into_pid2process, pid_alloc, PidHandle,
-  RecycleAllocator, SignalFlags, TaskControlBlock,
+  add_task, hart_id, insert_into_pid2process, map_user_res,
+  pid_alloc, stop_ready_task, PidHandle, RecycleAllocator,
+  SignalAction, SignalActions, SignalFlags,
+  TaskControlBlock, TaskStatus, SIG_IGN,
 };
 
 /// Process Control Block
@@ -23,35 +32,63 @@ pub struct ProcessControlBlock {
   /// immutable
   /// Process Identifier.
   pub pid: PidHandle,
+  /// Harts running a thread of the process, as a bit mask.
+  pub harts: AtomicUsize,
   /// mutable
-  inner: UPSafeCell<ProcessControlBlockInner>,
+  inner: SpinLock<ProcessControlBlockInner>,
 }
 
 impl ProcessControlBlock {
   pub fn inner_exclusive_access(
     &self,
-  ) -> RefMut<'_, ProcessControlBlockInner> {
+  ) -> SpinLockGuard<'_, ProcessControlBlockInner> {
     self.inner.exclusive_access()
   }
 
+  pub fn inner_try_exclusive_access(
+    &self,
+  ) -> Option<SpinLockGuard<'_, ProcessControlBlockInner>>
+  {
+    self.inner.try_exclusive_access()
+  }
+
+  /// Flush the TLB entries of `[addr, addr + len)` on the other
+  /// harts running a thread of the process, after its page table
+  /// has changed. The current hart flushes when it returns to user.
+  pub fn flush_remote_tlb(&self, addr: usize, len: usize) {
+    // pairs with the update of `harts` in `run_tasks`
+    fence(Ordering::SeqCst);
+    let hart_mask =
+      self.harts.load(Ordering::SeqCst) & !(1 << hart_id());
+    if hart_mask != 0 {
+      remote_sfence_vma(hart_mask, addr, len);
+    }
+  }
+
   pub fn new(elf_data: &[u8]) -> Arc<Self> {
     // memory_set with elf program headers/trampoline/trap-context/user-stack
     let (memory_set, ustack_base, entry_point) =
-      MemorySet::from_elf(elf_data);
+      MemorySet::from_elf(elf_data)
+        .expect("no memory for the initial process");
 
     // alloc a pid
     let pid_handle = pid_alloc();
+    let pgid = pid_handle.0;
 
     // create PCB
     let process = Arc::new(Self {
       pid: pid_handle,
+      harts: AtomicUsize::new(0),
       inner: unsafe {
-        UPSafeCell::new(ProcessControlBlockInner {
+        SpinLock::new(ProcessControlBlockInner {
           is_zombie: false,
           memory_set,
+          program_brk: USER_HEAP_BASE,
           parent: None,
           children: Vec::new(),
           exit_code: 0,
+          term_signal: None,
+          pgid,
           fd_table: vec![
             // 0 -> stdin
             Some(Arc::new(Stdin)),
@@ -60,7 +97,12 @@ impl ProcessControlBlock {
             // 2 -> stderr
             Some(Arc::new(Stdout)),
           ],
+          cwd: Arc::clone(&ROOT_INODE),
           signals: SignalFlags::empty(),
+          signal_mask: SignalFlags::empty(),
+          signal_actions: SignalActions::default(),
+          stopped: false,
+          wait_event: None,
           tasks: Vec::new(),
           task_res_allocator: RecycleAllocator::new(),
           mutex_list: Vec::new(),
@@ -71,11 +113,14 @@ impl ProcessControlBlock {
     });
 
     // create a main thread, we should allocate ustack and trap_cx here
-    let task = Arc::new(TaskControlBlock::new(
-      Arc::clone(&process),
-      ustack_base,
-      true,
-    ));
+    let task = Arc::new(
+      TaskControlBlock::new(
+        Arc::clone(&process),
+        ustack_base,
+        true,
+      )
+      .expect("no memory for the initial process"),
+    );
 
     // prepare trap_cx of main thread
     let task_inner = task.inner_exclusive_access();
@@ -106,30 +151,56 @@ impl ProcessControlBlock {
   }
 
   /// Only support processes with a single thread.
+  /// Return `false` and keep the old image if there is no memory for
+  /// the new one.
   pub fn exec(
     self: &Arc<Self>,
     elf_data: &[u8],
     args: Vec<String>,
-  ) {
+  ) -> bool {
     assert_eq!(
       self.inner_exclusive_access().thread_count(),
       1
     );
     // memory_set with elf program headers/trampoline/trap context/user stack
-    let (memory_set, ustack_base, entry_point) =
-      MemorySet::from_elf(elf_data);
+    let (mut memory_set, ustack_base, entry_point) =
+      match MemorySet::from_elf(elf_data) {
+        Some(image) => image,
+        None => return false,
+      };
     let new_token = memory_set.token();
+    // the user resource of the main thread is mapped before the old
+    // image is given up
+    let task = self.inner_exclusive_access().get_task(0);
+    let tid = task
+      .inner_exclusive_access()
+      .res
+      .as_ref()
+      .unwrap()
+      .tid;
+    if !map_user_res(&mut memory_set, ustack_base, tid) {
+      return false;
+    }
 
-    // substitute memory_set
-    self.inner_exclusive_access().memory_set = memory_set;
+    // substitute memory_set, the new one comes with an empty heap
+    let mut process_inner = self.inner_exclusive_access();
+    process_inner.memory_set = memory_set;
+    process_inner.program_brk = USER_HEAP_BASE;
+    // handlers of the old image are gone, ignored signals stay ignored
+    for action in
+      process_inner.signal_actions.table.iter_mut()
+    {
+      if action.handler != SIG_IGN {
+        *action = SignalAction::default();
+      }
+    }
+    drop(process_inner);
 
-    // then we alloc user resource for main thread again
-    // since memory_set has been changed.
-    let task = self.inner_exclusive_access().get_task(0);
+    // the user resource of the main thread lives in the new
+    // memory_set now.
     let mut task_inner = task.inner_exclusive_access();
     task_inner.res.as_mut().unwrap().ustack_base =
       ustack_base;
-    task_inner.res.as_mut().unwrap().alloc_user_res();
     task_inner.trap_cx_ppn =
       task_inner.res.as_mut().unwrap().trap_cx_ppn();
 
@@ -173,15 +244,18 @@ impl ProcessControlBlock {
     trap_cx.x[10] = args.len();
     trap_cx.x[11] = argv_base;
     *task_inner.get_trap_cx() = trap_cx;
+    true
   }
 
   /// Only support processes with a single thread.
-  pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+  /// Return `None` if there is no memory for the child.
+  pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
     let mut parent = self.inner_exclusive_access();
     assert_eq!(parent.thread_count(), 1);
-    // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
+    // clone parent's memory_set including trampoline/ustacks/trap_cxs,
+    // user pages are shared copy-on-write with the parent.
     let memory_set =
-      MemorySet::from_existed_user(&parent.memory_set);
+      MemorySet::from_existed_user(&mut parent.memory_set)?;
 
     // alloc a pid
     let pid = pid_alloc();
@@ -200,15 +274,24 @@ impl ProcessControlBlock {
     // create child process pcb
     let child = Arc::new(Self {
       pid,
+      harts: AtomicUsize::new(0),
       inner: unsafe {
-        UPSafeCell::new(ProcessControlBlockInner {
+        SpinLock::new(ProcessControlBlockInner {
           is_zombie: false,
           memory_set,
+          program_brk: parent.program_brk,
           children: Vec::new(),
           parent: Some(Arc::downgrade(self)),
           exit_code: 0,
+          term_signal: None,
+          pgid: parent.pgid,
           fd_table: new_fd_table,
+          cwd: Arc::clone(&parent.cwd),
           signals: SignalFlags::empty(),
+          signal_mask: parent.signal_mask,
+          signal_actions: parent.signal_actions.clone(),
+          stopped: false,
+          wait_event: None,
           tasks: Vec::new(),
           task_res_allocator: RecycleAllocator::new(),
           mutex_list: Vec::new(),
@@ -217,9 +300,8 @@ impl ProcessControlBlock {
         })
       },
     });
-    // add child
-    parent.children.push(Arc::clone(&child));
-    // create main thread of child process
+    // create main thread of child process, the child is dropped if
+    // there is no memory for its kstack
     let task = Arc::new(TaskControlBlock::new(
       Arc::clone(&child),
       parent
@@ -232,7 +314,9 @@ impl ProcessControlBlock {
       // here we do not allocate trap_cx or ustack again
       // but mention that we allocate a new kstack here
       false,
-    ));
+    )?);
+    // add child
+    parent.children.push(Arc::clone(&child));
     // attach task to child process
     let mut child_inner = child.inner_exclusive_access();
     child_inner.tasks.push(Some(Arc::clone(&task)));
@@ -248,12 +332,66 @@ impl ProcessControlBlock {
     );
     // add this thread to scheduler
     add_task(task);
-    child
+    Some(child)
   }
 
   pub fn getpid(&self) -> usize {
     self.pid.0
   }
+
+  /// Send `flag` to the process, it is delivered when one of its
+  /// threads returns to user space.
+  pub fn send_signal(&self, flag: SignalFlags) {
+    let mut inner = self.inner_exclusive_access();
+    // continuing a stopped process cancels pending stops, and
+    // the other way around.
+    if flag.intersects(SignalFlags::STOP) {
+      inner.signals -= SignalFlags::SIGCONT;
+    }
+    let mut continued = false;
+    if flag.contains(SignalFlags::SIGCONT) {
+      inner.signals -= SignalFlags::STOP;
+      continued = inner.stopped;
+    }
+    // insert the signal
+    inner.signals |= flag;
+    if continued {
+      inner.wait_event = Some(WaitEvent::Continued);
+    }
+    // a stopped process also runs again to die of SIGKILL
+    if continued
+      || inner.stopped
+        && flag.contains(SignalFlags::SIGKILL)
+    {
+      inner.wake_stopped_tasks();
+    }
+    drop(inner);
+    if continued {
+      self.notify_parent();
+    }
+  }
+
+  /// Send `SIGCHLD` to the parent process, the caller must not hold
+  /// the inner of either process.
+  pub fn notify_parent(&self) {
+    let parent = self
+      .inner_exclusive_access()
+      .parent
+      .as_ref()
+      .and_then(Weak::upgrade);
+    if let Some(parent) = parent {
+      parent.inner_exclusive_access().signals |=
+        SignalFlags::SIGCHLD;
+    }
+  }
+}
+
+/// A change of a child process reported by `waitpid` besides its exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitEvent {
+  /// Stopped by the signal.
+  Stopped(usize),
+  Continued,
 }
 
 pub struct ProcessControlBlockInner {
@@ -262,6 +400,8 @@ pub struct ProcessControlBlockInner {
 
   /// Represents the application address space.
   pub memory_set: MemorySet,
+  /// Current end of the heap, which starts at `USER_HEAP_BASE`.
+  pub program_brk: usize,
 
   /// Points to the parent process of the current process.
   pub parent: Option<Weak<ProcessControlBlock>>,
@@ -272,6 +412,12 @@ pub struct ProcessControlBlockInner {
   /// `exit_code` will save in its PCB, and then waiting for recycle its resources by it's parent process
   /// by calling `waitpid()`.
   pub exit_code: i32,
+  /// Signal which has terminated the process, reported by `waitpid`
+  /// instead of `exit_code`.
+  pub term_signal: Option<usize>,
+  /// Process group, which receives the signals typed on the console
+  /// while it is in the foreground.
+  pub pgid: usize,
 
   /// Type description:
   /// - Vec: dynamic length.
@@ -280,10 +426,20 @@ pub struct ProcessControlBlockInner {
   /// - Arc: sharing reference.
   /// - dyn: maybe `Stdin` / `Stdout`
   pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+  /// Current working directory, relative paths are walked from it.
+  pub cwd: Arc<Inode>,
 
   /// Record which signals have been received by the corresponding process
   /// and have not yet been processed.
   pub signals: SignalFlags,
+  /// Signals blocked from being delivered, they stay pending.
+  pub signal_mask: SignalFlags,
+  /// The action taken for each signal.
+  pub signal_actions: SignalActions,
+  /// Whether the process is stopped by a signal and waits for `SIGCONT`.
+  pub stopped: bool,
+  /// Stop or continue of the process not yet reported to `waitpid`.
+  pub wait_event: Option<WaitEvent>,
 
   /// Recording the threads generated by current process.
   pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
@@ -314,6 +470,34 @@ impl ProcessControlBlockInner {
     }
   }
 
+  /// Move the end of the heap to `new_brk`, return `false` if it is
+  /// out of the heap range or the grown part overlaps another area.
+  pub fn set_program_brk(
+    &mut self,
+    new_brk: usize,
+  ) -> bool {
+    if !(USER_HEAP_BASE..=USER_HEAP_LIMIT)
+      .contains(&new_brk)
+    {
+      return false;
+    }
+    let heap_bottom = VirtAddr::from(USER_HEAP_BASE);
+    let result = if new_brk < self.program_brk {
+      self.memory_set.shrink_to(heap_bottom, new_brk.into())
+    } else if self.memory_set.is_overlapped(
+      VirtAddr::from(self.program_brk).ceil(),
+      VirtAddr::from(new_brk).ceil(),
+    ) {
+      false
+    } else {
+      self.memory_set.append_to(heap_bottom, new_brk.into())
+    };
+    if result {
+      self.program_brk = new_brk;
+    }
+    result
+  }
+
   /// Allocate a minium free thread identifier.
   pub fn alloc_tid(&mut self) -> usize {
     self.task_res_allocator.alloc()
@@ -329,6 +513,29 @@ impl ProcessControlBlockInner {
     self.tasks.len()
   }
 
+  /// Stop all threads, ready ones are taken out of `TaskManager` here
+  /// and the others stop before they return to user space.
+  pub fn stop(&mut self, signum: usize) {
+    self.stopped = true;
+    self.wait_event = Some(WaitEvent::Stopped(signum));
+    for task in self.tasks.iter().flatten() {
+      stop_ready_task(task);
+    }
+  }
+
+  /// Put the stopped threads back to `TaskManager`.
+  pub fn wake_stopped_tasks(&mut self) {
+    self.stopped = false;
+    for task in self.tasks.iter().flatten() {
+      let mut task_inner = task.inner_exclusive_access();
+      if task_inner.task_status == TaskStatus::Stopped {
+        task_inner.task_status = TaskStatus::Ready;
+        drop(task_inner);
+        add_task(Arc::clone(task));
+      }
+    }
+  }
+
   /// Acquire the thread with the index of tid.
   pub fn get_task(
     &self,
@@ -1,6 +1,16 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+  arch::asm,
+  hint::spin_loop,
+  sync::atomic::{fence, Ordering},
+};
 
-use crate::{sync::UPSafeCell, trap::TrapContext};
+use crate::{
+  config::MAX_HARTS,
+  sync::UPSafeCell,
+  timer::{check_timer, get_time_ms},
+  trap::TrapContext,
+};
 
 use super::{
   manager::fetch_task, switch::__switch,
@@ -10,8 +20,25 @@ use super::{
 use lazy_static::lazy_static;
 
 lazy_static! {
-  pub static ref PROCESSOR: UPSafeCell<Processor> =
-    unsafe { UPSafeCell::new(Processor::new()) };
+  /// one `Processor` for each hart, only accessed by its own hart
+  pub static ref PROCESSORS: Vec<UPSafeCell<Processor>> =
+    (0..MAX_HARTS)
+      .map(|_| unsafe { UPSafeCell::new(Processor::new()) })
+      .collect();
+}
+
+/// Id of the hart running this code, kept in `tp` by the kernel.
+pub fn hart_id() -> usize {
+  let id;
+  unsafe {
+    asm!("mv {}, tp", out(reg) id);
+  }
+  id
+}
+
+/// The `Processor` of the current hart.
+fn processor() -> &'static UPSafeCell<Processor> {
+  &PROCESSORS[hart_id()]
 }
 
 /// Processor management structure
@@ -53,8 +80,23 @@ impl Processor {
 /// Loop `fetch_task` to get the process that needs to run, and switch the process through `__switch`
 pub fn run_tasks() {
   loop {
-    let mut processor = PROCESSOR.exclusive_access();
     if let Some(task) = fetch_task() {
+      // the task may be added back by another hart before its
+      // context is saved, wait for the hart to switch away from it.
+      while task.on_cpu.load(Ordering::Acquire) {
+        spin_loop();
+      }
+      task.on_cpu.store(true, Ordering::Relaxed);
+      // pairs with the fence in `exit_current_and_run_next`, the
+      // threads of an exited process are dropped instead of run.
+      fence(Ordering::SeqCst);
+      if task.process.upgrade().map_or(true, |process| {
+        process.inner_exclusive_access().is_zombie
+      }) {
+        task.on_cpu.store(false, Ordering::Release);
+        continue;
+      }
+      let mut processor = processor().exclusive_access();
       let idle_task_cx_ptr =
         processor.get_idle_task_cx_ptr();
       // access coming task TCB exclusively
@@ -62,16 +104,34 @@ pub fn run_tasks() {
       let next_task_cx_ptr =
         &task_inner.task_cx as *const TaskContext;
       task_inner.task_status = TaskStatus::Running;
+      task_inner.dispatch_count += 1;
       drop(task_inner);
       // release coming task TCB manually
-      processor.current = Some(task);
+      processor.current = Some(Arc::clone(&task));
       // release processor manually
       drop(processor);
+      // harts running the process get its TLB shootdowns
+      let process = task.process.upgrade().unwrap();
+      process
+        .harts
+        .fetch_or(1 << hart_id(), Ordering::SeqCst);
+      let start = get_time_ms();
       unsafe {
         __switch(idle_task_cx_ptr, next_task_cx_ptr);
       }
+      process
+        .harts
+        .fetch_and(!(1 << hart_id()), Ordering::SeqCst);
+      drop(process);
+      // back from the task, account the time it has run
+      task.inner_exclusive_access().run_time +=
+        get_time_ms() - start;
+      task.on_cpu.store(false, Ordering::Release);
     } else {
-      println!("no tasks available in run_tasks");
+      // interrupts are off in the kernel, an idle hart wakes up
+      // sleeping tasks by itself.
+      check_timer();
+      spin_loop();
     }
   }
 }
@@ -79,12 +139,12 @@ pub fn run_tasks() {
 /// Take the current task, leaving a None in its place.
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>>
 {
-  PROCESSOR.exclusive_access().take_current()
+  processor().exclusive_access().take_current()
 }
 
 /// Get running task.
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-  PROCESSOR.exclusive_access().current()
+  processor().exclusive_access().current()
 }
 
 /// Get current process from current running task.
@@ -124,7 +184,7 @@ pub fn current_kstack_top() -> usize {
 
 /// Return to idle control flow for new scheduling
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-  let mut processor = PROCESSOR.exclusive_access();
+  let mut processor = processor().exclusive_access();
   let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
   drop(processor);
   unsafe {
@@ -0,0 +1,236 @@
+//! Scheduling policies behind [`TaskManager`](super::TaskManager).
+//!
+//! A policy decides which ready task runs next, the one in use is
+//! chosen by [`SchedulerImpl`](super::manager::SchedulerImpl) at
+//! build time: [`StrideScheduler`] by default, [`MlfqScheduler`]
+//! with the `mlfq` feature.
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use super::TaskControlBlock;
+
+/// The pass of a task advances by `BIG_STRIDE / priority` every time
+/// it is scheduled.
+pub const BIG_STRIDE: usize = 1 << 20;
+/// Priority of a newly created task.
+pub const DEFAULT_PRIORITY: usize = 16;
+/// The lowest priority, a task with it is scheduled least often.
+pub const MIN_PRIORITY: usize = 2;
+/// Number of queues of the MLFQ scheduler.
+pub const MLFQ_LEVELS: usize = 4;
+/// Timer ticks between two priority boosts of the MLFQ scheduler.
+pub const MLFQ_BOOST_TICKS: usize = 100;
+
+/// A scheduling policy over the ready tasks.
+pub trait Scheduler {
+  /// Create an empty scheduler
+  fn new() -> Self;
+  /// Add a ready task
+  fn add(&mut self, task: Arc<TaskControlBlock>);
+  /// Remove the task to run next, or `None` if no task is ready
+  fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+  /// Remove the specified task by matching its reference pointer,
+  /// return whether it was ready.
+  fn remove(
+    &mut self,
+    task: &Arc<TaskControlBlock>,
+  ) -> bool;
+  /// Account a timer tick to the running `task`, return whether it
+  /// should give up the CPU.
+  fn tick(
+    &mut self,
+    _task: &Arc<TaskControlBlock>,
+  ) -> bool {
+    true
+  }
+}
+
+/// A simple FIFO scheduler.
+#[allow(unused)]
+pub struct FifoScheduler {
+  ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for FifoScheduler {
+  fn new() -> Self {
+    Self {
+      ready_queue: VecDeque::new(),
+    }
+  }
+
+  fn add(&mut self, task: Arc<TaskControlBlock>) {
+    self.ready_queue.push_back(task);
+  }
+
+  fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+    self.ready_queue.pop_front()
+  }
+
+  fn remove(
+    &mut self,
+    task: &Arc<TaskControlBlock>,
+  ) -> bool {
+    if let Some(id) = self
+      .ready_queue
+      .iter()
+      .position(|t| Arc::ptr_eq(t, task))
+    {
+      self.ready_queue.remove(id);
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// Stride scheduling: the ready task with the smallest pass runs
+/// next, so a task is scheduled in proportion to its priority.
+pub struct StrideScheduler {
+  ready_queue: Vec<Arc<TaskControlBlock>>,
+  /// pass of the task scheduled last
+  min_pass: usize,
+}
+
+impl Scheduler for StrideScheduler {
+  fn new() -> Self {
+    Self {
+      ready_queue: Vec::new(),
+      min_pass: 0,
+    }
+  }
+
+  fn add(&mut self, task: Arc<TaskControlBlock>) {
+    // a task woken after a long sleep or newly created does not
+    // get to catch up with the time it was not ready.
+    let mut inner = task.inner_exclusive_access();
+    inner.pass = inner.pass.max(self.min_pass);
+    drop(inner);
+    self.ready_queue.push(task);
+  }
+
+  fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+    let (id, _) =
+      self.ready_queue.iter().enumerate().min_by_key(
+        |(_, t)| t.inner_exclusive_access().pass,
+      )?;
+    let task = self.ready_queue.remove(id);
+    let mut inner = task.inner_exclusive_access();
+    self.min_pass = inner.pass;
+    inner.pass += BIG_STRIDE / inner.priority;
+    drop(inner);
+    Some(task)
+  }
+
+  fn remove(
+    &mut self,
+    task: &Arc<TaskControlBlock>,
+  ) -> bool {
+    if let Some(id) = self
+      .ready_queue
+      .iter()
+      .position(|t| Arc::ptr_eq(t, task))
+    {
+      self.ready_queue.remove(id);
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// Multi-level feedback queue scheduling: a task at level `i` runs
+/// for `2^i` ticks before being demoted, a task which yields or blocks
+/// before using up its time slice is promoted, and every
+/// [`MLFQ_BOOST_TICKS`] all ready tasks go back to the top level so
+/// that CPU hogs do not starve.
+#[allow(unused)]
+pub struct MlfqScheduler {
+  queues: [VecDeque<Arc<TaskControlBlock>>; MLFQ_LEVELS],
+  /// ticks since the last priority boost
+  ticks: usize,
+}
+
+impl MlfqScheduler {
+  /// Time slice of `level` in ticks.
+  fn time_slice(level: usize) -> usize {
+    1 << level
+  }
+
+  /// Move all ready tasks and the running `task` to the top level.
+  fn boost(&mut self, task: &Arc<TaskControlBlock>) {
+    for level in 1..MLFQ_LEVELS {
+      while let Some(t) = self.queues[level].pop_front() {
+        self.queues[0].push_back(t);
+      }
+    }
+    for t in self.queues[0].iter().chain(Some(task)) {
+      let mut inner = t.inner_exclusive_access();
+      inner.level = 0;
+      inner.slice_ticks = 0;
+    }
+  }
+}
+
+impl Scheduler for MlfqScheduler {
+  fn new() -> Self {
+    Self {
+      queues: Default::default(),
+      ticks: 0,
+    }
+  }
+
+  fn add(&mut self, task: Arc<TaskControlBlock>) {
+    let mut inner = task.inner_exclusive_access();
+    if inner.preempted {
+      inner.preempted = false;
+    } else {
+      // the task yields or wakes up from blocking
+      inner.level = inner.level.saturating_sub(1);
+      inner.slice_ticks = 0;
+    }
+    let level = inner.level;
+    drop(inner);
+    self.queues[level].push_back(task);
+  }
+
+  fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+    self.queues.iter_mut().find_map(|q| q.pop_front())
+  }
+
+  fn remove(
+    &mut self,
+    task: &Arc<TaskControlBlock>,
+  ) -> bool {
+    for queue in self.queues.iter_mut() {
+      if let Some(id) =
+        queue.iter().position(|t| Arc::ptr_eq(t, task))
+      {
+        queue.remove(id);
+        return true;
+      }
+    }
+    false
+  }
+
+  fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+    self.ticks += 1;
+    if self.ticks == MLFQ_BOOST_TICKS {
+      self.ticks = 0;
+      self.boost(task);
+    }
+    let mut inner = task.inner_exclusive_access();
+    inner.slice_ticks += 1;
+    let level = inner.level;
+    let preempt =
+      if inner.slice_ticks >= Self::time_slice(level) {
+        // the whole time slice is used up
+        inner.level = (level + 1).min(MLFQ_LEVELS - 1);
+        inner.slice_ticks = 0;
+        true
+      } else {
+        // a task of a higher level is ready
+        self.queues[..level].iter().any(|q| !q.is_empty())
+      };
+    inner.preempted = preempt;
+    preempt
+  }
+}
@@ -40,7 +40,50 @@ bitflags! {
   }
 }
 
+/// What happens to a process which receives a signal without
+/// installing a handler for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalDefault {
+  Ignore,
+  Terminate,
+  Stop,
+  Continue,
+}
+
 impl SignalFlags {
+  /// Signals which can neither be caught, blocked nor ignored.
+  pub const UNCATCHABLE: Self =
+    Self::SIGKILL.union(Self::SIGSTOP);
+  /// Signals which stop a process by default.
+  pub const STOP: Self = Self::SIGSTOP
+    .union(Self::SIGTSTP)
+    .union(Self::SIGTTIN)
+    .union(Self::SIGTTOU);
+
+  /// The flag of signal number `signum`, if it is a valid one.
+  pub fn from_signum(signum: usize) -> Option<Self> {
+    if signum == 0 || signum > MAX_SIG {
+      None
+    } else {
+      Self::from_bits(1 << signum)
+    }
+  }
+
+  /// The default action of a single signal.
+  pub fn default_action(&self) -> SignalDefault {
+    if self.intersects(
+      Self::SIGCHLD | Self::SIGURG | Self::SIGWINCH,
+    ) {
+      SignalDefault::Ignore
+    } else if self.intersects(Self::STOP) {
+      SignalDefault::Stop
+    } else if self.contains(Self::SIGCONT) {
+      SignalDefault::Continue
+    } else {
+      SignalDefault::Terminate
+    }
+  }
+
   pub fn check_error(&self) -> Option<(i32, &'static str)> {
     if self.contains(Self::SIGINT) {
       Some((-2, "Killed, SIGINT=2"))
@@ -54,8 +97,12 @@ impl SignalFlags {
       Some((-9, "Killed, SIGKILL=9"))
     } else if self.contains(Self::SIGSEGV) {
       Some((-11, "Segmentation Fault, SIGSEGV=11"))
+    } else if !self.is_empty() {
+      Some((
+        -(self.bits().trailing_zeros() as i32),
+        "Terminated by signal",
+      ))
     } else {
-      //println!("[K] signalflags check_error  {:?}", self);
       None
     }
   }
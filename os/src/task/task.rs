@@ -1,20 +1,23 @@
 //! Types related to task(Thread) management
-use core::cell::RefMut;
+use crate::sync::SpinLockGuard;
 
 use super::{
   kstack_alloc, KernelStack, ProcessControlBlock,
-  TaskContext, TaskUserRes,
+  TaskContext, TaskUserRes, DEFAULT_PRIORITY,
 };
 use crate::{
-  mm::PhysPageNum, sync::UPSafeCell, trap::TrapContext,
+  mm::PhysPageNum, sync::SpinLock, trap::TrapContext,
 };
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::AtomicBool;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum TaskStatus {
   Ready,
   Running,
   Blocked,
+  /// Stopped by a signal, out of `TaskManager` until `SIGCONT`.
+  Stopped,
 }
 
 /// Task control block structure(Thread)
@@ -22,9 +25,12 @@ pub struct TaskControlBlock {
   // immutable
   pub process: Weak<ProcessControlBlock>,
   pub kstack: KernelStack,
+  /// Whether a hart is running the thread or has not yet switched
+  /// away from it, so that no other hart picks it up meanwhile.
+  pub on_cpu: AtomicBool,
 
   // mutable
-  inner: UPSafeCell<TaskControlBlockInner>,
+  inner: SpinLock<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
@@ -32,24 +38,27 @@ impl TaskControlBlock {
   /// - process: the process this thread belongs to.
   /// - ustack_base: the base address in same process space.
   /// - alloc_user_res: thread resources.
+  ///
+  /// Return `None` if there is no memory for the thread.
   pub fn new(
     process: Arc<ProcessControlBlock>,
     ustack_base: usize,
     alloc_user_res: bool,
-  ) -> Self {
+  ) -> Option<Self> {
     let res = TaskUserRes::new(
       Arc::clone(&process),
       ustack_base,
       alloc_user_res,
-    );
+    )?;
     let trap_cx_ppn = res.trap_cx_ppn();
-    let kstack = kstack_alloc();
+    let kstack = kstack_alloc()?;
     let kstack_top = kstack.get_top();
-    Self {
+    Some(Self {
       process: Arc::downgrade(&process),
       kstack,
+      on_cpu: AtomicBool::new(false),
       inner: unsafe {
-        UPSafeCell::new(TaskControlBlockInner {
+        SpinLock::new(TaskControlBlockInner {
           res: Some(res),
           trap_cx_ppn,
           task_cx: TaskContext::goto_trap_return(
@@ -57,14 +66,23 @@ impl TaskControlBlock {
           ),
           task_status: TaskStatus::Ready,
           exit_code: None,
+          priority: DEFAULT_PRIORITY,
+          pass: 0,
+          run_time: 0,
+          dispatch_count: 0,
+          level: 0,
+          slice_ticks: 0,
+          preempted: false,
+          handling_sig: None,
+          trap_ctx_backup: None,
         })
       },
-    }
+    })
   }
 
   pub fn inner_exclusive_access(
     &self,
-  ) -> RefMut<'_, TaskControlBlockInner> {
+  ) -> SpinLockGuard<'_, TaskControlBlockInner> {
     self.inner.exclusive_access()
   }
 
@@ -89,6 +107,25 @@ pub struct TaskControlBlockInner {
   /// `exit_code` will save in its TCB, and then waiting for recycle its resources by it's parent process
   /// by calling `waittid()`.
   pub exit_code: Option<i32>,
+  /// Scheduling priority, a larger one gets a larger share of CPU.
+  pub priority: usize,
+  /// Pass of stride scheduling.
+  pub pass: usize,
+  /// Total time in milliseconds the thread has been running.
+  pub run_time: usize,
+  /// Number of times the thread has been scheduled.
+  pub dispatch_count: usize,
+  /// Queue level of MLFQ scheduling, 0 is the highest.
+  pub level: usize,
+  /// Ticks used of the time slice at the current level.
+  pub slice_ticks: usize,
+  /// Whether the thread is switched out by the timer.
+  pub preempted: bool,
+  /// The signal whose user handler the thread is running.
+  pub handling_sig: Option<usize>,
+  /// Trap context of the thread before entering the signal handler,
+  /// restored by `sigreturn`.
+  pub trap_ctx_backup: Option<TrapContext>,
 }
 
 impl TaskControlBlockInner {
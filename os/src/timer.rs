@@ -2,7 +2,7 @@
 
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use crate::task::{wakeup_task, TaskControlBlock};
 use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
@@ -57,8 +57,8 @@ impl Ord for TimerCondVar {
 }
 
 lazy_static! {
-  static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> = unsafe {
-    UPSafeCell::new(BinaryHeap::<TimerCondVar>::new())
+  static ref TIMERS: SpinLock<BinaryHeap<TimerCondVar>> = unsafe {
+    SpinLock::new(BinaryHeap::<TimerCondVar>::new())
   };
 }
 
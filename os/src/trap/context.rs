@@ -18,6 +18,9 @@ pub struct TrapContext {
   pub kernel_sp: usize,
   /// The virtual address of the kernel trap handler entrypoint.
   pub trap_handler: usize,
+  /// Id of the hart the thread returned to user space on, it is
+  /// loaded into `tp` when trapping back into the kernel.
+  pub hart_id: usize,
 }
 
 impl TrapContext {
@@ -43,6 +46,7 @@ impl TrapContext {
       kernel_satp,  // address of page table
       kernel_sp,    // kernel stack
       trap_handler, // address of trap_handler function
+      hart_id: 0,   // set by `trap_return`
     };
     cx.set_sp(sp); // app's user stack pointer
     cx // return initial Trap Context of app
@@ -25,12 +25,16 @@ pub use context::TrapContext;
 
 use crate::{
   config::TRAMPOLINE,
+  fs::{flush_fs, poll_console},
+  mm::VirtAddr,
   syscall::syscall,
   task::{
-    check_signals_of_current, current_add_signal,
-    current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next, SignalFlags,
+    current_add_signal, current_handle_page_fault,
+    current_process_exited, current_task, current_trap_cx,
+    current_trap_cx_user_va, current_user_token,
+    exit_current_and_run_next, handle_signals, hart_id,
+    kill_current_and_run_next,
+    suspend_current_and_run_next, tick_task, SignalFlags,
   },
   timer::{check_timer, set_next_trigger},
 };
@@ -74,6 +78,7 @@ fn set_user_trap_entry() {
 /// finally, jump to new address of __restore asm function.
 pub fn trap_return() -> ! {
   set_user_trap_entry();
+  current_trap_cx().hart_id = hart_id();
   let trap_cx_user_va = current_trap_cx_user_va();
   let user_satp = current_user_token();
   extern "C" {
@@ -108,13 +113,34 @@ pub fn trap_handler() -> ! {
       let mut cx = current_trap_cx();
       cx.sepc += 4;
       // get system call return value
-      let result =
-        syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]])
-          as usize;
+      let result = syscall(
+        cx.x[17],
+        [
+          cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14],
+          cx.x[15],
+        ],
+      ) as usize;
       // cx is changed during sys_exec, so we have to call it again
       cx = current_trap_cx();
       cx.x[10] = result;
     }
+    Trap::Exception(Exception::StorePageFault)
+      if current_handle_page_fault(
+        VirtAddr::from(stval).floor(),
+        true,
+      ) =>
+    {
+      // a lazy or copy-on-write page is ready, just retry the store.
+    }
+    Trap::Exception(Exception::LoadPageFault)
+    | Trap::Exception(Exception::InstructionPageFault)
+      if current_handle_page_fault(
+        VirtAddr::from(stval).floor(),
+        false,
+      ) =>
+    {
+      // a lazy page is ready, just retry the access.
+    }
     Trap::Exception(Exception::StoreFault)
     | Trap::Exception(Exception::StorePageFault)
     | Trap::Exception(Exception::InstructionFault)
@@ -139,7 +165,11 @@ pub fn trap_handler() -> ! {
     Trap::Interrupt(Interrupt::SupervisorTimer) => {
       set_next_trigger();
       check_timer();
-      suspend_current_and_run_next();
+      flush_fs();
+      poll_console();
+      if tick_task(&current_task().unwrap()) {
+        suspend_current_and_run_next();
+      }
     }
     _ => {
       panic!(
@@ -149,10 +179,14 @@ pub fn trap_handler() -> ! {
       );
     }
   }
-  // check signals
-  if let Some((errno, msg)) = check_signals_of_current() {
+  // the main thread has exited, the others follow it
+  if current_process_exited() {
+    exit_current_and_run_next(0);
+  }
+  // deliver signals
+  if let Some((errno, msg)) = handle_signals() {
     println!("[kernel] {}", msg);
-    exit_current_and_run_next(errno);
+    kill_current_and_run_next(-errno as usize);
   }
   trap_return();
 }
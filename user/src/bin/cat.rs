@@ -5,7 +5,7 @@
 extern crate user_lib;
 extern crate alloc;
 
-use user_lib::{close, open, read, OpenFlags};
+use user_lib::{close, fstat, open, read, OpenFlags, Stat};
 
 #[no_mangle]
 pub fn main(argc: usize, argv: &[&str]) -> i32 {
@@ -15,6 +15,12 @@ pub fn main(argc: usize, argv: &[&str]) -> i32 {
     panic!("Error occured when opening file");
   }
   let fd = fd as usize;
+  let mut stat = Stat::default();
+  if fstat(fd, &mut stat) == 0 && stat.is_dir() {
+    println!("cat: {}: Is a directory", argv[1]);
+    close(fd);
+    return -1;
+  }
   let mut buf = [0u8; 256];
   loop {
     let size = read(fd, &mut buf) as usize;
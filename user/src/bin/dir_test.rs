@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  chdir, close, getcwd, getdents, link, mkdir, open, read,
+  rename, rmdir, unlink, unlinkat, write, Dirents,
+  OpenFlags, AT_REMOVEDIR, DT_DIR, DT_REG,
+};
+
+fn cwd(buf: &mut [u8]) -> &str {
+  let len = getcwd(buf);
+  assert!(len > 0);
+  core::str::from_utf8(&buf[..len as usize - 1]).unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut buf = [0u8; 64];
+  assert_eq!(cwd(&mut buf), "/");
+
+  assert_eq!(mkdir("dir_test\0"), 0);
+  assert_eq!(mkdir("dir_test\0"), -1);
+  assert_eq!(mkdir("no_such_dir/sub\0"), -1);
+  assert_eq!(mkdir("dir_test/sub\0"), 0);
+
+  assert_eq!(chdir("dir_test/./sub\0"), 0);
+  assert_eq!(cwd(&mut buf), "/dir_test/sub");
+  let fd =
+    open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+  assert!(fd > 0);
+  write(fd as usize, b"in a directory");
+  close(fd as usize);
+
+  assert_eq!(chdir("..\0"), 0);
+  assert_eq!(cwd(&mut buf), "/dir_test");
+  let fd = open("sub/file\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  let len = read(fd as usize, &mut buf);
+  assert_eq!(&buf[..len as usize], b"in a directory");
+  close(fd as usize);
+
+  // directories are not writable, files are not directories
+  assert_eq!(open("sub\0", OpenFlags::WRONLY), -1);
+  assert_eq!(chdir("sub/file\0"), -1);
+  assert_eq!(getcwd(&mut buf[..4]), -1);
+
+  // entries are listed with their types, a few at a time
+  let fd = open("sub\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  let fd = fd as usize;
+  let mut dirents = [0u8; 128];
+  assert_eq!(getdents(fd, &mut dirents[..16]), -1);
+  let len = getdents(fd, &mut dirents[..48]);
+  assert!(len > 0);
+  let mut entries = Dirents::new(&dirents[..len as usize]);
+  let dot = entries.next().unwrap();
+  assert_eq!((dot.name, dot.type_), (".", DT_DIR));
+  let dot_dot = entries.next().unwrap();
+  assert_eq!((dot_dot.name, dot_dot.type_), ("..", DT_DIR));
+  assert!(entries.next().is_none());
+  let len = getdents(fd, &mut dirents);
+  let mut entries = Dirents::new(&dirents[..len as usize]);
+  let file = entries.next().unwrap();
+  assert_eq!((file.name, file.type_), ("file", DT_REG));
+  assert!(entries.next().is_none());
+  assert_eq!(getdents(fd, &mut dirents), 0);
+  close(fd);
+  assert_eq!(getdents(0, &mut dirents), -1);
+
+  // entries move between directories, only empty directories can be
+  // removed
+  assert_eq!(rename("sub/file\0", "moved\0"), 0);
+  assert_eq!(open("sub/file\0", OpenFlags::RDONLY), -1);
+  assert_eq!(mkdir("sub/inner\0"), 0);
+  assert_eq!(rename("sub\0", "sub/inner/sub\0"), -1);
+  assert_eq!(rename("sub/inner\0", "inner\0"), 0);
+  assert_eq!(chdir("inner/..\0"), 0);
+  assert_eq!(cwd(&mut buf), "/dir_test");
+  assert_eq!(rmdir("moved\0"), -1);
+  assert_eq!(unlink("sub\0"), -1);
+  assert_eq!(mkdir("sub/keep\0"), 0);
+  assert_eq!(rmdir("sub\0"), -1);
+  assert_eq!(unlinkat(-1, "sub/keep\0", AT_REMOVEDIR), -1);
+  assert_eq!(rmdir("sub/keep\0"), 0);
+  // a file lives on through its other links and open files
+  assert_eq!(link("moved\0", "sub/linked\0"), 0);
+  assert_eq!(link("moved\0", "sub/linked\0"), -1);
+  assert_eq!(link("sub\0", "sub_link\0"), -1);
+  assert_eq!(unlink("moved\0"), 0);
+  assert_eq!(open("moved\0", OpenFlags::RDONLY), -1);
+  let fd = open("sub/linked\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  assert_eq!(unlink("sub/linked\0"), 0);
+  let len = read(fd as usize, &mut buf);
+  assert_eq!(&buf[..len as usize], b"in a directory");
+  close(fd as usize);
+  assert_eq!(rmdir("inner\0"), 0);
+  assert_eq!(rmdir("sub\0"), 0);
+
+  // the working directory follows its renames and is gone once removed
+  assert_eq!(mkdir("old\0"), 0);
+  assert_eq!(chdir("old\0"), 0);
+  assert_eq!(
+    rename("/dir_test/old\0", "/dir_test/new\0"),
+    0
+  );
+  assert_eq!(cwd(&mut buf), "/dir_test/new");
+  assert_eq!(rmdir("/dir_test/new\0"), 0);
+  assert_eq!(getcwd(&mut buf), -1);
+  assert_eq!(mkdir("orphan\0"), -1);
+  assert_eq!(chdir("..\0"), -1);
+
+  assert_eq!(chdir("/\0"), 0);
+  assert_eq!(cwd(&mut buf), "/");
+  assert_eq!(rmdir("dir_test\0"), 0);
+  assert_eq!(chdir("dir_test\0"), -1);
+  println!("dir_test passed!");
+  0
+}
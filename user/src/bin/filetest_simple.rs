@@ -10,8 +10,12 @@ use user_lib::{close, open, read, write, OpenFlags};
 pub fn main() -> i32 {
   let test_str = "Hello, world!";
   let filea = "filea\0";
-  let fd =
-    open(filea, OpenFlags::CREATE | OpenFlags::WRONLY);
+  let fd = open(
+    filea,
+    OpenFlags::CREATE
+      | OpenFlags::TRUNC
+      | OpenFlags::WRONLY,
+  );
   assert!(fd > 0);
   let fd = fd as usize;
   write(fd, test_str.as_bytes());
@@ -12,8 +12,12 @@ pub fn main() -> i32 {
   for (i, ch) in buffer.iter_mut().enumerate() {
     *ch = i as u8;
   }
-  let f =
-    open("testf\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+  let f = open(
+    "testf\0",
+    OpenFlags::CREATE
+      | OpenFlags::TRUNC
+      | OpenFlags::WRONLY,
+  );
   if f < 0 {
     panic!("Open test file failed!");
   }
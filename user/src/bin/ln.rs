@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{link, symlink};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+  let (symbolic, args) = match argv.get(1) {
+    Some(&"-s") => (true, &argv[2..]),
+    _ => (false, &argv[1..argc]),
+  };
+  if args.len() != 2 {
+    println!("usage: ln [-s] <target> <link>");
+    return -1;
+  }
+  let ret = if symbolic {
+    symlink(args[0], args[1])
+  } else {
+    link(args[0], args[1])
+  };
+  if ret == -1 {
+    println!(
+      "ln: cannot create link {} to {}",
+      args[1], args[0]
+    );
+    return -1;
+  }
+  0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  close, getdents, open, Dirents, OpenFlags, DT_DIR, DT_LNK,
+};
+
+/// List the directory at `path`, marking directories with `/` and
+/// symbolic links with `@`.
+fn list(path: &str) -> i32 {
+  let fd = open(path, OpenFlags::RDONLY);
+  if fd == -1 {
+    println!("ls: cannot access {}", path);
+    return -1;
+  }
+  let fd = fd as usize;
+  let mut buf = [0u8; 512];
+  loop {
+    let len = getdents(fd, &mut buf);
+    if len == -1 {
+      println!("ls: cannot read directory {}", path);
+      close(fd);
+      return -1;
+    }
+    if len == 0 {
+      break;
+    }
+    for dirent in Dirents::new(&buf[..len as usize]) {
+      let suffix = match dirent.type_ {
+        DT_DIR => "/",
+        DT_LNK => "@",
+        _ => "",
+      };
+      println!("{}{}", dirent.name, suffix);
+    }
+  }
+  close(fd);
+  0
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+  if argc < 2 {
+    return list(".\0");
+  }
+  for path in argv[1..].iter() {
+    if argc > 2 {
+      println!("{}:", path);
+    }
+    if list(path) == -1 {
+      return -1;
+    }
+  }
+  0
+}
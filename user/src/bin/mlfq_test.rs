@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  exit, fork, get_time, sched_stat, sleep, waitpid, yield_,
+  SchedStat,
+};
+
+/// `MLFQ_LEVELS` of the kernel
+const MLFQ_LEVELS: usize = 4;
+/// More CPU hogs than harts
+const HOGS: usize = 8;
+const HOG_TIME_MS: isize = 1000;
+/// A level change is expected well within this time
+const TIMEOUT_MS: isize = 3000;
+/// Longest a sleeping thread may wait for a CPU among the hogs
+const MAX_LATENCY_MS: isize = 100;
+
+fn level() -> usize {
+  let mut stat = SchedStat::default();
+  assert_eq!(sched_stat(&mut stat), 0);
+  stat.level
+}
+
+/// Spin until the level of the thread satisfies `cond`, return
+/// whether it did within `TIMEOUT_MS`.
+fn spin_until(cond: impl Fn(usize) -> bool) -> bool {
+  let start = get_time();
+  while get_time() - start < TIMEOUT_MS {
+    if cond(level()) {
+      return true;
+    }
+  }
+  false
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  if !cfg!(feature = "mlfq") {
+    // stride scheduling keeps every thread at level 0
+    let start = get_time();
+    while get_time() - start < 200 {
+      assert_eq!(level(), 0);
+    }
+    println!("mlfq_test passed under stride scheduling!");
+    return 0;
+  }
+
+  // using up its time slices sinks a thread to the lowest level
+  assert!(spin_until(|level| level == MLFQ_LEVELS - 1));
+  // giving up the CPU early moves it up
+  assert!(spin_until(|level| level == MLFQ_LEVELS - 1));
+  yield_();
+  assert!(level() < MLFQ_LEVELS - 1);
+  // and the periodic boost brings it back to the top
+  assert!(spin_until(|level| level == MLFQ_LEVELS - 1));
+  assert!(spin_until(|level| level == 0));
+
+  // a thread which mostly sleeps, like the shell, is scheduled soon
+  // after it wakes up even if the CPU hogs outnumber the harts
+  let mut pids = [0isize; HOGS];
+  for pid in pids.iter_mut() {
+    *pid = fork();
+    if *pid == 0 {
+      let start = get_time();
+      while get_time() - start < HOG_TIME_MS {}
+      exit(0);
+    }
+  }
+  let mut max_latency = 0;
+  for _ in 0..20 {
+    let start = get_time();
+    sleep(10);
+    max_latency = max_latency.max(get_time() - start - 10);
+  }
+  println!("max wake-up latency {}ms", max_latency);
+  assert!(max_latency <= MAX_LATENCY_MS);
+  for pid in pids {
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+  }
+  println!("mlfq_test passed!");
+  0
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  close, fork, mmap, mprotect, munmap, open, read, waitpid,
+  write, MmapFlags, MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// `USER_HEAP_BASE` and `USER_MMAP_BASE` of the kernel
+const USER_HEAP_BASE: usize = 0x4000_0000;
+const USER_MMAP_BASE: usize = 0x1_0000_0000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+  // anonymous private mapping, zeroed and shared copy-on-write by fork
+  let len = PAGE_SIZE * 4;
+  let addr = mmap(
+    0,
+    len,
+    MmapProt::READ | MmapProt::WRITE,
+    MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    0,
+    0,
+  );
+  assert!(addr > 0);
+  let buf = unsafe {
+    core::slice::from_raw_parts_mut(addr as *mut u8, len)
+  };
+  assert!(buf.iter().all(|b| *b == 0));
+  buf.fill(0x11);
+  let pid = fork();
+  if pid == 0 {
+    buf.fill(0x22);
+    return 0;
+  }
+  let mut exit_code = 0;
+  waitpid(pid as usize, &mut exit_code);
+  assert!(buf.iter().all(|b| *b == 0x11));
+  assert_eq!(
+    mprotect(addr as usize, PAGE_SIZE, MmapProt::READ),
+    0
+  );
+  assert_eq!(munmap(addr as usize, len), 0);
+
+  // shared file mapping, stores go back to the file
+  let name = "mmap_file\0";
+  let fd = open(
+    name,
+    OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+  );
+  assert!(fd > 0);
+  let fd = fd as usize;
+  write(fd, &[b'a'; 100]);
+  let addr = mmap(
+    0,
+    100,
+    MmapProt::READ | MmapProt::WRITE,
+    MmapFlags::SHARED,
+    fd,
+    0,
+  );
+  assert!(addr > 0);
+  close(fd);
+  let buf = unsafe {
+    core::slice::from_raw_parts_mut(addr as *mut u8, 100)
+  };
+  assert!(buf.iter().all(|b| *b == b'a'));
+  buf[..10].fill(b'b');
+  assert_eq!(munmap(addr as usize, 100), 0);
+
+  let fd = open(name, OpenFlags::RDONLY);
+  assert!(fd > 0);
+  let fd = fd as usize;
+  let mut buffer = [0u8; 200];
+  assert_eq!(read(fd, &mut buffer), 100);
+  close(fd);
+  assert!(buffer[..10].iter().all(|b| *b == b'b'));
+  assert!(buffer[10..100].iter().all(|b| *b == b'a'));
+
+  // the heap range is kept for brk, a hint there is moved past it
+  let prot = MmapProt::READ | MmapProt::WRITE;
+  let anon = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+  assert_eq!(
+    mmap(
+      USER_HEAP_BASE,
+      PAGE_SIZE,
+      prot,
+      anon | MmapFlags::FIXED,
+      0,
+      0
+    ),
+    -1
+  );
+  let addr =
+    mmap(USER_HEAP_BASE, PAGE_SIZE, prot, anon, 0, 0);
+  assert!(addr as usize >= USER_MMAP_BASE);
+  assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+  println!("mmap_test passed!");
+  0
+}
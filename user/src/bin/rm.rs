@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::unlink;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+  assert!(argc >= 2);
+  for path in argv[1..].iter() {
+    if unlink(path) == -1 {
+      println!("rm: cannot remove {}", path);
+      return -1;
+    }
+  }
+  0
+}
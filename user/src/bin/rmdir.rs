@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::rmdir;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+  assert!(argc >= 2);
+  for path in argv[1..].iter() {
+    if rmdir(path) == -1 {
+      println!("rmdir: failed to remove {}", path);
+      return -1;
+    }
+  }
+  0
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+const HEAP_TEST_SIZE: usize = 1 << 20;
+
+#[no_mangle]
+fn main() -> i32 {
+  // the allocator grows the heap on demand, far beyond its first chunk.
+  let mut v: Vec<usize> = Vec::new();
+  for i in 0..HEAP_TEST_SIZE / core::mem::size_of::<usize>()
+  {
+    v.push(i);
+  }
+  for (i, x) in v.iter().enumerate() {
+    assert_eq!(i, *x);
+  }
+  drop(v);
+
+  // grow and shrink the heap by hand.
+  let origin = brk(0);
+  assert_eq!(sbrk(4096), origin);
+  let p = origin as *mut u8;
+  unsafe {
+    p.write_volatile(0x5a);
+    assert_eq!(p.read_volatile(), 0x5a);
+  }
+  assert_eq!(brk(0), origin + 4096);
+  assert_eq!(sbrk(-4096), origin + 4096);
+  assert_eq!(brk(0), origin);
+  assert_eq!(brk(1), -1);
+  println!("sbrk_test passed!");
+  0
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  close, lseek, open, pipe, pread, pwrite, read, unlink,
+  write, OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut buf = [0u8; 32];
+  let fd = open(
+    "seek_file\0",
+    OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+  );
+  assert!(fd > 0);
+  let fd = fd as usize;
+  assert_eq!(write(fd, b"hello, world"), 12);
+
+  // rewind and read again
+  assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+  assert_eq!(read(fd, &mut buf[..5]), 5);
+  assert_eq!(&buf[..5], b"hello");
+  assert_eq!(lseek(fd, 2, SEEK_CUR), 7);
+  assert_eq!(read(fd, &mut buf), 5);
+  assert_eq!(&buf[..5], b"world");
+  assert_eq!(lseek(fd, -5, SEEK_END), 7);
+  assert_eq!(lseek(fd, -13, SEEK_END), -1);
+  assert_eq!(lseek(fd, 0, 3), -1);
+
+  // positioned I/O leaves the offset alone
+  assert_eq!(pwrite(fd, b"W", 7), 1);
+  assert_eq!(pread(fd, &mut buf[..5], 7), 5);
+  assert_eq!(&buf[..5], b"World");
+  assert_eq!(lseek(fd, 0, SEEK_CUR), 7);
+  assert_eq!(pread(fd, &mut buf, 100), 0);
+
+  // writing past the end leaves zeros in between
+  assert_eq!(lseek(fd, 16, SEEK_SET), 16);
+  assert_eq!(write(fd, b"!"), 1);
+  assert_eq!(pread(fd, &mut buf, 0), 17);
+  assert_eq!(&buf[..17], b"hello, World\0\0\0\0!");
+  close(fd);
+
+  // every write of an appending file goes to its end, creating it
+  // does not clear it when it exists
+  let fd = open(
+    "seek_file\0",
+    OpenFlags::CREATE
+      | OpenFlags::WRONLY
+      | OpenFlags::APPEND,
+  );
+  assert!(fd > 0);
+  let fd = fd as usize;
+  assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+  assert_eq!(write(fd, b"?"), 1);
+  assert_eq!(lseek(fd, 0, SEEK_CUR), 18);
+  close(fd);
+  let fd = open("seek_file\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  let fd = fd as usize;
+  assert_eq!(read(fd, &mut buf), 18);
+  assert_eq!(&buf[16..18], b"!?");
+  close(fd);
+
+  // pipes cannot seek
+  let mut pipe_fd = [0usize; 2];
+  pipe(&mut pipe_fd);
+  assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+  assert_eq!(pread(pipe_fd[0], &mut buf, 0), -1);
+  close(pipe_fd[0]);
+  close(pipe_fd[1]);
+
+  assert_eq!(unlink("seek_file\0"), 0);
+  println!("seek_test passed!");
+  0
+}
@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+  exit, fork, getpid, kill, sigaction, sigprocmask,
+  sigreturn, sleep, waitpid, SignalAction, SignalFlags,
+  SIGKILL, SIGSTOP, SIGUSR1, SIGUSR2, SIG_IGN,
+};
+
+static CAUGHT: AtomicUsize = AtomicUsize::new(0);
+
+fn handler(signum: usize) {
+  CAUGHT.fetch_add(signum, Ordering::SeqCst);
+  sigreturn();
+}
+
+fn kill_self(signal: SignalFlags) {
+  assert_eq!(kill(getpid() as usize, signal.bits()), 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  // a user handler runs and returns to where it was interrupted
+  let action = SignalAction {
+    handler: handler as usize,
+    mask: SignalFlags::empty(),
+  };
+  let mut old_action = SignalAction::default();
+  assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+  assert_eq!(
+    sigaction(SIGUSR1, None, Some(&mut old_action)),
+    0
+  );
+  assert_eq!(old_action.handler, handler as usize);
+  kill_self(SignalFlags::SIGUSR1);
+  assert_eq!(
+    CAUGHT.load(Ordering::SeqCst),
+    SIGUSR1 as usize
+  );
+
+  // a blocked signal stays pending until it is unblocked
+  sigprocmask(SignalFlags::SIGUSR1);
+  kill_self(SignalFlags::SIGUSR1);
+  assert_eq!(
+    CAUGHT.load(Ordering::SeqCst),
+    SIGUSR1 as usize
+  );
+  assert_eq!(
+    sigprocmask(SignalFlags::empty()),
+    SignalFlags::SIGUSR1
+  );
+  assert_eq!(
+    CAUGHT.load(Ordering::SeqCst),
+    2 * SIGUSR1 as usize
+  );
+
+  // an ignored signal does nothing
+  let ignore = SignalAction {
+    handler: SIG_IGN,
+    mask: SignalFlags::empty(),
+  };
+  assert_eq!(sigaction(SIGUSR2, Some(&ignore), None), 0);
+  kill_self(SignalFlags::SIGUSR2);
+
+  // SIGKILL and SIGSTOP cannot be caught
+  assert_eq!(sigaction(SIGKILL, Some(&action), None), -1);
+  assert_eq!(sigaction(SIGSTOP, Some(&ignore), None), -1);
+
+  // the default action of SIGUSR2 terminates the child, the handler
+  // of SIGUSR1 is inherited
+  let pid = fork();
+  if pid == 0 {
+    kill_self(SignalFlags::SIGUSR1);
+    assert_eq!(
+      CAUGHT.load(Ordering::SeqCst),
+      3 * SIGUSR1 as usize
+    );
+    sigaction(
+      SIGUSR2,
+      Some(&SignalAction::default()),
+      None,
+    );
+    kill_self(SignalFlags::SIGUSR2);
+    exit(0);
+  }
+  let mut exit_code = 0;
+  waitpid(pid as usize, &mut exit_code);
+  assert_eq!(exit_code, -SIGUSR2);
+
+  // a stopped child runs again after SIGCONT
+  let pid = fork();
+  if pid == 0 {
+    kill_self(SignalFlags::SIGSTOP);
+    exit(7);
+  }
+  sleep(50);
+  assert_eq!(
+    kill(pid as usize, SignalFlags::SIGCONT.bits()),
+    0
+  );
+  waitpid(pid as usize, &mut exit_code);
+  assert_eq!(exit_code, 7);
+
+  // and can still be killed while it is stopped
+  let pid = fork();
+  if pid == 0 {
+    kill_self(SignalFlags::SIGSTOP);
+    exit(0);
+  }
+  sleep(50);
+  assert_eq!(
+    kill(pid as usize, SignalFlags::SIGKILL.bits()),
+    0
+  );
+  waitpid(pid as usize, &mut exit_code);
+  assert_eq!(exit_code, -SIGKILL);
+
+  println!("sig_tests passed!");
+  0
+}
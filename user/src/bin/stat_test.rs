@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  close, fstat, get_time, link, mkdir, open, rmdir, sleep,
+  unlink, write, OpenFlags, Stat, StatMode,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut stat = Stat::default();
+  let fd = open(
+    "stat_file\0",
+    OpenFlags::CREATE
+      | OpenFlags::TRUNC
+      | OpenFlags::WRONLY,
+  );
+  assert!(fd > 0);
+  let fd = fd as usize;
+  assert_eq!(fstat(fd, &mut stat), 0);
+  assert_eq!(stat.file_type(), StatMode::FILE);
+  assert_eq!(stat.permissions(), 0o644);
+  assert_eq!((stat.nlink, stat.uid, stat.gid), (1, 0, 0));
+  assert_eq!(stat.size, 0);
+  // the RTC counts from the Unix epoch, not from boot
+  assert!(stat.mtime_ms > get_time() as u64);
+  let created = stat.mtime_ms;
+
+  sleep(20);
+  write(fd, b"hello, stat");
+  assert_eq!(link("stat_file\0", "stat_link\0"), 0);
+  assert_eq!(fstat(fd, &mut stat), 0);
+  assert_eq!(stat.size, 11);
+  assert_eq!(stat.nlink, 2);
+  assert!(stat.mtime_ms > created);
+  assert!(stat.ctime_ms >= stat.mtime_ms);
+  let ino = stat.ino;
+  close(fd);
+
+  let fd = open("stat_link\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  assert_eq!(fstat(fd as usize, &mut stat), 0);
+  assert_eq!(stat.ino, ino);
+  close(fd as usize);
+
+  assert_eq!(mkdir("stat_dir\0"), 0);
+  let fd = open("stat_dir\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  assert_eq!(fstat(fd as usize, &mut stat), 0);
+  assert!(stat.is_dir());
+  assert_eq!(stat.permissions(), 0o755);
+  close(fd as usize);
+
+  // the console is not on the file system
+  assert_eq!(fstat(1, &mut stat), -1);
+  assert_eq!(fstat(99, &mut stat), -1);
+
+  assert_eq!(rmdir("stat_dir\0"), 0);
+  assert_eq!(unlink("stat_file\0"), 0);
+  assert_eq!(unlink("stat_link\0"), 0);
+  println!("stat_test passed!");
+  0
+}
@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+  fork, kill, sigaction, sigreturn, sleep, waitpid_options,
+  SignalAction, SignalFlags, WaitFlags, WaitStatus,
+  SIGCHLD, SIGSTOP, SIGTERM,
+};
+
+static SIGCHLD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn on_sigchld(_signum: usize) {
+  SIGCHLD_COUNT.fetch_add(1, Ordering::SeqCst);
+  sigreturn();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let action = SignalAction {
+    handler: on_sigchld as usize,
+    mask: SignalFlags::empty(),
+  };
+  assert_eq!(sigaction(SIGCHLD, Some(&action), None), 0);
+
+  let pid = fork();
+  if pid == 0 {
+    loop {
+      sleep(10);
+    }
+  }
+  let pid = pid as usize;
+  let mut status = WaitStatus::Exited(0);
+
+  // nothing to report while the child runs
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::NOHANG | WaitFlags::UNTRACED,
+    ),
+    -2
+  );
+
+  assert_eq!(kill(pid, SignalFlags::SIGSTOP.bits()), 0);
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::UNTRACED
+    ),
+    pid as isize
+  );
+  assert_eq!(status, WaitStatus::Stopped(SIGSTOP));
+  // the stop is reported only once
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::NOHANG | WaitFlags::UNTRACED,
+    ),
+    -2
+  );
+
+  assert_eq!(kill(pid, SignalFlags::SIGCONT.bits()), 0);
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::CONTINUED,
+    ),
+    pid as isize
+  );
+  assert_eq!(status, WaitStatus::Continued);
+
+  // a stopped child still dies of a terminating signal once continued
+  assert_eq!(kill(pid, SignalFlags::SIGTSTP.bits()), 0);
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::UNTRACED
+    ),
+    pid as isize
+  );
+  assert_eq!(kill(pid, SignalFlags::SIGTERM.bits()), 0);
+  assert_eq!(kill(pid, SignalFlags::SIGCONT.bits()), 0);
+  assert_eq!(
+    waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::empty()
+    ),
+    pid as isize
+  );
+  assert_eq!(status, WaitStatus::Signaled(SIGTERM));
+
+  // pending signals of one kind do not queue, so the stops, continues
+  // and the exit of the child may be told by fewer SIGCHLDs.
+  assert!(SIGCHLD_COUNT.load(Ordering::SeqCst) > 0);
+  println!("stop_test passed!");
+  0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  exit, fork, get_priority, get_time, sched_stat,
+  set_priority, waitpid, SchedStat,
+};
+
+const PRIORITIES: [isize; 3] = [4, 8, 16];
+const RUN_TIME_MS: isize = 1000;
+
+/// Spin until `RUN_TIME_MS` has passed and count the rounds done,
+/// children with a larger priority are expected to count more.
+fn spin(priority: isize, forked: isize) {
+  let start = get_time();
+  let mut count = 0;
+  while get_time() - start < RUN_TIME_MS {
+    count += 1;
+  }
+  let mut stat = SchedStat::default();
+  assert_eq!(sched_stat(&mut stat), 0);
+  println!(
+    "priority {}: count {}, run {}ms in {} dispatches",
+    priority, count, stat.run_time, stat.dispatch_count
+  );
+  // the timer switched the spinning thread out again and again, and
+  // it cannot have run longer than it has existed
+  assert!(stat.dispatch_count > 1);
+  assert!(stat.run_time > 0);
+  assert!(stat.run_time as isize <= get_time() - forked);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut stat = SchedStat::default();
+  assert_eq!(sched_stat(&mut stat), 0);
+  assert!(stat.dispatch_count >= 1);
+  assert_eq!(get_priority(), 16);
+  assert_eq!(set_priority(1), -1);
+  assert_eq!(set_priority(0), -1);
+  assert_eq!(set_priority(5), 5);
+  assert_eq!(get_priority(), 5);
+  assert_eq!(set_priority(16), 16);
+
+  let mut pids = [0isize; PRIORITIES.len()];
+  for (i, priority) in PRIORITIES.iter().enumerate() {
+    let forked = get_time();
+    let pid = fork();
+    if pid == 0 {
+      set_priority(*priority);
+      spin(*priority, forked);
+      exit(0);
+    }
+    pids[i] = pid;
+  }
+  for pid in pids {
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+  }
+  println!("stride_test passed!");
+  0
+}
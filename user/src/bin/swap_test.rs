@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  exit, fork, mmap, munmap, waitpid, MmapFlags, MmapProt,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// more than the physical memory of the kernel
+const SWAP_TEST_SIZE: usize = 8 << 20;
+
+fn page(addr: usize, i: usize) -> *mut usize {
+  (addr + i * PAGE_SIZE) as *mut usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let addr = mmap(
+    0,
+    SWAP_TEST_SIZE,
+    MmapProt::READ | MmapProt::WRITE,
+    MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    0,
+    0,
+  );
+  assert!(addr > 0);
+  let addr = addr as usize;
+  let pages = SWAP_TEST_SIZE / PAGE_SIZE;
+  // early pages are swapped out to make room for the later ones
+  for i in 0..pages {
+    unsafe { page(addr, i).write_volatile(i) };
+  }
+  for i in 0..pages {
+    assert_eq!(unsafe { page(addr, i).read_volatile() }, i);
+  }
+
+  // swapped out pages are copied to the child as well
+  let pid = fork();
+  if pid == 0 {
+    for i in 0..pages {
+      assert_eq!(
+        unsafe { page(addr, i).read_volatile() },
+        i
+      );
+      unsafe { page(addr, i).write_volatile(pages - i) };
+    }
+    exit(0);
+  }
+  let mut exit_code: i32 = 0;
+  assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+  assert_eq!(exit_code, 0);
+  for i in 0..pages {
+    assert_eq!(unsafe { page(addr, i).read_volatile() }, i);
+  }
+  assert_eq!(munmap(addr, SWAP_TEST_SIZE), 0);
+  println!("swap_test passed!");
+  0
+}
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  chdir, close, mkdir, open, read, readlink, rmdir,
+  symlink, unlink, write, OpenFlags,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut buf = [0u8; 64];
+  assert_eq!(mkdir("symlink_test\0"), 0);
+  assert_eq!(chdir("symlink_test\0"), 0);
+  assert_eq!(mkdir("dir\0"), 0);
+  let fd = open(
+    "dir/file\0",
+    OpenFlags::CREATE | OpenFlags::WRONLY,
+  );
+  assert!(fd > 0);
+  write(fd as usize, b"through a link");
+  close(fd as usize);
+
+  // relative targets are taken from the directory of the link,
+  // absolute ones from the root
+  assert_eq!(symlink("dir/file\0", "rel\0"), 0);
+  assert_eq!(symlink("/symlink_test/dir\0", "abs\0"), 0);
+  assert_eq!(symlink("rel\0", "chain\0"), 0);
+  assert_eq!(symlink("x\0", "rel\0"), -1);
+  for path in ["rel\0", "abs/file\0", "chain\0"] {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, &mut buf);
+    assert_eq!(&buf[..len as usize], b"through a link");
+    close(fd as usize);
+  }
+  assert_eq!(chdir("abs\0"), 0);
+  assert_eq!(chdir("..\0"), 0);
+
+  // readlink reads the link itself
+  let len = readlink("abs\0", &mut buf);
+  assert_eq!(&buf[..len as usize], b"/symlink_test/dir");
+  assert_eq!(readlink("abs\0", &mut buf[..4]), 4);
+  assert_eq!(readlink("dir\0", &mut buf), -1);
+
+  // dangling links and loops do not resolve
+  assert_eq!(symlink("nowhere\0", "dangling\0"), 0);
+  assert_eq!(open("dangling\0", OpenFlags::RDONLY), -1);
+  assert_eq!(symlink("loop_b\0", "loop_a\0"), 0);
+  assert_eq!(symlink("loop_a\0", "loop_b\0"), 0);
+  assert_eq!(open("loop_a\0", OpenFlags::RDONLY), -1);
+  assert_eq!(chdir("loop_b\0"), -1);
+
+  // unlink removes the link, not its target
+  for path in [
+    "rel\0",
+    "abs\0",
+    "chain\0",
+    "dangling\0",
+    "loop_a\0",
+    "loop_b\0",
+  ] {
+    assert_eq!(unlink(path), 0);
+  }
+  let fd = open("dir/file\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  close(fd as usize);
+  assert_eq!(unlink("dir/file\0"), 0);
+  assert_eq!(rmdir("dir\0"), 0);
+  assert_eq!(chdir("/\0"), 0);
+  assert_eq!(rmdir("symlink_test\0"), 0);
+  println!("symlink_test passed!");
+  0
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+  close, fstat, ftruncate, open, pread, pwrite, unlink,
+  write, OpenFlags, Stat,
+};
+
+const BIG: usize = 64 * 1024;
+
+#[no_mangle]
+pub fn main() -> i32 {
+  let mut buf = [0xffu8; 32];
+  let mut st = Stat::default();
+  let fd = open(
+    "truncate_file\0",
+    OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+  );
+  assert!(fd > 0);
+  let fd = fd as usize;
+  assert_eq!(write(fd, b"hello, world"), 12);
+
+  // shrinking drops the tail
+  assert_eq!(ftruncate(fd, 5), 0);
+  assert_eq!(fstat(fd, &mut st), 0);
+  assert_eq!(st.size, 5);
+  assert_eq!(pread(fd, &mut buf, 0), 5);
+  assert_eq!(&buf[..5], b"hello");
+
+  // growing leaves a hole that reads as zeros
+  assert_eq!(ftruncate(fd, BIG), 0);
+  assert_eq!(fstat(fd, &mut st), 0);
+  assert_eq!(st.size as usize, BIG);
+  assert_eq!(pread(fd, &mut buf, 0), 32);
+  assert_eq!(&buf[..5], b"hello");
+  assert!(buf[5..].iter().all(|&b| b == 0));
+  assert_eq!(pread(fd, &mut buf, BIG / 2), 32);
+  assert!(buf.iter().all(|&b| b == 0));
+  assert_eq!(pwrite(fd, b"end", BIG - 3), 3);
+  assert_eq!(pread(fd, &mut buf, BIG - 3), 3);
+  assert_eq!(&buf[..3], b"end");
+  assert_eq!(ftruncate(fd, 0), 0);
+  assert_eq!(pread(fd, &mut buf, 0), 0);
+  close(fd);
+
+  // only writable files can be truncated
+  let fd = open("truncate_file\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  assert_eq!(ftruncate(fd as usize, 0), -1);
+  close(fd as usize);
+  let fd = open(".\0", OpenFlags::RDONLY);
+  assert!(fd > 0);
+  assert_eq!(ftruncate(fd as usize, 0), -1);
+  close(fd as usize);
+
+  assert_eq!(unlink("truncate_file\0"), 0);
+  println!("truncate_test passed!");
+  0
+}
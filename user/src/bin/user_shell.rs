@@ -17,9 +17,277 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
 use user_lib::{
-  close, dup, exec, fork, open, pipe, waitpid, OpenFlags,
+  chdir, close, dup, exec, fork, getcwd, getpid, kill,
+  open, pipe, setpgid, sigaction, tcsetpgrp,
+  waitpid_options, OpenFlags, SignalAction, SignalFlags,
+  WaitFlags, WaitStatus, SIGINT, SIGTSTP, SIG_DFL, SIG_IGN,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobState {
+  Running,
+  Stopped,
+}
+
+/// A pipeline started by the shell.
+#[derive(Debug)]
+struct Job {
+  id: usize,
+  /// process group of the pipeline, led by its first process
+  pgid: usize,
+  /// processes of the pipeline which have not exited
+  pids: Vec<usize>,
+  command: String,
+  state: JobState,
+  /// signal which has terminated a process of the pipeline
+  term_signal: Option<i32>,
+}
+
+impl Job {
+  fn signal(&self, signal: SignalFlags) {
+    for pid in self.pids.iter() {
+      kill(*pid, signal.bits());
+    }
+  }
+
+  fn print(&self) {
+    let state = match self.state {
+      JobState::Running => "Running",
+      JobState::Stopped => "Stopped",
+    };
+    println!("[{}] {}  {}", self.id, state, self.command);
+  }
+
+  fn print_done(&self) {
+    match self.term_signal {
+      Some(signum) => println!(
+        "[{}] Killed by signal {}  {}",
+        self.id, signum, self.command
+      ),
+      None => {
+        println!("[{}] Done  {}", self.id, self.command)
+      }
+    }
+  }
+}
+
+/// Set the action of the signals typed on the console, the shell
+/// ignores them while the jobs it starts take the default action.
+fn set_console_signals(handler: usize) {
+  let action = SignalAction {
+    handler,
+    ..Default::default()
+  };
+  sigaction(SIGINT, Some(&action), None);
+  sigaction(SIGTSTP, Some(&action), None);
+}
+
+/// Wait for `job` in the foreground of the console, it is put into
+/// `jobs` if it stops.
+fn wait_foreground(mut job: Job, jobs: &mut Vec<Job>) {
+  tcsetpgrp(job.pgid);
+  while let Some(&pid) = job.pids.first() {
+    let mut status = WaitStatus::Exited(0);
+    let found_pid = waitpid_options(
+      pid as isize,
+      &mut status,
+      WaitFlags::UNTRACED,
+    );
+    assert_eq!(found_pid, pid as isize);
+    match status {
+      WaitStatus::Exited(_) => {
+        job.pids.remove(0);
+      }
+      WaitStatus::Signaled(signum) => {
+        job.pids.remove(0);
+        job.term_signal = Some(signum);
+      }
+      WaitStatus::Stopped(_) => {
+        // stop the rest of the pipeline as well
+        job.signal(SignalFlags::SIGSTOP);
+        job.state = JobState::Stopped;
+        tcsetpgrp(getpid() as usize);
+        println!("");
+        job.print();
+        jobs.push(job);
+        return;
+      }
+      WaitStatus::Continued => {}
+    }
+  }
+  tcsetpgrp(getpid() as usize);
+  match job.term_signal {
+    // the line typed with `^C` is left as it is
+    Some(SIGINT) => println!(""),
+    Some(_) => job.print_done(),
+    _ => {}
+  }
+}
+
+/// Reap the background processes which have exited and follow the
+/// stops and continues of the jobs.
+fn update_jobs(jobs: &mut Vec<Job>) {
+  for job in jobs.iter_mut() {
+    let mut i = 0;
+    while i < job.pids.len() {
+      let mut status = WaitStatus::Exited(0);
+      let found_pid = waitpid_options(
+        job.pids[i] as isize,
+        &mut status,
+        WaitFlags::NOHANG
+          | WaitFlags::UNTRACED
+          | WaitFlags::CONTINUED,
+      );
+      if found_pid == job.pids[i] as isize {
+        match status {
+          WaitStatus::Exited(_) => {
+            job.pids.remove(i);
+            continue;
+          }
+          WaitStatus::Signaled(signum) => {
+            job.pids.remove(i);
+            job.term_signal = Some(signum);
+            continue;
+          }
+          WaitStatus::Stopped(_) => {
+            job.state = JobState::Stopped
+          }
+          WaitStatus::Continued => {
+            job.state = JobState::Running
+          }
+        }
+      }
+      i += 1;
+    }
+  }
+  jobs.retain(|job| {
+    if job.pids.is_empty() {
+      job.print_done();
+    }
+    !job.pids.is_empty()
+  });
+}
+
+/// Index in `jobs` of the job given as `%n` or `n`, the latest one if
+/// it is not given.
+fn find_job(
+  jobs: &[Job],
+  spec: Option<&str>,
+) -> Option<usize> {
+  match spec {
+    None => jobs.len().checked_sub(1),
+    Some(spec) => {
+      let id = spec
+        .strip_prefix('%')
+        .unwrap_or(spec)
+        .parse::<usize>()
+        .ok()?;
+      jobs.iter().position(|job| job.id == id)
+    }
+  }
+}
+
+fn parse_signal(name: &str) -> Option<SignalFlags> {
+  let signal = match name {
+    "INT" | "2" => SignalFlags::SIGINT,
+    "KILL" | "9" => SignalFlags::SIGKILL,
+    "TERM" | "15" => SignalFlags::SIGTERM,
+    "CONT" | "18" => SignalFlags::SIGCONT,
+    "STOP" | "19" => SignalFlags::SIGSTOP,
+    "TSTP" | "20" => SignalFlags::SIGTSTP,
+    _ => return None,
+  };
+  Some(signal)
+}
+
+/// Run `command` if it is a builtin, return whether it is one.
+fn run_builtin(command: &str, jobs: &mut Vec<Job>) -> bool {
+  let args: Vec<_> = command
+    .split(' ')
+    .filter(|arg| !arg.is_empty())
+    .collect();
+  match args.first().copied() {
+    Some("cd") => {
+      let mut path =
+        String::from(args.get(1).copied().unwrap_or("/"));
+      path.push('\0');
+      if chdir(path.as_str()) == -1 {
+        println!("cd: no such directory {}", args[1]);
+      }
+    }
+    Some("pwd") => {
+      let mut buf = [0u8; 256];
+      let len = getcwd(&mut buf);
+      if len > 0 {
+        println!(
+          "{}",
+          core::str::from_utf8(&buf[..len as usize - 1])
+            .unwrap()
+        );
+      }
+    }
+    Some("jobs") => {
+      for job in jobs.iter() {
+        job.print();
+      }
+    }
+    Some(name @ ("fg" | "bg")) => {
+      let idx = match find_job(jobs, args.get(1).copied()) {
+        Some(idx) => idx,
+        None => {
+          println!("{}: no such job", name);
+          return true;
+        }
+      };
+      jobs[idx].signal(SignalFlags::SIGCONT);
+      jobs[idx].state = JobState::Running;
+      if name == "fg" {
+        let job = jobs.remove(idx);
+        println!("{}", job.command);
+        wait_foreground(job, jobs);
+      } else {
+        jobs[idx].print();
+      }
+    }
+    Some("kill") => {
+      let (signal, target) = match args.as_slice() {
+        [_, target] => {
+          (Some(SignalFlags::SIGTERM), *target)
+        }
+        [_, signal, target] => (
+          signal.strip_prefix('-').and_then(parse_signal),
+          *target,
+        ),
+        _ => {
+          println!("usage: kill [-SIGNAL] %job|pid");
+          return true;
+        }
+      };
+      let signal = match signal {
+        Some(signal) => signal,
+        None => {
+          println!("kill: invalid signal");
+          return true;
+        }
+      };
+      if target.starts_with('%') {
+        match find_job(jobs, Some(target)) {
+          Some(idx) => jobs[idx].signal(signal),
+          None => println!("kill: no such job"),
+        }
+      } else if let Ok(pid) = target.parse::<usize>() {
+        if kill(pid, signal.bits()) == -1 {
+          println!("kill: no such process");
+        }
+      } else {
+        println!("kill: invalid target {}", target);
+      }
+    }
+    _ => return false,
+  }
+  true
+}
+
 #[derive(Debug)]
 struct ProcessArguments {
   input: String,
@@ -80,16 +348,29 @@ impl ProcessArguments {
 #[no_mangle]
 pub fn main() -> i32 {
   println!("Rust user shell");
+  // the shell leads its own process group in the foreground
+  setpgid(0, 0);
+  tcsetpgrp(getpid() as usize);
+  set_console_signals(SIG_IGN);
   let mut line: String = String::new();
+  let mut jobs: Vec<Job> = Vec::new();
   print!("{}", LINE_START);
   loop {
     let c = getchar();
     match c {
       LF | CR => {
         println!("");
-        if !line.is_empty() {
+        // a trailing `&` runs the job in the background
+        let (command, background) =
+          match line.trim_end().strip_suffix('&') {
+            Some(command) => (command, true),
+            None => (line.as_str(), false),
+          };
+        if !command.trim().is_empty()
+          && !run_builtin(command, &mut jobs)
+        {
           let splited: Vec<_> =
-            line.as_str().split('|').collect();
+            command.split('|').collect();
           let process_arguments_list: Vec<_> = splited
             .iter()
             .map(|&cmd| ProcessArguments::new(cmd))
@@ -132,8 +413,15 @@ pub fn main() -> i32 {
             for (i, process_argument) in
               process_arguments_list.iter().enumerate()
             {
+              // the pipeline is a process group led by its first
+              // process, set by both sides as either may run first
+              let pgid =
+                children.first().copied().unwrap_or(0)
+                  as usize;
               let pid = fork();
               if pid == 0 {
+                setpgid(0, pgid);
+                set_console_signals(SIG_DFL);
                 let input = &process_argument.input;
                 let output = &process_argument.output;
                 let args_copy = &process_argument.args_copy;
@@ -158,7 +446,9 @@ pub fn main() -> i32 {
                 if !output.is_empty() {
                   let output_fd = open(
                     output.as_str(),
-                    OpenFlags::CREATE | OpenFlags::WRONLY,
+                    OpenFlags::CREATE
+                      | OpenFlags::TRUNC
+                      | OpenFlags::WRONLY,
                   );
                   if output_fd == -1 {
                     println!(
@@ -202,6 +492,7 @@ pub fn main() -> i32 {
                 }
                 unreachable!();
               } else {
+                setpgid(pid as usize, pgid);
                 children.push(pid);
               }
             }
@@ -209,16 +500,32 @@ pub fn main() -> i32 {
               close(pipe_fd[0]);
               close(pipe_fd[1]);
             }
-            let mut exit_code: i32 = 0;
-            for pid in children.into_iter() {
-              let exit_pid =
-                waitpid(pid as usize, &mut exit_code);
-              assert_eq!(pid, exit_pid);
-              //println!("Shell: Process {} exited with code {}", pid, exit_code);
+            let job = Job {
+              id: jobs
+                .iter()
+                .map(|job| job.id)
+                .max()
+                .unwrap_or(0)
+                + 1,
+              pgid: children[0] as usize,
+              pids: children
+                .into_iter()
+                .map(|pid| pid as usize)
+                .collect(),
+              command: String::from(command.trim()),
+              state: JobState::Running,
+              term_signal: None,
+            };
+            if background {
+              println!("[{}] {:?}", job.id, job.pids);
+              jobs.push(job);
+            } else {
+              wait_foreground(job, &mut jobs);
             }
           }
-          line.clear();
         }
+        line.clear();
+        update_jobs(&mut jobs);
         print!("{}", LINE_START);
       }
       BS | DL => {
@@ -41,6 +41,18 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
   ("yield\0", "\0", "\0", "\0", 0),
   ("barrier_fail\0", "\0", "\0", "\0", 0),
   ("barrier_condvar\0", "\0", "\0", "\0", 0),
+  ("sbrk_test\0", "\0", "\0", "\0", 0),
+  ("mmap_test\0", "\0", "\0", "\0", 0),
+  ("swap_test\0", "\0", "\0", "\0", 0),
+  ("stride_test\0", "\0", "\0", "\0", 0),
+  ("mlfq_test\0", "\0", "\0", "\0", 0),
+  ("sig_tests\0", "\0", "\0", "\0", 0),
+  ("stop_test\0", "\0", "\0", "\0", 0),
+  ("dir_test\0", "\0", "\0", "\0", 0),
+  ("symlink_test\0", "\0", "\0", "\0", 0),
+  ("stat_test\0", "\0", "\0", "\0", 0),
+  ("seek_test\0", "\0", "\0", "\0", 0),
+  ("truncate_test\0", "\0", "\0", "\0", 0),
 ];
 
 static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[
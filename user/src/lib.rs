@@ -13,16 +13,34 @@ extern crate alloc;
 extern crate bitflags;
 
 use alloc::vec::Vec;
-use buddy_system_allocator::LockedHeap;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::Layout;
 use syscall::*;
 
-const USER_HEAP_SIZE: usize = 32768;
-
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] =
-  [0; USER_HEAP_SIZE];
+/// The heap grows by at least this many bytes at a time.
+const USER_HEAP_GROW_SIZE: usize = 32768;
 
+/// The heap starts empty and is grown with `sbrk` on demand.
 #[global_allocator]
-static HEAP: LockedHeap<32> = LockedHeap::empty();
+static HEAP: LockedHeapWithRescue<32> =
+  LockedHeapWithRescue::new(heap_rescue);
+
+/// Called when the heap cannot satisfy `layout`. The buddy allocator
+/// needs a block aligned to its own size, twice the size makes sure
+/// the new region contains one.
+fn heap_rescue(heap: &mut Heap<32>, layout: &Layout) {
+  let size =
+    (layout.size().max(layout.align()).next_power_of_two()
+      * 2)
+      .max(USER_HEAP_GROW_SIZE);
+  let start = sys_sbrk(size as isize);
+  if start != -1 {
+    unsafe {
+      heap
+        .add_to_heap(start as usize, start as usize + size);
+    }
+  }
+}
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(
@@ -34,11 +52,6 @@ pub fn handle_alloc_error(
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-  unsafe {
-    HEAP
-      .lock()
-      .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-  }
   //take out the starting addresses of argc strings respectively.
   let mut v: Vec<&'static str> = Vec::new();
   for i in 0..argc {
@@ -78,19 +91,490 @@ bitflags! {
     const RDWR = 1 << 1;
     const CREATE = 1 << 9;
     const TRUNC = 1 << 10;
+    const APPEND = 1 << 11;
+  }
+}
+
+bitflags! {
+  /// File type bits of `Stat::mode`, the permission bits are below them
+  pub struct StatMode: u32 {
+    const DIR = 0o040000;
+    const FILE = 0o100000;
+    const LINK = 0o120000;
+  }
+}
+
+/// File status filled by `fstat`, times are in milliseconds since the
+/// Unix epoch
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Stat {
+  pub dev: u64,
+  pub ino: u64,
+  /// File type and permission bits
+  pub mode: u32,
+  pub nlink: u32,
+  pub uid: u32,
+  pub gid: u32,
+  pub size: u64,
+  pub atime_ms: u64,
+  pub mtime_ms: u64,
+  pub ctime_ms: u64,
+}
+
+impl Stat {
+  /// The file type bits of `mode`
+  pub fn file_type(&self) -> StatMode {
+    StatMode::from_bits_truncate(self.mode & 0o170000)
+  }
+
+  /// The permission bits of `mode`
+  pub fn permissions(&self) -> u32 {
+    self.mode & 0o7777
+  }
+
+  pub fn is_dir(&self) -> bool {
+    self.file_type() == StatMode::DIR
+  }
+}
+
+/// Scheduling statistics of a thread filled by `sched_stat`
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SchedStat {
+  /// Time in milliseconds the thread has run before it was last
+  /// switched out
+  pub run_time: usize,
+  /// Number of times the thread has been scheduled
+  pub dispatch_count: usize,
+  /// Queue level of MLFQ scheduling, 0 is the highest and the only
+  /// one under stride scheduling
+  pub level: usize,
+}
+
+/// `Dirent::type_` of a directory
+pub const DT_DIR: u8 = 4;
+/// `Dirent::type_` of a regular file
+pub const DT_REG: u8 = 8;
+/// `Dirent::type_` of a symbolic link
+pub const DT_LNK: u8 = 10;
+
+/// A directory entry parsed from the `linux_dirent64` records filled by
+/// `getdents`
+#[derive(Debug)]
+pub struct Dirent<'a> {
+  pub ino: u64,
+  /// One of `DT_DIR`, `DT_REG` and `DT_LNK`
+  pub type_: u8,
+  pub name: &'a str,
+}
+
+/// Iterator over the records in a buffer filled by `getdents`
+pub struct Dirents<'a> {
+  buf: &'a [u8],
+}
+
+impl<'a> Dirents<'a> {
+  /// `buf` holds exactly the bytes `getdents` returned
+  pub fn new(buf: &'a [u8]) -> Self {
+    Self { buf }
+  }
+}
+
+impl<'a> Iterator for Dirents<'a> {
+  type Item = Dirent<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.buf.len() < 19 {
+      return None;
+    }
+    let ino =
+      u64::from_le_bytes(self.buf[..8].try_into().unwrap());
+    let reclen = u16::from_le_bytes(
+      self.buf[16..18].try_into().unwrap(),
+    ) as usize;
+    let type_ = self.buf[18];
+    let name = &self.buf[19..reclen];
+    let len = name
+      .iter()
+      .position(|b| *b == 0)
+      .unwrap_or(name.len());
+    let name = core::str::from_utf8(&name[..len]).unwrap();
+    self.buf = &self.buf[reclen..];
+    Some(Dirent { ino, type_, name })
+  }
+}
+
+bitflags! {
+  pub struct MmapProt: u32 {
+    const READ = 1 << 0;
+    const WRITE = 1 << 1;
+    const EXEC = 1 << 2;
+  }
+}
+
+bitflags! {
+  pub struct MmapFlags: u32 {
+    const SHARED = 1 << 0;
+    const PRIVATE = 1 << 1;
+    const FIXED = 1 << 4;
+    const ANONYMOUS = 1 << 5;
   }
 }
 
 bitflags! {
   pub struct SignalFlags: i32 {
+    const SIGDEF    = 1;
+    const SIGHUP    = 1 << 1;
     const SIGINT    = 1 << 2;
+    const SIGQUIT   = 1 << 3;
     const SIGILL    = 1 << 4;
+    const SIGTRAP   = 1 << 5;
     const SIGABRT   = 1 << 6;
+    const SIGBUS    = 1 << 7;
     const SIGFPE    = 1 << 8;
+    const SIGKILL   = 1 << 9;
+    const SIGUSR1   = 1 << 10;
     const SIGSEGV   = 1 << 11;
+    const SIGUSR2   = 1 << 12;
+    const SIGPIPE   = 1 << 13;
+    const SIGALRM   = 1 << 14;
+    const SIGTERM   = 1 << 15;
+    const SIGSTKFLT = 1 << 16;
+    const SIGCHLD   = 1 << 17;
+    const SIGCONT   = 1 << 18;
+    const SIGSTOP   = 1 << 19;
+    const SIGTSTP   = 1 << 20;
+    const SIGTTIN   = 1 << 21;
+    const SIGTTOU   = 1 << 22;
+    const SIGURG    = 1 << 23;
+    const SIGXCPU   = 1 << 24;
+    const SIGXFSZ   = 1 << 25;
+    const SIGVTALRM = 1 << 26;
+    const SIGPROF   = 1 << 27;
+    const SIGWINCH  = 1 << 28;
+    const SIGIO     = 1 << 29;
+    const SIGPWR    = 1 << 30;
+    const SIGSYS    = 1 << 31;
+  }
+}
+
+bitflags! {
+  pub struct WaitFlags: u32 {
+    const NOHANG    = 1 << 0;
+    const UNTRACED  = 1 << 1;
+    const CONTINUED = 1 << 3;
+    const STATUS    = 1 << 16;
+  }
+}
+
+/// What happened to a child process, as reported by `waitpid_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+  /// Exited with the exit code.
+  Exited(i32),
+  /// Terminated by the signal number.
+  Signaled(i32),
+  /// Stopped by the signal number.
+  Stopped(i32),
+  /// Continued by `SIGCONT`.
+  Continued,
+}
+
+impl WaitStatus {
+  fn decode(status: i32) -> Self {
+    if status == 0xffff {
+      Self::Continued
+    } else if status & 0xff == 0x7f {
+      Self::Stopped((status >> 8) & 0xff)
+    } else if status & 0x7f != 0 {
+      Self::Signaled(status & 0x7f)
+    } else {
+      Self::Exited((status >> 8) as u8 as i8 as i32)
+    }
   }
 }
 
+/// Signal numbers, as taken by `sigaction`.
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGABRT: i32 = 6;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+
+/// Handler taking the default action of the signal.
+pub const SIG_DFL: usize = 0;
+/// Handler ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
+/// Action taken when a signal is delivered (same as the kernel's
+/// `SignalAction`).
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+  /// Entry of the handler, which ends by calling `sigreturn`, or
+  /// [`SIG_DFL`] / [`SIG_IGN`].
+  pub handler: usize,
+  /// Signals blocked while the handler runs.
+  pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+  fn default() -> Self {
+    Self {
+      handler: SIG_DFL,
+      mask: SignalFlags::empty(),
+    }
+  }
+}
+
+/// ### Function:
+///   Copy the absolute path of the current working directory into `buf`,
+///   followed by a `\0`.
+///
+/// ### Return value:
+///   Returns the length of the path including the `\0`, or -1 if `buf`
+///   is too small.
+///
+/// syscall ID: 17
+pub fn getcwd(buf: &mut [u8]) -> isize {
+  sys_getcwd(buf)
+}
+
+/// ### Function:
+///   Create a directory, which comes with the `.` and `..` entries.
+///
+/// ### Parameter:
+///   - `path`: path of the new directory ending with `\0`, relative to the
+///           current working directory unless it starts with `/`.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if the parent directory does not
+///   exist or the path exists already.
+///
+/// syscall ID: 34
+pub fn mkdir(path: &str) -> isize {
+  sys_mkdir(path)
+}
+
+/// `dirfd` of the `*at` functions referring to the current working
+/// directory.
+pub const AT_FDCWD: isize = -100;
+/// `unlinkat` flag to remove a directory instead of a file.
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// ### Function:
+///   Remove a file, or an empty directory with `AT_REMOVEDIR` in `flags`.
+///   Its data blocks and inode are given back to the file system once
+///   its last link is removed and it is no longer open.
+///
+/// ### Parameter:
+///   - `dirfd`: an open directory a relative `path` is taken from, or
+///           `AT_FDCWD` for the current working directory.
+///   - `path`: path of the entry to remove ending with `\0`.
+///   - `flags`: 0 or `AT_REMOVEDIR`.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if the entry does not exist, is
+///   not of the type given by `flags`, or is a non-empty directory.
+///
+/// syscall ID: 35
+pub fn unlinkat(
+  dirfd: isize,
+  path: &str,
+  flags: u32,
+) -> isize {
+  sys_unlinkat(dirfd, path, flags)
+}
+
+/// `unlinkat` a file relative to the current working directory.
+pub fn unlink(path: &str) -> isize {
+  sys_unlinkat(AT_FDCWD, path, 0)
+}
+
+/// `unlinkat` an empty directory relative to the current working
+/// directory.
+pub fn rmdir(path: &str) -> isize {
+  sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR)
+}
+
+/// ### Function:
+///   Add a hard link `new_path` to the file at `old_path`, the file lives
+///   until its last link is removed and it is no longer open.
+///
+/// ### Parameter:
+///   - `old_dirfd`, `new_dirfd`: open directories the relative paths are
+///           taken from, or `AT_FDCWD`.
+///   - `old_path`, `new_path`: paths ending with `\0`.
+///   - `flags`: unused, should be 0.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if `old_path` does not exist or is
+///   a directory, or `new_path` exists.
+///
+/// syscall ID: 37
+pub fn linkat(
+  old_dirfd: isize,
+  old_path: &str,
+  new_dirfd: isize,
+  new_path: &str,
+  flags: u32,
+) -> isize {
+  sys_linkat(
+    old_dirfd, old_path, new_dirfd, new_path, flags,
+  )
+}
+
+/// `linkat` relative to the current working directory.
+pub fn link(old_path: &str, new_path: &str) -> isize {
+  sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
+}
+
+/// ### Function:
+///   Create a symbolic link at `path` to `target`. Opening a path through
+///   it continues at `target`, which need not exist.
+///
+/// ### Parameter:
+///   - `target`: the target path ending with `\0`, relative to the
+///           directory holding the link unless it starts with `/`.
+///   - `dirfd`: an open directory a relative `path` is taken from, or
+///           `AT_FDCWD`.
+///   - `path`: path of the new link ending with `\0`.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if `path` exists.
+///
+/// syscall ID: 36
+pub fn symlinkat(
+  target: &str,
+  dirfd: isize,
+  path: &str,
+) -> isize {
+  sys_symlinkat(target, dirfd, path)
+}
+
+/// `symlinkat` relative to the current working directory.
+pub fn symlink(target: &str, path: &str) -> isize {
+  sys_symlinkat(target, AT_FDCWD, path)
+}
+
+/// ### Function:
+///   Copy the target of the symbolic link at `path` into `buf`, without a
+///   trailing `\0`.
+///
+/// ### Parameter:
+///   - `dirfd`: an open directory a relative `path` is taken from, or
+///           `AT_FDCWD`.
+///   - `path`: path of the link ending with `\0`.
+///   - `buf`: the target is truncated to its length.
+///
+/// ### Return value:
+///   Returns the number of bytes copied, otherwise -1 if `path` is not a
+///   symbolic link.
+///
+/// syscall ID: 78
+pub fn readlinkat(
+  dirfd: isize,
+  path: &str,
+  buf: &mut [u8],
+) -> isize {
+  sys_readlinkat(dirfd, path, buf)
+}
+
+/// `readlinkat` relative to the current working directory.
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+  sys_readlinkat(AT_FDCWD, path, buf)
+}
+
+/// ### Function:
+///   Move the entry at `old_path` to `new_path`, which may be in another
+///   directory. An existing `new_path` of the same type is replaced, a
+///   directory it replaces must be empty.
+///
+/// ### Parameter:
+///   - `old_dirfd`, `new_dirfd`: open directories the relative paths are
+///           taken from, or `AT_FDCWD`.
+///   - `old_path`, `new_path`: paths ending with `\0`.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1, e.g. when moving a directory
+///   into itself.
+///
+/// syscall ID: 38
+pub fn renameat(
+  old_dirfd: isize,
+  old_path: &str,
+  new_dirfd: isize,
+  new_path: &str,
+) -> isize {
+  sys_renameat(old_dirfd, old_path, new_dirfd, new_path)
+}
+
+/// `renameat` relative to the current working directory.
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+  sys_renameat(AT_FDCWD, old_path, AT_FDCWD, new_path)
+}
+
+/// ### Function:
+///   Change the current working directory.
+///
+/// ### Parameter:
+///   - `path`: path of a directory ending with `\0`.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if it is not a directory.
+///
+/// syscall ID: 49
+pub fn chdir(path: &str) -> isize {
+  sys_chdir(path)
+}
+
+/// ### Function:
+///   Read the entries of a directory opened by `open`, continuing where
+///   the last call stopped. Parse them with `Dirents`.
+///
+/// ### Parameter:
+///   - `fd`: a file descriptor of an open directory.
+///   - `buf`: filled with `linux_dirent64` records.
+///
+/// ### Return value:
+///   Returns the number of bytes filled, 0 at the end of the directory,
+///   or -1 if `fd` is not a directory or `buf` cannot hold an entry.
+///
+/// syscall ID: 61
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+  sys_getdents64(fd, buf)
+}
+
+/// ### Function:
+///   Get the status of an open file: its type, permissions, owner, size,
+///   link count and timestamps.
+///
+/// ### Parameter:
+///   - `fd`: a file descriptor of a file on the file system.
+///   - `st`: filled with the status.
+///
+/// ### Return value:
+///   Returns 0 if success, otherwise -1 if `fd` is not open or is a pipe
+///   or the console.
+///
+/// syscall ID: 80
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+  sys_fstat(fd, st)
+}
+
 /// ### Function:
 ///     Copy an already open file in the process and assign it to a new file descriptor.
 ///
@@ -117,7 +601,7 @@ pub fn dup(fd: usize) -> isize {
 /// | 0     | RDONLY | File is opened in read-only mode                                                    |
 /// | 0x001 | WRONLY | File is opened in write-only mode                                                   |
 /// | 0x002 | RDWR   | File is opened in read-write mode                                                   |
-/// | 0x200 | CREATE | File is created if it does not exist, and its size is set to 0 if it already exists |
+/// | 0x200 | CREATE | File is created if it does not exist                                                |
 /// | 0x400 | TRUNC  | File is opened with its contents cleared and its size set to 0                      |
 ///
 /// ### Return value:
@@ -157,6 +641,84 @@ pub fn pipe(pipe_fd: &mut [usize]) -> isize {
   sys_pipe(pipe_fd)
 }
 
+/// `whence` of `lseek` taking the offset from the start
+pub const SEEK_SET: usize = 0;
+/// `whence` of `lseek` taking the offset from the current offset
+pub const SEEK_CUR: usize = 1;
+/// `whence` of `lseek` taking the offset from the end
+pub const SEEK_END: usize = 2;
+
+/// ### Function:
+///   Set the size of an open file, freeing the blocks past a smaller
+///   size or leaving a hole that reads as zeros up to a larger one.
+///
+/// ### Parameters:
+///   - `fd`: a file descriptor of a regular file opened for writing.
+///   - `len`: the new size in bytes.
+///
+/// ### Return value:
+///   Returns 0 on success, or -1 if the file is not a writable regular
+///   file or `len` is too large.
+///
+/// syscall ID: 46
+pub fn ftruncate(fd: usize, len: usize) -> isize {
+  sys_ftruncate(fd, len)
+}
+
+/// ### Function:
+///   Move the offset of an open file, which may go past the end.
+///
+/// ### Parameters:
+///   - `fd`: a file descriptor of a file on the file system.
+///   - `offset`: bytes to move, may be negative.
+///   - `whence`: `SEEK_SET`, `SEEK_CUR` or `SEEK_END` to move from the
+///           start, the current offset or the end.
+///
+/// ### Return value:
+///   Returns the new offset, or -1 if it would be negative or the file
+///   cannot seek like a pipe.
+///
+/// syscall ID: 62
+pub fn lseek(
+  fd: usize,
+  offset: isize,
+  whence: usize,
+) -> isize {
+  sys_lseek(fd, offset, whence)
+}
+
+/// ### Function:
+///   Read from a file at `offset` without moving its offset.
+///
+/// ### Return value:
+///   Returns the length read, or -1 if the file cannot seek.
+///
+/// syscall ID: 67
+pub fn pread(
+  fd: usize,
+  buf: &mut [u8],
+  offset: usize,
+) -> isize {
+  sys_pread64(fd, buf, offset)
+}
+
+/// ### Function:
+///   Write to a file at `offset` without moving its offset, even if it
+///   was opened with `APPEND`.
+///
+/// ### Return value:
+///   Returns the length written, or -1 if the file cannot seek or cannot
+///   grow past `offset`.
+///
+/// syscall ID: 68
+pub fn pwrite(
+  fd: usize,
+  buf: &[u8],
+  offset: usize,
+) -> isize {
+  sys_pwrite64(fd, buf, offset)
+}
+
 /// ### Function:
 ///   Read the data to the buffer in memory from a file.
 ///
@@ -182,7 +744,8 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 ///   - `len`: indicates the length of the buffer in memory.
 ///
 /// ### Return:
-///   Returns the length of a successful write.
+///   Returns the length of a successful write, which is short if the
+///   file reaches its maximum size, or -1 if it cannot grow at all.
 ///
 /// syscall ID: 64
 ///
@@ -243,6 +806,145 @@ pub fn kill(pid: usize, signum: i32) -> isize {
   sys_kill(pid, signum)
 }
 
+/// ### Function:
+///   Install a new action for a signal and/or get the current one.
+///
+/// ### Parameters:
+///   - `signum`: the number of signal, `SIGKILL` and `SIGSTOP` cannot be caught.
+///   - `action`: the new action, or `None` to keep the current one.
+///   - `old_action`: filled with the previous action if not `None`.
+///
+/// ### Return:
+///   Return -1 if the signal number is invalid, otherwise return 0.
+///
+/// syscall ID: 134
+pub fn sigaction(
+  signum: i32,
+  action: Option<&SignalAction>,
+  old_action: Option<&mut SignalAction>,
+) -> isize {
+  sys_sigaction(
+    signum,
+    action.map_or(core::ptr::null(), |a| a),
+    old_action.map_or(core::ptr::null_mut(), |a| a),
+  )
+}
+
+/// ### Function:
+///   Set the signals blocked from being delivered to the current process,
+///   blocked signals stay pending until they are unblocked.
+///
+/// ### Parameters:
+///   - `mask`: the new set of blocked signals.
+///
+/// ### Return:
+///   Return the previous mask.
+///
+/// syscall ID: 135
+pub fn sigprocmask(mask: SignalFlags) -> SignalFlags {
+  SignalFlags::from_bits_truncate(sys_sigprocmask(
+    mask.bits() as u32,
+  ) as i32)
+}
+
+/// ### Function:
+///   Return from a signal handler to where the current thread was interrupted,
+///   it must be the last thing a handler does.
+///
+/// ### Return:
+///   Return -1 if the thread is not running a signal handler, otherwise
+///   it does not return to the caller.
+///
+/// syscall ID: 139
+pub fn sigreturn() -> isize {
+  sys_sigreturn()
+}
+
+/// ### Function:
+///   Set the scheduling priority of the current thread, a thread with
+///   a larger priority is scheduled more often.
+///
+/// ### Parameters:
+///   - `priority`: the new priority, at least 2.
+///
+/// ### Return:
+///   Return the new priority, or -1 if it is invalid.
+///
+/// syscall ID: 140
+pub fn set_priority(priority: isize) -> isize {
+  sys_set_priority(priority)
+}
+
+/// ### Function:
+///   Get the scheduling priority of the current thread.
+///
+/// ### Return:
+///   Return the priority, 16 unless it has been set.
+///
+/// syscall ID: 141
+pub fn get_priority() -> isize {
+  sys_get_priority()
+}
+
+/// ### Function:
+///   Move a process into a process group.
+///
+/// ### Parameters:
+///   - `pid`: the process, 0 for the current one.
+///   - `pgid`: the process group, 0 for the one led by `pid`.
+///
+/// ### Return:
+///   Return -1 if the process is neither the current one nor one of its
+///   children, otherwise return 0.
+///
+/// syscall ID: 154
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+  sys_setpgid(pid, pgid)
+}
+
+/// ### Function:
+///   Get the process group of a process.
+///
+/// ### Parameters:
+///   - `pid`: the process, 0 for the current one.
+///
+/// ### Return:
+///   Return -1 if the process does not exist, otherwise its process group.
+///
+/// syscall ID: 155
+pub fn getpgid(pid: usize) -> isize {
+  sys_getpgid(pid)
+}
+
+/// ### Function:
+///   Put a process group in the foreground of the console, it receives
+///   `SIGINT` on `^C` and `SIGTSTP` on `^Z`.
+///
+/// ### Parameters:
+///   - `pgid`: the process group.
+///
+/// ### Return:
+///   Return 0.
+///
+/// syscall ID: 1060
+pub fn tcsetpgrp(pgid: usize) -> isize {
+  sys_tcsetpgrp(pgid)
+}
+
+/// ### Function:
+///   Get the scheduling statistics of the current thread.
+///
+/// ### Parameters:
+///   - `stat`: filled with the statistics.
+///
+/// ### Return:
+///   Return 0.
+///
+/// syscall ID: 1050
+pub fn sched_stat(stat: &mut SchedStat) -> isize {
+  sys_sched_stat(stat)
+}
+
 /// ### Function:
 ///   Get the current time, saved in the TimeVal struct ts, _tz ignored in our implementation.
 ///
@@ -269,13 +971,103 @@ pub fn getpid() -> isize {
 ///   The current process forks out a child process.
 ///
 /// ### Return:
-///   Returns 0 for the child process, and returns the PID of the child process for the current process.
+///   Returns 0 for the child process, and returns the PID of the child process for the current process,
+///   or -1 if there is no memory for the child process.
 ///
 /// syscall ID: 220
 pub fn fork() -> isize {
   sys_fork()
 }
 
+/// ### Function:
+///   Set the end of the heap(program break) of current process.
+///
+/// ### Parameter:
+///   - `addr`: the new end of the heap, 0 means only querying the current one.
+///
+/// ### Return:
+///   Returns the new end of the heap, or -1 if `addr` is out of the heap range.
+///
+/// syscall ID: 214
+pub fn brk(addr: usize) -> isize {
+  sys_brk(addr)
+}
+
+/// ### Function:
+///   Grow the heap of current process by `increment` bytes, shrink it if negative.
+///
+/// ### Parameter:
+///   - `increment`: the bytes to add to the end of the heap.
+///
+/// ### Return:
+///   Returns the old end of the heap, or -1 if the new end is out of the heap range.
+///
+/// syscall ID: 1040
+pub fn sbrk(increment: isize) -> isize {
+  sys_sbrk(increment)
+}
+
+/// ### Function:
+///   Map a file or anonymous memory into the address space of current process.
+///
+/// ### Parameters:
+///   - `addr`: the hint of start address, which must be page aligned and not mapped yet with `MmapFlags::FIXED`.
+///   - `len`: the length of the mapping in bytes.
+///   - `prot`: the protection of the mapping, must not be empty.
+///   - `flags`: exactly one of `SHARED` and `PRIVATE`, with optional `FIXED` and `ANONYMOUS`.
+///   - `fd`: the file to map, ignored with `MmapFlags::ANONYMOUS`.
+///   - `offset`: the page aligned offset in the file to start mapping from.
+///
+/// ### Return:
+///   Returns the start address of the mapping, or -1 on failure.
+///
+/// syscall ID: 222
+pub fn mmap(
+  addr: usize,
+  len: usize,
+  prot: MmapProt,
+  flags: MmapFlags,
+  fd: usize,
+  offset: usize,
+) -> isize {
+  sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+
+/// ### Function:
+///   Unmap the pages of `[addr, addr + len)`, dirty pages of shared file mappings are written back.
+///
+/// ### Parameters:
+///   - `addr`: the page aligned start address.
+///   - `len`: the length in bytes.
+///
+/// ### Return:
+///   Returns 0 on success, or -1 if the parameters are invalid.
+///
+/// syscall ID: 215
+pub fn munmap(addr: usize, len: usize) -> isize {
+  sys_munmap(addr, len)
+}
+
+/// ### Function:
+///   Change the protection of the pages of `[addr, addr + len)`.
+///
+/// ### Parameters:
+///   - `addr`: the page aligned start address.
+///   - `len`: the length in bytes.
+///   - `prot`: the new protection, must not be empty.
+///
+/// ### Return:
+///   Returns 0 on success, or -1 if some page is not mapped or the protection is not allowed.
+///
+/// syscall ID: 226
+pub fn mprotect(
+  addr: usize,
+  len: usize,
+  prot: MmapProt,
+) -> isize {
+  sys_mprotect(addr, len, prot.bits)
+}
+
 /// ### Function:
 ///     Empty the address space of the current process and load a specific executable file,
 ///     return to user mode and start its execution.
@@ -285,8 +1077,8 @@ pub fn fork() -> isize {
 ///   - `args`: the elements of this array are the start address of each parameter strings
 ///
 /// ### Return:
-///   Returns -1 if there is an error (if no executable matching the name is found),
-///     otherwise it should not be returned
+///   Returns -1 if there is an error (if no executable matching the name is found,
+///     or there is no memory to load it), otherwise it should not be returned
 ///
 /// syscall ID: 221
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
@@ -310,7 +1102,8 @@ pub fn exec(path: &str, args: &[*const u8]) -> isize {
 /// syscall ID: 260
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
   loop {
-    match sys_waitpid(pid as isize, exit_code as *mut _) {
+    match sys_waitpid(pid as isize, exit_code as *mut _, 0)
+    {
       -2 => {
         yield_();
       }
@@ -322,7 +1115,7 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
 
 pub fn wait(exit_code: &mut i32) -> isize {
   loop {
-    match sys_waitpid(-1, exit_code as *mut _) {
+    match sys_waitpid(-1, exit_code as *mut _, 0) {
       -2 => {
         yield_();
       }
@@ -336,7 +1129,47 @@ pub fn waitpid_nb(
   pid: usize,
   exit_code: &mut i32,
 ) -> isize {
-  sys_waitpid(pid as isize, exit_code as *mut _)
+  sys_waitpid(pid as isize, exit_code as *mut _, 0)
+}
+
+/// ### Function:
+///   Like `waitpid`, but also reports stopped and continued children as
+///   asked by `flags`.
+///
+/// ### Parameters:
+///   - `pid`: the child process to wait, or -1 for any child process.
+///   - `status`: what happened to the child process.
+///   - `flags`: `NOHANG` returns -2 at once instead of waiting, `UNTRACED`
+///           reports stops and `CONTINUED` reports continues.
+///
+/// ### Return:
+///   Returns -1 if the child process to wait for does not exist, -2 with
+///     `NOHANG` if nothing has happened, otherwise the process ID.
+///
+/// syscall ID: 260
+pub fn waitpid_options(
+  pid: isize,
+  status: &mut WaitStatus,
+  flags: WaitFlags,
+) -> isize {
+  let mut raw = 0;
+  loop {
+    match sys_waitpid(
+      pid,
+      &mut raw,
+      (flags | WaitFlags::STATUS).bits(),
+    ) {
+      -2 if !flags.contains(WaitFlags::NOHANG) => {
+        yield_();
+      }
+      found_pid => {
+        if found_pid >= 0 {
+          *status = WaitStatus::decode(raw);
+        }
+        return found_pid;
+      }
+    }
+  }
 }
 
 /// ### Function:
@@ -347,7 +1180,7 @@ pub fn waitpid_nb(
 ///   - `arg`: the params provided to thread
 ///
 /// ### Return:
-///   the tid of created thread.
+///   the tid of created thread, or -1 if there is no memory for it.
 ///
 /// syscall ID: 1000
 pub fn thread_create(entry: usize, arg: usize) -> isize {
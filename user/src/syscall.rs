@@ -1,19 +1,46 @@
 use core::arch::asm;
 
+use crate::{SchedStat, SignalAction, Stat};
+
+const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_MKDIR: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_FTRUNCATE: usize = 46;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_GET_PRIORITY: usize = 141;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -27,6 +54,9 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
+const SYSCALL_SBRK: usize = 1040;
+const SYSCALL_SCHED_STAT: usize = 1050;
+const SYSCALL_TCSETPGRP: usize = 1060;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
   let mut ret: isize;
@@ -46,10 +76,126 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
   ret
 }
 
+/// `syscall` with all six argument registers, a0 to a5.
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+  let mut ret: isize;
+  unsafe {
+    asm!(
+        "ecall",
+        inlateout("x10") args[0] => ret,
+        in("x11") args[1],
+        in("x12") args[2],
+        in("x13") args[3],
+        in("x14") args[4],
+        in("x15") args[5],
+        in("x17") id
+    );
+  }
+  ret
+}
+
 pub fn sys_kill(pid: usize, signal: i32) -> isize {
   syscall(SYSCALL_KILL, [pid, signal as usize, 0])
 }
 
+pub fn sys_sigaction(
+  signum: i32,
+  action: *const SignalAction,
+  old_action: *mut SignalAction,
+) -> isize {
+  syscall(
+    SYSCALL_SIGACTION,
+    [signum as usize, action as usize, old_action as usize],
+  )
+}
+
+pub fn sys_sigprocmask(mask: u32) -> isize {
+  syscall(SYSCALL_SIGPROCMASK, [mask as usize, 0, 0])
+}
+
+pub fn sys_sigreturn() -> isize {
+  syscall(SYSCALL_SIGRETURN, [0, 0, 0])
+}
+
+pub fn sys_set_priority(priority: isize) -> isize {
+  syscall(SYSCALL_SET_PRIORITY, [priority as usize, 0, 0])
+}
+
+pub fn sys_get_priority() -> isize {
+  syscall(SYSCALL_GET_PRIORITY, [0, 0, 0])
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+  syscall(SYSCALL_SETPGID, [pid, pgid, 0])
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+  syscall(SYSCALL_GETPGID, [pid, 0, 0])
+}
+
+pub fn sys_tcsetpgrp(pgid: usize) -> isize {
+  syscall(SYSCALL_TCSETPGRP, [pgid, 0, 0])
+}
+
+pub fn sys_sched_stat(stat: &mut SchedStat) -> isize {
+  syscall(
+    SYSCALL_SCHED_STAT,
+    [stat as *mut SchedStat as usize, 0, 0],
+  )
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
+  syscall(SYSCALL_FTRUNCATE, [fd, len, 0])
+}
+
+pub fn sys_lseek(
+  fd: usize,
+  offset: isize,
+  whence: usize,
+) -> isize {
+  syscall(SYSCALL_LSEEK, [fd, offset as usize, whence])
+}
+
+pub fn sys_pread64(
+  fd: usize,
+  buf: &mut [u8],
+  offset: usize,
+) -> isize {
+  syscall6(
+    SYSCALL_PREAD64,
+    [
+      fd,
+      buf.as_mut_ptr() as usize,
+      buf.len(),
+      offset,
+      0,
+      0,
+    ],
+  )
+}
+
+pub fn sys_pwrite64(
+  fd: usize,
+  buf: &[u8],
+  offset: usize,
+) -> isize {
+  syscall6(
+    SYSCALL_PWRITE64,
+    [fd, buf.as_ptr() as usize, buf.len(), offset, 0, 0],
+  )
+}
+
+pub fn sys_getdents64(fd: usize, buf: &mut [u8]) -> isize {
+  syscall(
+    SYSCALL_GETDENTS64,
+    [fd, buf.as_mut_ptr() as usize, buf.len()],
+  )
+}
+
+pub fn sys_fstat(fd: usize, st: &mut Stat) -> isize {
+  syscall(SYSCALL_FSTAT, [fd, st as *mut Stat as usize, 0])
+}
+
 pub fn sys_dup(fd: usize) -> isize {
   syscall(SYSCALL_DUP, [fd, 0, 0])
 }
@@ -58,6 +204,108 @@ pub fn sys_pipe(pipe: &mut [usize]) -> isize {
   syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
 }
 
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+  syscall(
+    SYSCALL_GETCWD,
+    [buf.as_mut_ptr() as usize, buf.len(), 0],
+  )
+}
+
+pub fn sys_mkdir(path: &str) -> isize {
+  syscall(SYSCALL_MKDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_unlinkat(
+  dirfd: isize,
+  path: &str,
+  flags: u32,
+) -> isize {
+  syscall(
+    SYSCALL_UNLINKAT,
+    [
+      dirfd as usize,
+      path.as_ptr() as usize,
+      flags as usize,
+    ],
+  )
+}
+
+pub fn sys_symlinkat(
+  target: &str,
+  dirfd: isize,
+  path: &str,
+) -> isize {
+  syscall(
+    SYSCALL_SYMLINKAT,
+    [
+      target.as_ptr() as usize,
+      dirfd as usize,
+      path.as_ptr() as usize,
+    ],
+  )
+}
+
+pub fn sys_readlinkat(
+  dirfd: isize,
+  path: &str,
+  buf: &mut [u8],
+) -> isize {
+  syscall6(
+    SYSCALL_READLINKAT,
+    [
+      dirfd as usize,
+      path.as_ptr() as usize,
+      buf.as_mut_ptr() as usize,
+      buf.len(),
+      0,
+      0,
+    ],
+  )
+}
+
+pub fn sys_linkat(
+  old_dirfd: isize,
+  old_path: &str,
+  new_dirfd: isize,
+  new_path: &str,
+  flags: u32,
+) -> isize {
+  syscall6(
+    SYSCALL_LINKAT,
+    [
+      old_dirfd as usize,
+      old_path.as_ptr() as usize,
+      new_dirfd as usize,
+      new_path.as_ptr() as usize,
+      flags as usize,
+      0,
+    ],
+  )
+}
+
+pub fn sys_renameat(
+  old_dirfd: isize,
+  old_path: &str,
+  new_dirfd: isize,
+  new_path: &str,
+) -> isize {
+  syscall6(
+    SYSCALL_RENAMEAT,
+    [
+      old_dirfd as usize,
+      old_path.as_ptr() as usize,
+      new_dirfd as usize,
+      new_path.as_ptr() as usize,
+      0,
+      0,
+    ],
+  )
+}
+
+pub fn sys_chdir(path: &str) -> isize {
+  syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
 pub fn sys_open(path: &str, flags: u32) -> isize {
   syscall(
     SYSCALL_OPEN,
@@ -113,10 +361,11 @@ pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
 pub fn sys_waitpid(
   pid: isize,
   exit_code: *mut i32,
+  options: u32,
 ) -> isize {
   syscall(
     SYSCALL_WAITPID,
-    [pid as usize, exit_code as usize, 0],
+    [pid as usize, exit_code as usize, options as usize],
   )
 }
 
@@ -177,3 +426,37 @@ pub fn sys_condvar_wait(
 ) -> isize {
   syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
 }
+
+pub fn sys_brk(addr: usize) -> isize {
+  syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+  syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
+
+pub fn sys_mmap(
+  addr: usize,
+  len: usize,
+  prot: u32,
+  flags: u32,
+  fd: usize,
+  offset: usize,
+) -> isize {
+  syscall6(
+    SYSCALL_MMAP,
+    [addr, len, prot as usize, flags as usize, fd, offset],
+  )
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+  syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_mprotect(
+  addr: usize,
+  len: usize,
+  prot: u32,
+) -> isize {
+  syscall(SYSCALL_MPROTECT, [addr, len, prot as usize])
+}